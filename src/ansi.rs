@@ -0,0 +1,94 @@
+use std::borrow::Cow;
+
+/// The escape character starting ANSI sequences.
+const ESC: u8 = 0x1b;
+
+/// Remove the ANSI escape sequences from bytes, such as colors left in a file
+/// past the bytes read by text detection, or added by `--filter-cmd`.
+///
+/// Control sequences (`ESC [`) end at their final byte, operating system
+/// commands (`ESC ]`) at a bell or a string terminator, and other escapes
+/// after the byte following the escape character. A sequence cut short by the
+/// end of the bytes is removed too.
+pub fn strip(bytes: &[u8]) -> Cow<'_, [u8]> {
+    if !bytes.contains(&ESC) {
+        return Cow::Borrowed(bytes);
+    }
+
+    let mut stripped = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] != ESC {
+            stripped.push(bytes[index]);
+            index += 1;
+            continue;
+        }
+
+        index += 1;
+        match bytes.get(index) {
+            Some(b'[') => {
+                index += 1;
+                while index < bytes.len() && !(0x40..=0x7e).contains(&bytes[index]) {
+                    index += 1;
+                }
+                index += 1;
+            }
+            Some(b']') => {
+                index += 1;
+                while index < bytes.len() {
+                    if bytes[index] == 0x07 {
+                        index += 1;
+                        break;
+                    }
+                    if bytes[index] == ESC && bytes.get(index + 1) == Some(&b'\\') {
+                        index += 2;
+                        break;
+                    }
+                    index += 1;
+                }
+            }
+            Some(_) => index += 1,
+            None => {}
+        }
+    }
+    Cow::Owned(stripped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stripped(bytes: &[u8]) -> String {
+        String::from_utf8(strip(bytes).into_owned()).unwrap()
+    }
+
+    #[test]
+    fn colors_and_cursor_moves_are_removed() {
+        assert_eq!(
+            stripped(b"\x1b[1;31merror\x1b[0m: failed\n"),
+            "error: failed\n"
+        );
+        assert_eq!(stripped(b"a\x1b[2K\x1b[1Gb"), "ab");
+    }
+
+    #[test]
+    fn operating_system_commands_end_at_bell_or_terminator() {
+        let link = b"\x1b]8;;https://example.com\x07link\x1b]8;;\x1b\\ done";
+        assert_eq!(stripped(link), "link done");
+    }
+
+    #[test]
+    fn other_and_truncated_sequences_are_removed() {
+        // Such as the reset of the terminal, `ESC c`
+        assert_eq!(stripped(b"a\x1bcb"), "ab");
+        assert_eq!(stripped(b"text\x1b[31"), "text");
+        assert_eq!(stripped(b"text\x1b"), "text");
+    }
+
+    #[test]
+    fn text_without_escapes_is_borrowed() {
+        let text = "naïve 漢字\n".as_bytes();
+        assert!(matches!(strip(text), Cow::Borrowed(_)));
+        assert_eq!(stripped("\x1b[32m漢字\x1b[0m".as_bytes()), "漢字");
+    }
+}
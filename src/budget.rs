@@ -0,0 +1,442 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use tiktoken_rs::CoreBPE;
+
+use crate::filter::SkipReason;
+use crate::language::Languages;
+use crate::select::Priority;
+use crate::FileEntry;
+
+/// Number of lines kept at the start of a file replaced by a stub.
+const STUB_LINES: usize = 10;
+
+/// Replace the content of the files over a number of tokens by their first
+/// lines, followed by the number of tokens left out.
+///
+/// The files must have their tokens counted.
+pub fn stub(files: &mut [FileEntry], threshold: usize, bpe: &CoreBPE) {
+    for file in files {
+        let Some(tokens) = file.tokens.filter(|&tokens| tokens > threshold) else {
+            continue;
+        };
+
+        let head = file
+            .content
+            .lines()
+            .take(STUB_LINES)
+            .collect::<Vec<_>>()
+            .join("\n");
+        let kept = bpe.encode_ordinary(&head).len();
+        file.content = format!(
+            "{}\n[... {} tokens omitted]",
+            head,
+            tokens.saturating_sub(kept)
+        );
+        file.tokens = Some(bpe.encode_ordinary(&file.content).len());
+    }
+}
+
+/// Keep the first lines of every file, followed by a marker when some were
+/// left out. Returns the tokens saved compared to the whole files.
+///
+/// The files must have their tokens counted.
+pub fn preview(files: &mut [FileEntry], lines: usize, bpe: &CoreBPE) -> usize {
+    let mut saved = 0;
+    for file in files {
+        if file.content.lines().nth(lines).is_none() {
+            continue;
+        }
+
+        let head = file
+            .content
+            .lines()
+            .take(lines)
+            .collect::<Vec<_>>()
+            .join("\n");
+        file.content = format!("{}\n[... rest omitted]", head);
+        let tokens = bpe.encode_ordinary(&file.content).len();
+        saved += file.tokens.unwrap_or_default().saturating_sub(tokens);
+        file.tokens = Some(tokens);
+    }
+    saved
+}
+
+/// The token budgets of a run, applied in turn to the files read: those of
+/// directories, those of languages, then `--max-tokens`.
+pub struct Budgets {
+    pub dirs: Vec<DirBudget>,
+    pub languages: Vec<LangBudget>,
+    pub max_tokens: Option<usize>,
+}
+
+/// The files dropped by the budgets, each with its reason, and the warnings
+/// about them.
+pub struct Trimmed {
+    pub dropped: Vec<(FileEntry, SkipReason)>,
+    pub warnings: Vec<String>,
+}
+
+impl Budgets {
+    /// Drop the files that do not fit in the budgets, keeping the others in
+    /// order and counting the tokens used of each budget. `paths` gives the
+    /// path of each candidate relative to the current directory, by source.
+    ///
+    /// The files must have their tokens counted. Priority files are always
+    /// kept, even if they alone exceed a budget.
+    pub fn apply(
+        &mut self,
+        files: &mut Vec<FileEntry>,
+        paths: &[PathBuf],
+        languages: &Languages,
+        priority: &Priority,
+    ) -> Trimmed {
+        let mut trimmed = Trimmed {
+            dropped: Vec::new(),
+            warnings: Vec::new(),
+        };
+
+        if !self.dirs.is_empty() {
+            let dropped = trim_dirs(files, paths, &mut self.dirs, priority);
+            if !dropped.is_empty() {
+                trimmed.warnings.push(format!(
+                    "Dropped {} files to fit the directory budgets",
+                    dropped.len()
+                ));
+            }
+            for budget in self.dirs.iter().filter(|budget| budget.files == 0) {
+                trimmed.warnings.push(format!(
+                    "Directory budget of {} matches no file",
+                    budget.dir.display()
+                ));
+            }
+            trimmed
+                .dropped
+                .extend(dropped.into_iter().map(|(file, index)| {
+                    let reason = SkipReason::DirBudget {
+                        dir: self.dirs[index].dir.clone(),
+                        budget: self.dirs[index].budget,
+                    };
+                    (file, reason)
+                }));
+        }
+
+        if !self.languages.is_empty() {
+            let dropped = trim_languages(files, &mut self.languages, languages, priority);
+            if !dropped.is_empty() {
+                trimmed.warnings.push(format!(
+                    "Dropped {} files to fit the language budgets",
+                    dropped.len()
+                ));
+            }
+            trimmed
+                .dropped
+                .extend(dropped.into_iter().map(|(file, index)| {
+                    let reason = SkipReason::LangBudget {
+                        language: self.languages[index].language.clone(),
+                        budget: self.languages[index].budget,
+                    };
+                    (file, reason)
+                }));
+        }
+
+        // Drop files from the end until the rest fits in the token budget
+        if let Some(budget) = self.max_tokens {
+            let dropped = trim(files, budget, priority);
+            if !dropped.is_empty() {
+                trimmed.warnings.push(format!(
+                    "Dropped {} files to fit the token budget ({})",
+                    dropped.len(),
+                    budget
+                ));
+            }
+            trimmed.dropped.extend(
+                dropped
+                    .into_iter()
+                    .map(|file| (file, SkipReason::MaxTokens(budget))),
+            );
+        }
+
+        trimmed
+    }
+}
+
+/// Drop the files that match no priority glob, starting from the end, until the
+/// total number of tokens of their content fits in the budget, which leaves
+/// out the tree and headers written around them. Returns the dropped files.
+///
+/// Priority files are always kept, even if they alone exceed the budget.
+pub fn trim(files: &mut Vec<FileEntry>, budget: usize, priority: &Priority) -> Vec<FileEntry> {
+    let mut total: usize = files.iter().filter_map(|file| file.tokens).sum();
+    let mut drop = vec![false; files.len()];
+
+    for index in (0..files.len()).rev() {
+        if total <= budget {
+            break;
+        }
+        if priority.rank(&files[index].display).is_some() {
+            continue;
+        }
+        total -= files[index].tokens.unwrap_or(0);
+        drop[index] = true;
+    }
+
+    let mut dropped = Vec::new();
+    for (file, drop) in std::mem::take(files).into_iter().zip(drop) {
+        if drop {
+            dropped.push(file);
+        } else {
+            files.push(file);
+        }
+    }
+
+    dropped
+}
+
+/// A budget of tokens for the files below a directory, given with `--dir-budget`.
+#[derive(Debug)]
+pub struct DirBudget {
+    /// Directory, relative to the current directory.
+    pub dir: PathBuf,
+    pub budget: usize,
+    /// Tokens of the files kept below the directory.
+    pub used: usize,
+    /// Number of files below the directory, kept or dropped.
+    pub files: usize,
+}
+
+/// Parse budgets of the form `path=tokens`, rejecting a budget larger than
+/// the one of a directory containing it, which could never be reached.
+pub fn dir_budgets(budgets: &[String]) -> Result<Vec<DirBudget>> {
+    let mut parsed: Vec<DirBudget> = Vec::new();
+    for budget in budgets {
+        let (dir, tokens) = budget
+            .rsplit_once('=')
+            .with_context(|| format!("Invalid directory budget: {}", budget))?;
+        let tokens: usize = tokens
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid directory budget: {}", budget))?;
+        // `./src` and `src/` name the same directory as the displayed `src`
+        let dir: PathBuf = Path::new(dir.trim())
+            .components()
+            .filter(|component| *component != Component::CurDir)
+            .collect();
+        if parsed.iter().any(|parsed| parsed.dir == dir) {
+            bail!("Directory budget given twice: {}", dir.display());
+        }
+        parsed.push(DirBudget {
+            dir,
+            budget: tokens,
+            used: 0,
+            files: 0,
+        });
+    }
+
+    for child in &parsed {
+        if let Some(parent) = parsed
+            .iter()
+            .find(|parent| parent.dir != child.dir && child.dir.starts_with(&parent.dir))
+            .filter(|parent| child.budget > parent.budget)
+        {
+            bail!(
+                "Budget of {} ({}) is larger than the budget of {} ({}) containing it",
+                child.dir.display(),
+                child.budget,
+                parent.dir.display(),
+                parent.budget
+            );
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Keep the files in order while they fit in the budgets of the directories
+/// containing them, counting the tokens used by each. Returns the dropped
+/// files, each with the index of the budget it did not fit in.
+///
+/// The files must have their tokens counted. Priority files are always kept,
+/// even if they alone exceed the budget.
+pub fn trim_dirs(
+    files: &mut Vec<FileEntry>,
+    paths: &[PathBuf],
+    budgets: &mut [DirBudget],
+    priority: &Priority,
+) -> Vec<(FileEntry, usize)> {
+    let mut dropped = Vec::new();
+    for file in std::mem::take(files) {
+        let tokens = file.tokens.unwrap_or(0);
+        // Matched against the path on disk, not the one shown, which may
+        // have a prefix or be redacted
+        let path = &paths[file.source];
+        for budget in budgets
+            .iter_mut()
+            .filter(|budget| path.starts_with(&budget.dir))
+        {
+            budget.files += 1;
+        }
+        let exceeded = budgets.iter().position(|budget| {
+            path.starts_with(&budget.dir) && budget.used + tokens > budget.budget
+        });
+        match exceeded {
+            Some(index) if priority.rank(&file.display).is_none() => {
+                dropped.push((file, index));
+            }
+            _ => {
+                for budget in budgets
+                    .iter_mut()
+                    .filter(|budget| path.starts_with(&budget.dir))
+                {
+                    budget.used += tokens;
+                }
+                files.push(file);
+            }
+        }
+    }
+
+    dropped
+}
+
+/// A budget of tokens for the files of a language, given with `--lang-budget`.
+#[derive(Debug)]
+pub struct LangBudget {
+    pub language: String,
+    pub budget: usize,
+    /// Tokens of the files of the language kept.
+    pub used: usize,
+}
+
+/// Parse budgets of the form `language=tokens`, the language given by its name
+/// or by one of its extensions.
+pub fn lang_budgets(budgets: &[String], languages: &Languages) -> Result<Vec<LangBudget>> {
+    let mut parsed: Vec<LangBudget> = Vec::new();
+    for budget in budgets {
+        let (name, tokens) = budget
+            .split_once('=')
+            .with_context(|| format!("Invalid language budget: {}", budget))?;
+        let tokens: usize = tokens
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid language budget: {}", budget))?;
+        let language = languages
+            .resolve(name)
+            .with_context(|| format!("Unknown language in budget: {}", budget))?;
+        if parsed.iter().any(|parsed| parsed.language == language) {
+            bail!("Language budget given twice: {}", language);
+        }
+        parsed.push(LangBudget {
+            language,
+            budget: tokens,
+            used: 0,
+        });
+    }
+    Ok(parsed)
+}
+
+/// Keep the files in order while they fit in the budget of their language,
+/// counting the tokens used by each. Returns the dropped files, each with the
+/// index of the budget it did not fit in.
+///
+/// The files must have their tokens counted. Priority files are always kept,
+/// even if they alone exceed the budget.
+pub fn trim_languages(
+    files: &mut Vec<FileEntry>,
+    budgets: &mut [LangBudget],
+    languages: &Languages,
+    priority: &Priority,
+) -> Vec<(FileEntry, usize)> {
+    let mut dropped = Vec::new();
+    for file in std::mem::take(files) {
+        let tokens = file.tokens.unwrap_or(0);
+        let index = languages.detect(&file.display).and_then(|language| {
+            budgets
+                .iter()
+                .position(|budget| budget.language == language)
+        });
+        match index {
+            Some(index)
+                if budgets[index].used + tokens > budgets[index].budget
+                    && priority.rank(&file.display).is_none() =>
+            {
+                dropped.push((file, index));
+            }
+            Some(index) => {
+                budgets[index].used += tokens;
+                files.push(file);
+            }
+            None => files.push(file),
+        }
+    }
+
+    dropped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(source: usize, path: &str, tokens: usize) -> FileEntry {
+        FileEntry {
+            source,
+            display: PathBuf::from(path),
+            tag: None,
+            size: 0,
+            tokens: Some(tokens),
+            content: String::new(),
+            indent: String::new(),
+            license: String::new(),
+            streamed: false,
+        }
+    }
+
+    #[test]
+    fn dir_budgets_name_the_directories_as_shown() {
+        let budgets = dir_budgets(&["./src=1000".to_string(), "docs/=500".to_string()]).unwrap();
+        assert_eq!(budgets[0].dir, Path::new("src"));
+        assert_eq!(budgets[1].dir, Path::new("docs"));
+
+        let error = dir_budgets(&["./src=1000".to_string(), "src/=500".to_string()]).unwrap_err();
+        assert_eq!(error.to_string(), "Directory budget given twice: src");
+    }
+
+    #[test]
+    fn dir_budgets_count_the_files_they_match() {
+        let mut budgets = dir_budgets(&["./src=10".to_string(), "lib=10".to_string()]).unwrap();
+        let paths = ["src/a.rs", "src/b.rs", "c.rs"].map(PathBuf::from);
+        let mut files = vec![
+            file(0, "src/a.rs", 6),
+            file(1, "src/b.rs", 6),
+            file(2, "c.rs", 6),
+        ];
+
+        let dropped = trim_dirs(
+            &mut files,
+            &paths,
+            &mut budgets,
+            &Priority::new(&[]).unwrap(),
+        );
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].0.display, Path::new("src/b.rs"));
+        assert_eq!((budgets[0].used, budgets[0].files), (6, 2));
+        assert_eq!(budgets[1].files, 0);
+    }
+
+    #[test]
+    fn dir_budgets_match_the_paths_on_disk() {
+        // As shown with --path-prefix app
+        let mut budgets = dir_budgets(&["src=10".to_string()]).unwrap();
+        let paths = ["src/a.rs", "src/b.rs"].map(PathBuf::from);
+        let mut files = vec![file(0, "app/src/a.rs", 6), file(1, "app/src/b.rs", 6)];
+
+        let dropped = trim_dirs(
+            &mut files,
+            &paths,
+            &mut budgets,
+            &Priority::new(&[]).unwrap(),
+        );
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].0.display, Path::new("app/src/b.rs"));
+        assert_eq!(budgets[0].files, 2);
+    }
+}
@@ -0,0 +1,85 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context, Result};
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+
+use crate::source::Candidate;
+
+/// Name of the file holding the last output, kept in the git directory.
+const CACHE_FILE: &str = "llmr-output.json";
+
+/// Largest output kept by `--resume-from-cache`, all formats together, as the
+/// content of every file is in it.
+pub const MAX_CACHE_BYTES: usize = 64 * 1024 * 1024;
+
+/// What `--resume-from-cache` keeps of a run to write it again while no file
+/// changed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutputCache {
+    /// Hash of the options, the directory and the HEAD commit of the run.
+    pub key: String,
+    /// The files found by the walk, in its order.
+    pub files: Vec<Stamp>,
+    /// What was written to each output, stdout being `None`.
+    pub outputs: Vec<CachedOutput>,
+}
+
+/// The size and modification time of a file, telling whether it changed.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Stamp {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Nanoseconds since the epoch, zero when unknown.
+    pub modified: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CachedOutput {
+    pub path: Option<PathBuf>,
+    pub content: String,
+}
+
+/// Path of the cache, in the git directory of the repository containing a
+/// directory, if any.
+pub fn path(dir: &Path) -> Option<PathBuf> {
+    let repository = Repository::discover(dir).ok()?;
+    Some(repository.path().join(CACHE_FILE))
+}
+
+/// Stamp the files among the candidates, a file gone since the walk getting an
+/// empty stamp that tells it apart.
+pub fn stamps(candidates: &[Candidate]) -> Vec<Stamp> {
+    candidates
+        .iter()
+        .filter(|candidate| !candidate.is_dir)
+        .map(|candidate| {
+            let metadata = fs::metadata(&candidate.path).ok();
+            let modified = metadata
+                .as_ref()
+                .and_then(|metadata| metadata.modified().ok())
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|since| since.as_nanos() as u64)
+                .unwrap_or_default();
+            Stamp {
+                path: candidate.path.clone(),
+                size: metadata.map(|metadata| metadata.len()).unwrap_or_default(),
+                modified,
+            }
+        })
+        .collect()
+}
+
+/// Load the cache, if it exists and can be read.
+pub fn load(path: &Path) -> Option<OutputCache> {
+    let content = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+pub fn save(path: &Path, cache: &OutputCache) -> Result<()> {
+    let json = serde_json::to_string(cache).context("Failed to serialize output cache")?;
+    fs::write(path, json)
+        .with_context(|| format!("Failed to write output cache: {}", path.display()))
+}
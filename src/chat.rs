@@ -0,0 +1,87 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+/// Who a chat message comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize))]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+}
+
+/// A message of an OpenAI-style chat request.
+#[derive(Debug, Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize))]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+}
+
+/// Build the messages written by `--format chat`: the preamble as a system
+/// message, then the parts of the output and the instructions as user messages.
+pub fn messages(
+    preamble: Option<&str>,
+    parts: Vec<String>,
+    instructions: Option<&str>,
+) -> Vec<Message> {
+    let system = preamble.map(|preamble| Message {
+        role: Role::System,
+        content: preamble.to_string(),
+    });
+    let user = parts
+        .into_iter()
+        .chain(instructions.map(str::to_string))
+        .map(|content| Message {
+            role: Role::User,
+            content,
+        });
+
+    system.into_iter().chain(user).collect()
+}
+
+/// Serialize the messages as a JSON array, ready to be sent as the `messages`
+/// of a request.
+pub fn to_json(messages: &[Message]) -> Result<String> {
+    serde_json::to_string_pretty(messages).context("Failed to serialize chat messages")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip_through_json() {
+        let tree = "└── .\n    └── \"quoted\\path\".rs\n".to_string();
+        let file = "let s = \"a\\tb\\n\";\r\n// </script> \u{1b}[0m \u{0} é 漢字 🦀\n".to_string();
+        let messages = messages(
+            Some("You are a \"careful\" reviewer."),
+            vec![tree.clone(), file.clone()],
+            Some("Fix the bug.\n"),
+        );
+
+        let parsed: Vec<Message> = serde_json::from_str(&to_json(&messages).unwrap()).unwrap();
+        let roles: Vec<Role> = parsed.iter().map(|message| message.role).collect();
+        assert_eq!(roles, [Role::System, Role::User, Role::User, Role::User]);
+        let contents: Vec<&str> = parsed
+            .iter()
+            .map(|message| message.content.as_str())
+            .collect();
+        assert_eq!(
+            contents,
+            [
+                "You are a \"careful\" reviewer.",
+                tree.as_str(),
+                file.as_str(),
+                "Fix the bug.\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn preamble_and_instructions_are_optional() {
+        let messages = messages(None, vec!["files".to_string()], None);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].role, Role::User);
+    }
+}
@@ -0,0 +1,61 @@
+use anyhow::{bail, Result};
+use tiktoken_rs::{CoreBPE, Rank};
+
+/// Tokens a character may span, the most a chunk is widened by on each side
+/// to decode it.
+const MAX_CHARACTER_TOKENS: usize = 4;
+
+/// A part of a file of at most a number of tokens.
+pub struct Chunk {
+    /// Range of the tokens of the file held by the chunk.
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+/// Split a content into chunks of at most `size` tokens, each starting with the
+/// last `overlap` tokens of the previous one.
+///
+/// A chunk whose bounds would split a character across tokens is widened to
+/// hold the whole character, so it may be a few tokens larger.
+pub fn split(content: &str, size: usize, overlap: usize, bpe: &CoreBPE) -> Result<Vec<Chunk>> {
+    if size == 0 {
+        bail!("--chunk-tokens must be at least 1");
+    }
+    if overlap >= size {
+        bail!(
+            "--split-tokens-overlap ({}) must be smaller than --chunk-tokens ({})",
+            overlap,
+            size
+        );
+    }
+
+    let tokens = bpe.encode_ordinary(content);
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    loop {
+        let chunk = decode(bpe, &tokens, start, (start + size).min(tokens.len()))?;
+        let end = chunk.end;
+        chunks.push(chunk);
+        if end >= tokens.len() {
+            break;
+        }
+        start = end - overlap;
+    }
+    Ok(chunks)
+}
+
+/// Decode a range of tokens, widening it until its bounds fall between
+/// characters.
+fn decode(bpe: &CoreBPE, tokens: &[Rank], start: usize, end: usize) -> Result<Chunk> {
+    for before in 0..MAX_CHARACTER_TOKENS {
+        for after in 0..MAX_CHARACTER_TOKENS {
+            let start = start.saturating_sub(before);
+            let end = (end + after).min(tokens.len());
+            if let Ok(text) = bpe.decode(tokens[start..end].to_vec()) {
+                return Ok(Chunk { start, end, text });
+            }
+        }
+    }
+    bail!("Failed to decode the tokens {}..{} into text", start, end)
+}
@@ -0,0 +1,80 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use globset::{Glob, GlobMatcher};
+
+/// Globs of the paths of generated code, unless replaced with `--codegen-pattern`.
+pub const DEFAULT_PATTERNS: &[&str] = &[
+    "**/generated/**",
+    "**/gen/**",
+    "**/*.pb.go",
+    "**/*.pb.gw.go",
+    "**/*.pb.cc",
+    "**/*.pb.h",
+    "**/*.pb.swift",
+    "**/*_pb2.py",
+    "**/*_pb2.pyi",
+    "**/*_pb2_grpc.py",
+    "**/*_pb.js",
+    "**/*_pb.d.ts",
+    "**/*_grpc_pb.js",
+    "**/*.generated.*",
+    "**/*.g.dart",
+];
+
+/// Markers written near the top of generated files by code generators.
+const MARKERS: &[&str] = &[
+    "Code generated",
+    "DO NOT EDIT",
+    "@generated",
+    "Generated by the protocol buffer compiler",
+    "AUTO-GENERATED",
+    "autogenerated by",
+];
+
+/// Number of lines at the top of a file searched for markers.
+const HEADER_LINES: usize = 10;
+
+/// Generated client and server stubs, recognized by `--skip-codegen`.
+pub struct Codegen {
+    globs: Vec<(String, GlobMatcher)>,
+}
+
+impl Codegen {
+    /// Match the given globs, or the default ones if there are none.
+    pub fn new(patterns: &[String]) -> Result<Self> {
+        let patterns: Vec<String> = if patterns.is_empty() {
+            DEFAULT_PATTERNS
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect()
+        } else {
+            patterns.to_vec()
+        };
+        let globs = patterns
+            .into_iter()
+            .map(|pattern| {
+                let glob = Glob::new(&pattern)
+                    .with_context(|| format!("Invalid codegen pattern: {}", pattern))?
+                    .compile_matcher();
+                Ok((pattern, glob))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Codegen { globs })
+    }
+
+    /// Describe why a file looks generated, from its displayed path or the
+    /// first lines of its content, if it does.
+    pub fn detect(&self, path: &Path, content: &str) -> Option<String> {
+        if let Some((pattern, _)) = self.globs.iter().find(|(_, glob)| glob.is_match(path)) {
+            return Some(format!("path matches `{}`", pattern));
+        }
+
+        let marker = content
+            .lines()
+            .take(HEADER_LINES)
+            .find_map(|line| MARKERS.iter().find(|marker| line.contains(*marker)))?;
+        Some(format!("header contains `{}`", marker))
+    }
+}
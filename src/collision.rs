@@ -0,0 +1,168 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::FileEntry;
+
+/// Normalize a display path the way a case-insensitive filesystem sees it,
+/// with forward slashes as separators.
+fn normalize(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/").to_lowercase()
+}
+
+/// Short, stable hash of a path, used to tell colliding headers apart.
+pub fn short_hash(path: &Path) -> String {
+    let hash = fnv1a(path.to_string_lossy().as_bytes());
+    format!("{:08x}", hash >> 32)
+}
+
+/// FNV-1a hash, which unlike the standard hasher is stable across releases.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325u64, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Find files whose headers would be indistinguishable after normalization,
+/// tag them with a short hash of their path and return a warning per collision.
+pub fn disambiguate(files: &mut [FileEntry]) -> Vec<String> {
+    let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
+    for (index, file) in files.iter().enumerate() {
+        groups
+            .entry(normalize(&file.display))
+            .or_default()
+            .push(index);
+    }
+
+    let mut collisions: Vec<Vec<usize>> = groups
+        .into_values()
+        .filter(|indices| indices.len() > 1)
+        .collect();
+    collisions.sort();
+
+    let mut warnings = Vec::new();
+    for indices in collisions {
+        let paths: Vec<String> = indices
+            .iter()
+            .map(|&index| files[index].display.display().to_string())
+            .collect();
+        warnings.push(format!(
+            "Warning: {} produce the same header, disambiguating with a hash of their path",
+            paths.join(" and ")
+        ));
+
+        for index in indices {
+            files[index].tag = Some(short_hash(&files[index].display));
+        }
+    }
+
+    warnings
+}
+
+/// Replace the display paths by file names alone, suffixing names seen earlier
+/// in the output with a number (`mod.rs`, `mod.2.rs`, `mod.3.rs`, ...).
+pub fn flatten(files: &mut [FileEntry]) {
+    let mut used: HashSet<PathBuf> = HashSet::new();
+
+    for file in files.iter_mut() {
+        let name = PathBuf::from(file.display.file_name().unwrap_or(file.display.as_os_str()));
+
+        let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+
+        let mut flat = name.clone();
+        let mut suffix = 2;
+        while used.contains(&flat) {
+            flat = match name.extension() {
+                Some(extension) => PathBuf::from(format!(
+                    "{}.{}.{}",
+                    stem,
+                    suffix,
+                    extension.to_string_lossy()
+                )),
+                None => PathBuf::from(format!("{}.{}", stem, suffix)),
+            };
+            suffix += 1;
+        }
+
+        used.insert(flat.clone());
+        file.display = flat;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(paths: &[&str]) -> Vec<FileEntry> {
+        paths
+            .iter()
+            .enumerate()
+            .map(|(source, path)| FileEntry {
+                source,
+                display: PathBuf::from(path),
+                tag: None,
+                size: 0,
+                tokens: None,
+                content: String::new(),
+                indent: String::new(),
+                license: String::new(),
+                streamed: false,
+            })
+            .collect()
+    }
+
+    fn tags(files: &[FileEntry]) -> Vec<Option<&str>> {
+        files.iter().map(|file| file.tag.as_deref()).collect()
+    }
+
+    #[test]
+    fn paths_differing_in_case_are_told_apart() {
+        let mut files = files(&["README.md", "src/lib.rs", "Readme.md"]);
+        let warnings = disambiguate(&mut files);
+
+        assert_eq!(
+            warnings,
+            ["Warning: README.md and Readme.md produce the same header, disambiguating with a hash of their path"]
+        );
+        assert_eq!(files[1].tag, None);
+        let (readme, other) = (files[0].tag.clone().unwrap(), files[2].tag.clone().unwrap());
+        assert_eq!(readme, short_hash(Path::new("README.md")));
+        assert_eq!(other, short_hash(Path::new("Readme.md")));
+        assert_ne!(readme, other);
+    }
+
+    #[test]
+    fn paths_differing_in_separators_are_told_apart() {
+        // As built from the normalized paths directly, whatever the platform
+        let mut files = files(&["src/a.rs", "src\\a.rs", "src/b.rs"]);
+        assert_eq!(disambiguate(&mut files).len(), 1);
+        assert!(files[0].tag.is_some() && files[1].tag.is_some());
+        assert_eq!(files[2].tag, None);
+    }
+
+    #[test]
+    fn distinct_paths_are_left_alone() {
+        let mut files = files(&["a.rs", "b.rs", "a/b.rs"]);
+        assert!(disambiguate(&mut files).is_empty());
+        assert_eq!(tags(&files), [None, None, None]);
+    }
+
+    #[test]
+    fn flattened_names_are_numbered() {
+        let mut files = files(&[
+            "a/mod.rs",
+            "b/mod.rs",
+            "c/Makefile",
+            "d/Makefile",
+            "e/mod.rs",
+        ]);
+        flatten(&mut files);
+        let names: Vec<String> = files
+            .iter()
+            .map(|file| file.display.display().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            ["mod.rs", "mod.2.rs", "Makefile", "Makefile.2", "mod.3.rs"]
+        );
+    }
+}
@@ -0,0 +1,52 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use anyhow::{bail, Context, Result};
+
+/// Environment variable holding the path of the file given to `--filter-cmd`.
+pub const FILE_VAR: &str = "LLMR_FILE";
+
+/// Run a shell command with the content of a file on its stdin, returning
+/// what it prints on stdout.
+pub fn pipe(command: &str, path: &Path, content: &str) -> Result<String> {
+    let mut child = shell(command)
+        .env(FILE_VAR, path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .with_context(|| format!("Failed to run command: {}", command))?;
+
+    // Feed stdin from another thread, so a command writing before it has read
+    // everything does not block on a full pipe
+    let mut stdin = child.stdin.take().context("Failed to open command stdin")?;
+    let input = content.to_string();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let output = child
+        .wait_with_output()
+        .with_context(|| format!("Failed to run command: {}", command))?;
+    // A command may exit without reading its input, which is not an error
+    let _ = writer.join();
+
+    if !output.status.success() {
+        bail!("{}", output.status);
+    }
+    String::from_utf8(output.stdout).context("Command output is not valid UTF-8")
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
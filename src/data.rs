@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Number of keys below which the repetition of keys is not considered.
+const MIN_KEYS: usize = 100;
+
+/// Thresholds above which a JSON or YAML file is considered generated data.
+#[derive(Debug, Clone, Copy)]
+pub struct DataThresholds {
+    /// Number of occurrences of each distinct key, on average.
+    pub key_repetition: f64,
+    /// Number of elements of a top-level array.
+    pub array_length: usize,
+}
+
+/// Keys and top-level array found in a JSON or YAML document.
+#[derive(Debug, Default)]
+struct Shape {
+    keys: HashMap<String, usize>,
+    /// Number of elements of the document, if it is an array.
+    array_length: Option<usize>,
+}
+
+/// Describe why a JSON or YAML file looks like machine-written data rather than
+/// hand-written configuration, if it does.
+pub fn data_like(path: &Path, content: &str, thresholds: &DataThresholds) -> Option<String> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    let shape = match extension.as_str() {
+        "json" => json_shape(content),
+        "yaml" | "yml" => yaml_shape(content),
+        _ => return None,
+    };
+
+    if let Some(length) = shape
+        .array_length
+        .filter(|&length| length >= thresholds.array_length)
+    {
+        return Some(format!("top-level array of {} elements", length));
+    }
+
+    let total: usize = shape.keys.values().sum();
+    let repetition = total as f64 / shape.keys.len().max(1) as f64;
+    if total >= MIN_KEYS && repetition >= thresholds.key_repetition {
+        return Some(format!(
+            "{} keys, {} distinct (repeated {:.0} times on average)",
+            total,
+            shape.keys.len(),
+            repetition
+        ));
+    }
+
+    None
+}
+
+/// Count the object keys of a JSON document, and its elements if it is an array.
+fn json_shape(content: &str) -> Shape {
+    let mut shape = Shape::default();
+    let mut depth = 0usize;
+    let mut elements = 0;
+    let mut empty = true;
+    let mut chars = content.char_indices().peekable();
+
+    let is_array = content.trim_start().starts_with('[');
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => {
+                // Read the string, then check whether a colon makes it a key
+                let start = index + 1;
+                let mut end = start;
+                while let Some((index, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => {
+                            end = index;
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                let rest = content[end + 1..].trim_start();
+                if rest.starts_with(':') {
+                    *shape
+                        .keys
+                        .entry(content[start..end].to_string())
+                        .or_default() += 1;
+                }
+                if depth == 1 {
+                    empty = false;
+                }
+            }
+            '[' | '{' => {
+                if depth == 1 {
+                    empty = false;
+                }
+                depth += 1;
+            }
+            ']' | '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 1 => elements += 1,
+            c if depth == 1 && !c.is_whitespace() => empty = false,
+            _ => {}
+        }
+    }
+
+    if is_array {
+        shape.array_length = Some(if empty { 0 } else { elements + 1 });
+    }
+    shape
+}
+
+/// Count the mapping keys of a YAML document, and its elements if it is a
+/// sequence at the top level.
+fn yaml_shape(content: &str) -> Shape {
+    let mut shape = Shape::default();
+    let mut elements = 0;
+    let mut first = None;
+
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed == "---" {
+            continue;
+        }
+        first.get_or_insert(line);
+        if line.starts_with("- ") || line == "-" {
+            elements += 1;
+        }
+
+        let item = trimmed.trim_start_matches("- ");
+        if let Some((key, _)) = item.split_once(':') {
+            let key = key.trim_matches(|c| c == '"' || c == '\'');
+            if !key.is_empty() && !key.contains(char::is_whitespace) {
+                *shape.keys.entry(key.to_string()).or_default() += 1;
+            }
+        }
+    }
+
+    if first.is_some_and(|line| line.starts_with('-')) {
+        shape.array_length = Some(elements);
+    }
+    shape
+}
@@ -0,0 +1,240 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use ignore::WalkBuilder;
+use toml::{Table, Value};
+
+use crate::workspace;
+
+/// Names of the manifests whose dependencies are summed up by `--include-deps`.
+const MANIFESTS: &[&str] = &["Cargo.toml", "package.json", "pyproject.toml", "go.mod"];
+
+/// The dependencies declared by a manifest.
+#[derive(Debug)]
+pub struct Manifest {
+    /// Path of the manifest, relative to the walk root.
+    pub path: PathBuf,
+    /// Dependencies by kind, such as `dependencies` or `dev-dependencies`, each
+    /// given as a name followed by its version requirement, if any.
+    pub groups: Vec<(String, Vec<String>)>,
+}
+
+/// Find the manifests below a directory, skipping ignored directories such as
+/// `node_modules` or `target`, and read their dependencies. Returns the
+/// manifests declaring some and a warning for each that could not be read.
+pub fn find(root: &Path) -> Result<(Vec<Manifest>, Vec<String>)> {
+    let mut manifests = Vec::new();
+    let mut warnings = Vec::new();
+    for entry in WalkBuilder::new(root).sort_by_file_name(Ord::cmp).build() {
+        let entry = entry.with_context(|| "Error during directory traversal")?;
+        let Some(name) = entry.file_name().to_str() else {
+            continue;
+        };
+        if !MANIFESTS.contains(&name) || !entry.path().is_file() {
+            continue;
+        }
+
+        let path = entry.path();
+        let groups = match name {
+            "Cargo.toml" => workspace::read_toml(path).map(|manifest| cargo(&manifest)),
+            "package.json" => workspace::read_json(path).map(|manifest| npm(&manifest)),
+            "pyproject.toml" => workspace::read_toml(path).map(|manifest| python(&manifest)),
+            _ => fs::read_to_string(path)
+                .with_context(|| format!("Failed to read manifest: {}", path.display()))
+                .map(|content| go(&content)),
+        };
+        match groups {
+            Ok(groups) if groups.is_empty() => {}
+            Ok(groups) => manifests.push(Manifest {
+                path: path.strip_prefix(root).unwrap_or(path).to_path_buf(),
+                groups,
+            }),
+            Err(e) => warnings.push(format!("Warning: {:#}, leaving out its dependencies", e)),
+        }
+    }
+    Ok((manifests, warnings))
+}
+
+/// Render the dependencies of the manifests, one line per kind of dependency.
+pub fn render(manifests: &[Manifest]) -> String {
+    let mut output = String::new();
+    for manifest in manifests {
+        output.push_str(&format!("{}\n", manifest.path.display()));
+        for (kind, dependencies) in &manifest.groups {
+            output.push_str(&format!("    {}: {}\n", kind, dependencies.join(", ")));
+        }
+    }
+    output
+}
+
+/// Keep the kinds that declare at least one dependency.
+fn groups(kinds: impl IntoIterator<Item = (String, Vec<String>)>) -> Vec<(String, Vec<String>)> {
+    kinds
+        .into_iter()
+        .filter(|(_, dependencies)| !dependencies.is_empty())
+        .collect()
+}
+
+/// Dependencies of a `Cargo.toml`, including those shared by a workspace.
+fn cargo(manifest: &Table) -> Vec<(String, Vec<String>)> {
+    let table = |table: Option<&Value>| -> Vec<String> {
+        let Some(table) = table.and_then(Value::as_table) else {
+            return Vec::new();
+        };
+        table
+            .iter()
+            .map(|(name, dependency)| {
+                let version = match dependency {
+                    Value::String(version) => Some(version.as_str()),
+                    _ => dependency.get("version").and_then(Value::as_str),
+                };
+                match version {
+                    Some(version) => format!("{} {}", name, version),
+                    None if dependency.get("path").is_some() => format!("{} (path)", name),
+                    None if dependency.get("workspace").is_some() => {
+                        format!("{} (workspace)", name)
+                    }
+                    None => name.clone(),
+                }
+            })
+            .collect()
+    };
+
+    let workspace = manifest.get("workspace");
+    groups(
+        ["dependencies", "dev-dependencies", "build-dependencies"]
+            .into_iter()
+            .map(|kind| (kind.to_string(), table(manifest.get(kind))))
+            .chain(std::iter::once((
+                "workspace dependencies".to_string(),
+                table(workspace.and_then(|workspace| workspace.get("dependencies"))),
+            ))),
+    )
+}
+
+/// Dependencies of a `package.json`.
+fn npm(manifest: &serde_json::Value) -> Vec<(String, Vec<String>)> {
+    groups(
+        ["dependencies", "devDependencies", "peerDependencies"]
+            .into_iter()
+            .map(|kind| {
+                let dependencies = manifest
+                    .get(kind)
+                    .and_then(|dependencies| dependencies.as_object())
+                    .map(|dependencies| {
+                        dependencies
+                            .iter()
+                            .map(|(name, version)| match version.as_str() {
+                                Some(version) => format!("{} {}", name, version),
+                                None => name.clone(),
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                (kind.to_string(), dependencies)
+            }),
+    )
+}
+
+/// Dependencies of a `pyproject.toml`, as PEP 621 requirements or in the
+/// tables of Poetry.
+fn python(manifest: &Table) -> Vec<(String, Vec<String>)> {
+    let requirements = |value: Option<&Value>| -> Vec<String> {
+        value
+            .and_then(Value::as_array)
+            .map(|requirements| {
+                requirements
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let poetry = |value: Option<&Value>| -> Vec<String> {
+        let Some(table) = value.and_then(Value::as_table) else {
+            return Vec::new();
+        };
+        table
+            .iter()
+            .filter(|(name, _)| *name != "python")
+            .map(|(name, dependency)| {
+                let version = match dependency {
+                    Value::String(version) => Some(version.as_str()),
+                    _ => dependency.get("version").and_then(Value::as_str),
+                };
+                match version {
+                    Some(version) => format!("{} {}", name, version),
+                    None => name.clone(),
+                }
+            })
+            .collect()
+    };
+
+    let project = manifest.get("project");
+    let mut kinds = vec![(
+        "dependencies".to_string(),
+        requirements(project.and_then(|project| project.get("dependencies"))),
+    )];
+    if let Some(extras) = project
+        .and_then(|project| project.get("optional-dependencies"))
+        .and_then(Value::as_table)
+    {
+        for (extra, value) in extras.iter() {
+            kinds.push((format!("optional ({})", extra), requirements(Some(value))));
+        }
+    }
+    let tool = manifest.get("tool").and_then(|tool| tool.get("poetry"));
+    kinds.push((
+        "poetry dependencies".to_string(),
+        poetry(tool.and_then(|poetry| poetry.get("dependencies"))),
+    ));
+    kinds.push((
+        "poetry dev-dependencies".to_string(),
+        poetry(tool.and_then(|poetry| poetry.get("dev-dependencies"))),
+    ));
+    groups(kinds)
+}
+
+/// Modules required by a `go.mod`, on `require` lines or in `require` blocks,
+/// those only needed by dependencies apart.
+fn go(content: &str) -> Vec<(String, Vec<String>)> {
+    let mut direct = Vec::new();
+    let mut indirect = Vec::new();
+    let mut in_block = false;
+    for line in content.lines() {
+        let line = line.trim();
+        let requirement = if in_block {
+            if line == ")" {
+                in_block = false;
+                continue;
+            }
+            line
+        } else if line == "require (" {
+            in_block = true;
+            continue;
+        } else if let Some(requirement) = line.strip_prefix("require ") {
+            requirement.trim()
+        } else {
+            continue;
+        };
+
+        let (requirement, comment) = match requirement.split_once("//") {
+            Some((requirement, comment)) => (requirement.trim(), comment.trim()),
+            None => (requirement, ""),
+        };
+        if requirement.is_empty() {
+            continue;
+        }
+        match comment == "indirect" {
+            true => indirect.push(requirement.to_string()),
+            false => direct.push(requirement.to_string()),
+        }
+    }
+
+    groups([
+        ("require".to_string(), direct),
+        ("indirect".to_string(), indirect),
+    ])
+}
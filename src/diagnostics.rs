@@ -0,0 +1,205 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+
+use crate::filter::{Rule, SkipReason};
+
+/// Number of skipped files listed for each rule, unless verbose.
+const SAMPLE_SIZE: usize = 5;
+
+/// Files skipped by the same rule.
+struct SkipGroup {
+    rule: Rule,
+    count: usize,
+    /// The first files skipped, up to the sample size unless verbose.
+    samples: Vec<(PathBuf, SkipReason)>,
+}
+
+/// Where the skipped files and warnings are printed, given with `--errors`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    Stderr,
+    Stdout,
+    /// A file, written anew by each run.
+    File(PathBuf),
+}
+
+impl Destination {
+    /// The file the messages are written to, if any.
+    pub fn file(&self) -> Option<&PathBuf> {
+        match self {
+            Destination::File(path) => Some(path),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Destination {
+    type Err = String;
+
+    /// Parse `stderr`, `stdout` or `file:PATH`.
+    fn from_str(destination: &str) -> Result<Self, Self::Err> {
+        match destination {
+            "stderr" => Ok(Destination::Stderr),
+            "stdout" => Ok(Destination::Stdout),
+            _ => match destination.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(Destination::File(PathBuf::from(path))),
+                _ => Err(format!(
+                    "invalid destination `{}` (expected stderr, stdout or file:PATH)",
+                    destination
+                )),
+            },
+        }
+    }
+}
+
+/// A message printed at the end of the run.
+enum Entry {
+    Skips(SkipGroup),
+    Warning(String),
+}
+
+/// Skipped files and warnings gathered during a run, printed at the end in the
+/// order they first occurred, to stderr unless routed elsewhere.
+///
+/// Skipped files are grouped by the rule that excluded them, keeping their
+/// count and a bounded sample, so a limit hit on a large repository does not
+/// list every file.
+pub struct Diagnostics {
+    verbose: bool,
+    destination: Destination,
+    entries: Vec<Entry>,
+}
+
+impl Diagnostics {
+    /// Start gathering messages, keeping every skipped file if verbose.
+    pub fn new(verbose: bool, destination: Destination) -> Self {
+        Diagnostics {
+            verbose,
+            destination,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Record a file skipped for a reason.
+    pub fn skip(&mut self, path: &Path, reason: SkipReason) {
+        let rule = reason.rule();
+        let position = self
+            .entries
+            .iter()
+            .position(|entry| matches!(entry, Entry::Skips(group) if group.rule == rule));
+        let index = match position {
+            Some(index) => index,
+            None => {
+                self.entries.push(Entry::Skips(SkipGroup {
+                    rule,
+                    count: 0,
+                    samples: Vec::new(),
+                }));
+                self.entries.len() - 1
+            }
+        };
+
+        if let Entry::Skips(group) = &mut self.entries[index] {
+            group.count += 1;
+            if self.verbose || group.samples.len() < SAMPLE_SIZE {
+                group.samples.push((path.to_path_buf(), reason));
+            }
+        }
+    }
+
+    pub fn warn(&mut self, message: String) {
+        self.entries.push(Entry::Warning(message));
+    }
+
+    /// Keep a message telling what the run did, only shown if verbose.
+    pub fn info(&mut self, message: String) {
+        if self.verbose {
+            self.entries.push(Entry::Warning(message));
+        }
+    }
+
+    /// Print the messages to their destination, a file being left empty when
+    /// there are none.
+    pub fn print(&self) -> Result<()> {
+        let mut out: Box<dyn Write> = match &self.destination {
+            Destination::Stderr => Box::new(io::stderr().lock()),
+            Destination::Stdout => Box::new(io::stdout().lock()),
+            Destination::File(path) => Box::new(
+                File::create(path)
+                    .with_context(|| format!("Failed to create errors file: {}", path.display()))?,
+            ),
+        };
+        self.write(&mut out)
+            .with_context(|| match &self.destination {
+                Destination::File(path) => {
+                    format!("Failed to write errors file: {}", path.display())
+                }
+                _ => "Failed to print the skipped files and warnings".to_string(),
+            })
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        for entry in &self.entries {
+            match entry {
+                Entry::Skips(group) => {
+                    for (path, reason) in &group.samples {
+                        writeln!(out, "Skipping file {}: {}", path.display(), reason)?;
+                    }
+                    let more = group.count - group.samples.len();
+                    if more > 0 {
+                        writeln!(
+                            out,
+                            "... and {} more files skipped by {}",
+                            format_count(more),
+                            group.rule.name()
+                        )?;
+                    }
+                }
+                Entry::Warning(message) => writeln!(out, "{}", message)?,
+            }
+        }
+        out.flush()
+    }
+}
+
+/// Format a count with thousands separators, such as "4,312".
+fn format_count(count: usize) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::new();
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(diagnostics: &Diagnostics) -> String {
+        let mut out = Vec::new();
+        diagnostics.write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn info_is_only_kept_when_verbose() {
+        for verbose in [false, true] {
+            let mut diagnostics = Diagnostics::new(verbose, Destination::Stderr);
+            diagnostics.info("--recent 5 resolved to --sort mtime --tail-files 5".to_string());
+            diagnostics.warn("Dropped 2 files".to_string());
+            let expected = match verbose {
+                true => "--recent 5 resolved to --sort mtime --tail-files 5\nDropped 2 files\n",
+                false => "Dropped 2 files\n",
+            };
+            assert_eq!(written(&diagnostics), expected);
+        }
+    }
+}
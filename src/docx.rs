@@ -0,0 +1,176 @@
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use zip::ZipArchive;
+
+use crate::longpath;
+
+/// Whether a file is a Word document handled by `--extract-docx`.
+pub fn is_docx(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("docx"))
+}
+
+/// Extract the text of a Word document as Markdown, preceded by a note that it
+/// was extracted.
+pub fn extract(path: &Path) -> Result<String> {
+    let file = longpath::open(path)?;
+    let mut archive = ZipArchive::new(file)
+        .with_context(|| format!("Failed to open document: {}", path.display()))?;
+    let mut xml = String::new();
+    archive
+        .by_name("word/document.xml")
+        .with_context(|| format!("Failed to find the document body: {}", path.display()))?
+        .read_to_string(&mut xml)
+        .with_context(|| format!("Failed to read the document body: {}", path.display()))?;
+
+    Ok(format!(
+        "[Text extracted from a Word document]\n\n{}",
+        to_markdown(&xml)
+    ))
+}
+
+/// A block of the document: a paragraph, possibly a heading or a list item, or
+/// a table flattened to pipe-separated rows.
+enum Block {
+    Paragraph(String),
+    ListItem(String),
+    Table(Vec<String>),
+}
+
+/// Convert the XML body of a Word document to Markdown, keeping headings, list
+/// items and tables.
+fn to_markdown(xml: &str) -> String {
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut text = String::new();
+    let mut heading: Option<usize> = None;
+    let mut list = false;
+    let mut in_text = false;
+    let mut rows: Vec<String> = Vec::new();
+    let mut cells: Vec<String> = Vec::new();
+    let mut cell = String::new();
+    let mut tables = 0;
+
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        if in_text {
+            text.push_str(&unescape(&rest[..start]));
+        }
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+
+        let closing = tag.starts_with('/');
+        let self_closing = tag.ends_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+
+        match (name, closing) {
+            ("w:t", false) => in_text = !self_closing,
+            ("w:t", true) => in_text = false,
+            ("w:tab", false) => text.push('\t'),
+            ("w:br", false) => text.push('\n'),
+            ("w:pStyle", false) => heading = heading_level(attribute(tag, "w:val")),
+            ("w:numPr", false) => list = true,
+            ("w:p", false) if !self_closing => {
+                text.clear();
+                heading = None;
+                list = false;
+            }
+            ("w:p", true) => {
+                let paragraph = text.trim().to_string();
+                if tables > 0 {
+                    if !paragraph.is_empty() {
+                        if !cell.is_empty() {
+                            cell.push(' ');
+                        }
+                        cell.push_str(&paragraph);
+                    }
+                } else if paragraph.is_empty() {
+                    // Empty paragraphs only space out the document
+                } else if let Some(level) = heading {
+                    blocks.push(Block::Paragraph(format!(
+                        "{} {}",
+                        "#".repeat(level),
+                        paragraph
+                    )));
+                } else if list {
+                    blocks.push(Block::ListItem(format!("- {}", paragraph)));
+                } else {
+                    blocks.push(Block::Paragraph(paragraph));
+                }
+                text.clear();
+            }
+            ("w:tbl", false) => tables += 1,
+            ("w:tbl", true) => {
+                tables -= 1;
+                if tables == 0 && !rows.is_empty() {
+                    blocks.push(Block::Table(std::mem::take(&mut rows)));
+                }
+            }
+            ("w:tc", true) => cells.push(std::mem::take(&mut cell)),
+            ("w:tr", true) => {
+                rows.push(format!("| {} |", cells.join(" | ")));
+                cells.clear();
+            }
+            _ => {}
+        }
+    }
+
+    // Consecutive list items stay together, other blocks are separated by a
+    // blank line
+    let mut markdown = String::new();
+    let mut previous_list = false;
+    for block in &blocks {
+        let (content, is_list) = match block {
+            Block::Paragraph(paragraph) => (paragraph.clone(), false),
+            Block::ListItem(item) => (item.clone(), true),
+            Block::Table(rows) => (rows.join("\n"), false),
+        };
+        if !markdown.is_empty() {
+            markdown.push_str(if previous_list && is_list {
+                "\n"
+            } else {
+                "\n\n"
+            });
+        }
+        markdown.push_str(&content);
+        previous_list = is_list;
+    }
+    markdown
+}
+
+/// Level of the heading for a paragraph style such as `Heading2` or `Title`.
+fn heading_level(style: Option<&str>) -> Option<usize> {
+    let style = style?;
+    if style == "Title" {
+        return Some(1);
+    }
+    style
+        .strip_prefix("Heading")?
+        .parse()
+        .ok()
+        .filter(|level| (1..=6).contains(level))
+}
+
+/// Value of an attribute of a tag.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!("{}=\"", name))? + name.len() + 2;
+    let end = tag[start..].find('"')?;
+    Some(&tag[start..start + end])
+}
+
+/// Decode the XML entities of a text.
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
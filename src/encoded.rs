@@ -0,0 +1,45 @@
+/// Length of an unbroken run of base64 or hex characters from which it is
+/// counted as encoded data, shorter than the wrapped lines of PEM, MIME or
+/// `xxd -p`.
+const MIN_RUN: usize = 48;
+
+/// Bytes of encoded runs from which a file may be skipped, leaving alone the
+/// keys and hashes found in small files.
+const MIN_ENCODED_BYTES: usize = 4096;
+
+/// Share of the content in encoded runs from which a file is skipped, so that
+/// source files with a small embedded blob are kept.
+const MIN_ENCODED_SHARE: f64 = 0.5;
+
+/// Describe why a text file looks like binary data encoded as text, such as a
+/// base64 blob or a hex dump, if it does.
+///
+/// Such files pass the text detection and may be under the size limit, but
+/// take far more tokens than their size suggests.
+pub fn encoded_like(content: &str) -> Option<String> {
+    let mut encoded = 0;
+    let mut hex = true;
+    for run in content
+        .split(|c: char| !is_base64(c))
+        .filter(|run| run.len() >= MIN_RUN && run.bytes().any(|byte| byte.is_ascii_digit()))
+    {
+        encoded += run.len();
+        hex &= run.bytes().all(|byte| byte.is_ascii_hexdigit());
+    }
+
+    let share = encoded as f64 / content.len().max(1) as f64;
+    if encoded < MIN_ENCODED_BYTES || share < MIN_ENCODED_SHARE {
+        return None;
+    }
+    Some(format!(
+        "{} runs of {} bytes, {:.0}% of the file",
+        if hex { "hex" } else { "base64" },
+        encoded,
+        share * 100.0
+    ))
+}
+
+/// Whether a character can be part of base64 or hex data.
+fn is_base64(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '=' | '-' | '_')
+}
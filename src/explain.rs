@@ -0,0 +1,256 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+
+use crate::filter::{Decision, Rule, SkipReason};
+use crate::source::{in_git_repo, WalkOptions, LLMRIGNORE};
+
+/// Find the ignore rule that keeps the walker from visiting a path below `root`.
+///
+/// This mirrors the walker's filters in their order: `.llmrignore`, `.ignore`
+/// and `.gitignore` files from the deepest directory upwards, then the
+/// repository's `.git/info/exclude` and the global gitignore, then hidden paths
+/// if none of them matched, a whitelist un-hiding a path. A negated
+/// `--ignore-pattern` then re-includes what they exclude, before the other
+/// `--ignore-pattern` and `--exclude-dir` apply.
+pub fn ignore_reason(root: &Path, path: &Path, options: &WalkOptions) -> Option<SkipReason> {
+    let relative = path.strip_prefix(root).ok()?;
+    let mut current = root.to_path_buf();
+
+    // A path is excluded as soon as one of its ancestors is
+    for component in relative.components() {
+        current.push(component);
+
+        let is_dir = current.is_dir();
+        let reason = match matched(root, &current, is_dir, options) {
+            Match::Ignore((source, pattern)) => Some(SkipReason::Ignored {
+                line: find_line(&source, &pattern),
+                source,
+                pattern,
+            }),
+            Match::Whitelist(_) => None,
+            Match::None => component
+                .as_os_str()
+                .to_str()
+                .filter(|name| name.starts_with('.') && *name != "." && *name != "..")
+                .map(|_| SkipReason::Hidden),
+        };
+
+        let pattern = options.ignore_patterns.matched(&current, is_dir);
+        if let Some(reason) = reason.filter(|_| !pattern.is_whitelist()) {
+            return Some(reason);
+        }
+        if let Match::Ignore(glob) = pattern {
+            return Some(SkipReason::IgnorePattern(glob.original().to_string()));
+        }
+
+        if let Some(name) = component
+            .as_os_str()
+            .to_str()
+            .filter(|name| is_dir && options.exclude_dirs.iter().any(|excluded| excluded == name))
+        {
+            return Some(SkipReason::ExcludeDir(name.to_string()));
+        }
+    }
+
+    None
+}
+
+/// Match a single path against every ignore file that applies to it, giving
+/// the file and the pattern that decided.
+fn matched(
+    root: &Path,
+    path: &Path,
+    is_dir: bool,
+    options: &WalkOptions,
+) -> Match<(PathBuf, String)> {
+    let mut matchers: Vec<Gitignore> = Vec::new();
+
+    // Directories whose ignore files apply to the path
+    let dirs: Vec<&Path> = path
+        .ancestors()
+        .skip(1)
+        .take_while(|dir| options.parents || dir.starts_with(root))
+        .collect();
+
+    // Git's own ignore rules only apply inside a repository with `--require-git`,
+    // and their files only up to the root of the repository
+    let git_rules = !options.require_git || in_git_repo(path);
+    let git_dirs = dirs
+        .iter()
+        .position(|dir| dir.join(".git").exists())
+        .map_or(dirs.len(), |position| position + 1);
+
+    // Ignore files consulted in each directory, in order of precedence
+    let names = [
+        (LLMRIGNORE, options.llmrignore),
+        (".ignore", options.dot_ignore),
+        (".gitignore", options.gitignore && git_rules),
+    ];
+
+    for (name, enabled) in names {
+        if !enabled {
+            continue;
+        }
+        let dirs = match name {
+            ".gitignore" => &dirs[..git_dirs],
+            _ => &dirs[..],
+        };
+        for dir in dirs {
+            let file = dir.join(name);
+            if file.is_file() {
+                matchers.push(Gitignore::new(&file).0);
+            }
+        }
+    }
+
+    let repo_root = dirs.iter().find(|dir| dir.join(".git").exists());
+    if let Some(repo_root) = repo_root.filter(|_| options.gitignore) {
+        let exclude = repo_root.join(".git").join("info").join("exclude");
+        if exclude.is_file() {
+            let mut builder = GitignoreBuilder::new(repo_root);
+            builder.add(&exclude);
+            if let Ok(gitignore) = builder.build() {
+                matchers.push(gitignore);
+            }
+        }
+    }
+    if let Some(global_excludes) = options.global_excludes.as_ref().filter(|_| git_rules) {
+        let mut builder = GitignoreBuilder::new(Path::new(""));
+        builder.add(global_excludes);
+        if let Ok(gitignore) = builder.build() {
+            matchers.push(gitignore);
+        }
+    }
+
+    matchers
+        .iter()
+        .map(|gitignore| gitignore.matched(path, is_dir))
+        .find(|m| !m.is_none())
+        .map_or(Match::None, |m| {
+            m.map(|glob| {
+                let source = glob.from().map(Path::to_path_buf).unwrap_or_default();
+                (source, glob.original().to_string())
+            })
+        })
+}
+
+/// Locate the line of an ignore file on which a pattern is defined.
+fn find_line(source: &Path, pattern: &str) -> Option<usize> {
+    let content = fs::read_to_string(source).ok()?;
+    content
+        .lines()
+        .position(|line| line.trim() == pattern)
+        .map(|index| index + 1)
+}
+
+/// Explain the decision taken for a path that was not visited by the walker.
+pub fn unvisited(root: &Path, path: &Path, options: &WalkOptions) -> String {
+    if !path.exists() {
+        return "    => not found".to_string();
+    }
+
+    match ignore_reason(root, path, options) {
+        Some(reason) => format!("    {}: {}\n    => excluded", reason.rule().name(), reason),
+        None if path.is_dir() => "    => directory, traversed".to_string(),
+        None => "    => not visited".to_string(),
+    }
+}
+
+/// Render the rules evaluated for a path and the one that excluded it, if any.
+pub fn render(decision: &Decision) -> String {
+    let mut lines: Vec<String> = decision
+        .passed
+        .iter()
+        .map(|rule| format!("    {}: passed", rule.name()))
+        .collect();
+
+    match &decision.skip {
+        Some(reason) => {
+            lines.push(format!("    {}: {}", reason.rule().name(), reason));
+            // Non-text files are still listed in the tree
+            if reason.rule() == Rule::Text {
+                lines.push("    => listed in the tree, content excluded".to_string());
+            } else {
+                lines.push("    => excluded".to_string());
+            }
+        }
+        None => lines.push("    => included".to_string()),
+    }
+
+    lines.join("\n")
+}
+
+/// Print the explanation for a path to stderr.
+pub fn print(root: &Path, path: &Path, explanation: &str) {
+    let display = path.strip_prefix(root).unwrap_or(path);
+    eprintln!("Explain {}:", display.display());
+    eprintln!("{}", explanation);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::ignore_patterns;
+    use crate::source::tests::{files, fixture, options};
+
+    #[test]
+    fn child_negation_is_not_explained_as_ignored() {
+        let root = fixture(
+            "explain-negation",
+            &[
+                (".git/HEAD", ""),
+                (".gitignore", "/src/generated\n"),
+                ("src/.gitignore", "!generated\n"),
+                ("src/generated/a.rs", ""),
+            ],
+        );
+        let src = root.join("src");
+        assert!(ignore_reason(&src, &src.join("generated/a.rs"), &options()).is_none());
+    }
+
+    #[test]
+    fn gitignore_above_the_repository_does_not_apply() {
+        let root = fixture(
+            "explain-above",
+            &[
+                (".gitignore", "*.rs\n"),
+                ("repo/.git/HEAD", ""),
+                ("repo/a.rs", ""),
+            ],
+        );
+        let repo = root.join("repo");
+        assert!(ignore_reason(&repo, &repo.join("a.rs"), &options()).is_none());
+    }
+
+    #[test]
+    fn whitelisted_dotfiles_are_not_explained_as_hidden() {
+        let root = fixture(
+            "explain-dotfile",
+            &[
+                (
+                    ".gitignore",
+                    "!.github/
+",
+                ),
+                (".github/workflows/ci.yml", ""),
+                (".env", ""),
+                (".cache/a", ""),
+            ],
+        );
+        let options = WalkOptions {
+            ignore_patterns: ignore_patterns(&root, &["!.env".to_string()]).unwrap(),
+            ..options()
+        };
+        assert_eq!(files(&root, &options), [".env", ".github/workflows/ci.yml"]);
+
+        assert!(ignore_reason(&root, &root.join(".github/workflows/ci.yml"), &options).is_none());
+        assert!(ignore_reason(&root, &root.join(".env"), &options).is_none());
+        assert!(matches!(
+            ignore_reason(&root, &root.join(".cache/a"), &options),
+            Some(SkipReason::Hidden)
+        ));
+    }
+}
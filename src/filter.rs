@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::format_size;
+use crate::select::Slice;
+
+/// Limits applied to the files found by the walker.
+pub struct Limits {
+    pub max_file_size: u64,
+    pub max_total_size: u64,
+    pub max_files: usize,
+    pub max_files_per_extension: Option<usize>,
+    /// Size above which JSON files are skipped, with `--exclude-large-json`.
+    pub max_json_size: Option<u64>,
+}
+
+/// Extensions of the files `--exclude-large-json` applies to.
+const JSON_EXTENSIONS: &[&str] = &["json", "jsonl", "geojson"];
+
+/// What the files included so far add up to.
+#[derive(Debug, Default)]
+pub struct Totals {
+    pub files: usize,
+    pub size: u64,
+    /// Number of files included for each extension.
+    pub per_extension: HashMap<String, usize>,
+}
+
+impl Totals {
+    pub fn add(&mut self, path: &Path, size: u64) {
+        self.files += 1;
+        self.size += size;
+        *self.per_extension.entry(extension(path)).or_default() += 1;
+    }
+
+    pub fn remove(&mut self, path: &Path, size: u64) {
+        self.files -= 1;
+        self.size -= size;
+        if let Some(count) = self.per_extension.get_mut(&extension(path)) {
+            *count -= 1;
+        }
+    }
+}
+
+/// Extension of a file as counted by `--max-files-per-ext`, lowercase and empty
+/// for files without one.
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+/// A rule evaluated when deciding whether a path is included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    Hidden,
+    Ignore,
+    IgnorePattern,
+    ExcludeDir,
+    Symlink,
+    Special,
+    Owner,
+    DiffRange,
+    StartAt,
+    HeadFiles,
+    TailFiles,
+    MaxFiles,
+    MaxFilesPerExtension,
+    TotalSize,
+    FileSize,
+    LargeJson,
+    Text,
+    DataFile,
+    Codegen,
+    Encoded,
+    Read,
+    Sent,
+    Duplicate,
+    DirBudget,
+    LangBudget,
+    MaxTokens,
+    MaxOutputBytes,
+    Output,
+}
+
+impl Rule {
+    /// Short name of the rule, as shown in explanations.
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::Hidden => "hidden",
+            Rule::Ignore => "ignore files",
+            Rule::IgnorePattern => "--ignore-pattern",
+            Rule::ExcludeDir => "--exclude-dir",
+            Rule::Symlink => "--follow-symlinks",
+            Rule::Special => "special files",
+            Rule::Owner => "--owner",
+            Rule::DiffRange => "--diff-range",
+            Rule::StartAt => "--start-at",
+            Rule::HeadFiles => "--head-files",
+            Rule::TailFiles => "--tail-files",
+            Rule::MaxFiles => "--num-files",
+            Rule::MaxFilesPerExtension => "--max-files-per-ext",
+            Rule::TotalSize => "--total-size",
+            Rule::FileSize => "--file-size",
+            Rule::LargeJson => "--exclude-large-json",
+            Rule::Text => "text detection",
+            Rule::DataFile => "--skip-data-files",
+            Rule::Codegen => "--skip-codegen",
+            Rule::Encoded => "--skip-blobs",
+            Rule::Read => "read",
+            Rule::Sent => "--state",
+            Rule::Duplicate => "--exclude-symlinked-dupes",
+            Rule::DirBudget => "--dir-budget",
+            Rule::LangBudget => "--lang-budget",
+            Rule::MaxTokens => "--max-tokens",
+            Rule::MaxOutputBytes => "--max-output-bytes",
+            Rule::Output => "--output",
+        }
+    }
+}
+
+/// The reason a path was excluded.
+#[derive(Debug, Clone)]
+pub enum SkipReason {
+    Hidden,
+    Ignored {
+        source: PathBuf,
+        line: Option<usize>,
+        pattern: String,
+    },
+    IgnorePattern(String),
+    ExcludeDir(String),
+    Symlink(PathBuf),
+    /// A named pipe, socket or device, given by its kind.
+    Special(&'static str),
+    Owner(String),
+    DiffRange(String),
+    StartAt(PathBuf),
+    Sliced(Slice),
+    MaxFiles(usize),
+    MaxFilesPerExtension {
+        extension: String,
+        limit: usize,
+    },
+    TotalSize(u64),
+    FileSize {
+        size: u64,
+        limit: u64,
+    },
+    LargeJson {
+        size: u64,
+        limit: u64,
+    },
+    NonText,
+    DataFile(String),
+    Codegen(String),
+    Encoded(String),
+    Read(String),
+    Sent(usize),
+    /// The same content as a file kept, given by its path as shown.
+    Duplicate(PathBuf),
+    DirBudget {
+        dir: PathBuf,
+        budget: usize,
+    },
+    LangBudget {
+        language: String,
+        budget: usize,
+    },
+    MaxTokens(usize),
+    MaxOutputBytes(u64),
+    Output,
+}
+
+impl SkipReason {
+    /// The rule that produced this reason.
+    pub fn rule(&self) -> Rule {
+        match self {
+            SkipReason::Hidden => Rule::Hidden,
+            SkipReason::Ignored { .. } => Rule::Ignore,
+            SkipReason::IgnorePattern(_) => Rule::IgnorePattern,
+            SkipReason::ExcludeDir(_) => Rule::ExcludeDir,
+            SkipReason::Symlink(_) => Rule::Symlink,
+            SkipReason::Special(_) => Rule::Special,
+            SkipReason::Owner(_) => Rule::Owner,
+            SkipReason::DiffRange(_) => Rule::DiffRange,
+            SkipReason::StartAt(_) => Rule::StartAt,
+            SkipReason::Sliced(Slice::Head(_)) => Rule::HeadFiles,
+            SkipReason::Sliced(Slice::Tail(_)) => Rule::TailFiles,
+            SkipReason::MaxFiles(_) => Rule::MaxFiles,
+            SkipReason::MaxFilesPerExtension { .. } => Rule::MaxFilesPerExtension,
+            SkipReason::TotalSize(_) => Rule::TotalSize,
+            SkipReason::FileSize { .. } => Rule::FileSize,
+            SkipReason::LargeJson { .. } => Rule::LargeJson,
+            SkipReason::NonText => Rule::Text,
+            SkipReason::DataFile(_) => Rule::DataFile,
+            SkipReason::Codegen(_) => Rule::Codegen,
+            SkipReason::Encoded(_) => Rule::Encoded,
+            SkipReason::Read(_) => Rule::Read,
+            SkipReason::Sent(_) => Rule::Sent,
+            SkipReason::Duplicate(_) => Rule::Duplicate,
+            SkipReason::DirBudget { .. } => Rule::DirBudget,
+            SkipReason::LangBudget { .. } => Rule::LangBudget,
+            SkipReason::MaxTokens(_) => Rule::MaxTokens,
+            SkipReason::MaxOutputBytes(_) => Rule::MaxOutputBytes,
+            SkipReason::Output => Rule::Output,
+        }
+    }
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Hidden => write!(f, "Hidden path"),
+            SkipReason::Ignored {
+                source,
+                line,
+                pattern,
+            } => {
+                let name = source
+                    .file_name()
+                    .map(|name| name.to_string_lossy())
+                    .unwrap_or_default();
+                write!(
+                    f,
+                    "Matched {} pattern `{}` from {}",
+                    name,
+                    pattern,
+                    source.display()
+                )?;
+                if let Some(line) = line {
+                    write!(f, ":{}", line)?;
+                }
+                Ok(())
+            }
+            SkipReason::IgnorePattern(pattern) => {
+                write!(f, "Matched --ignore-pattern `{}`", pattern)
+            }
+            SkipReason::ExcludeDir(name) => write!(f, "Inside a directory named `{}`", name),
+            SkipReason::Symlink(shown) => {
+                write!(
+                    f,
+                    "Same file as {}, through a symbolic link",
+                    shown.display()
+                )
+            }
+            SkipReason::Special(kind) => write!(f, "Not a regular file, but a {}", kind),
+            SkipReason::Owner(owners) => write!(f, "Not selected by CODEOWNERS: {}", owners),
+            SkipReason::DiffRange(range) => write!(f, "Not changed in {}", range),
+            SkipReason::StartAt(start) => {
+                write!(
+                    f,
+                    "Sorted before {}, where the files start",
+                    start.display()
+                )
+            }
+            SkipReason::Sliced(Slice::Head(count)) => {
+                write!(f, "Not among the first {} files in sort order", count)
+            }
+            SkipReason::Sliced(Slice::Tail(count)) => {
+                write!(f, "Not among the last {} files in sort order", count)
+            }
+            SkipReason::MaxFiles(limit) => write!(f, "Maximum file limit ({}) reached", limit),
+            SkipReason::MaxFilesPerExtension { extension, limit } if extension.is_empty() => {
+                write!(
+                    f,
+                    "Maximum file limit ({}) reached for files without extension",
+                    limit
+                )
+            }
+            SkipReason::MaxFilesPerExtension { extension, limit } => write!(
+                f,
+                "Maximum file limit ({}) reached for .{} files",
+                limit, extension
+            ),
+            SkipReason::TotalSize(limit) => {
+                write!(f, "Total size limit ({}) reached", format_size(*limit))
+            }
+            SkipReason::FileSize { size, limit } => write!(
+                f,
+                "File exceeds maximum size ({} > {})",
+                format_size(*size),
+                format_size(*limit)
+            ),
+            SkipReason::LargeJson { size, limit } => write!(
+                f,
+                "JSON file exceeds maximum size ({} > {})",
+                format_size(*size),
+                format_size(*limit)
+            ),
+            SkipReason::NonText => write!(f, "Non-text file"),
+            SkipReason::DataFile(shape) => write!(f, "Looks like generated data: {}", shape),
+            SkipReason::Codegen(reason) => write!(f, "Looks like generated code: {}", reason),
+            SkipReason::Encoded(runs) => write!(f, "Looks like encoded binary data: {}", runs),
+            SkipReason::Read(error) => write!(f, "Error reading file: {}", error),
+            SkipReason::Sent(part) => write!(f, "Sent unchanged in part {}", part),
+            SkipReason::Duplicate(original) => {
+                write!(f, "Same content as {}", original.display())
+            }
+            SkipReason::DirBudget { dir, budget } => {
+                write!(f, "Token budget of {} ({}) exceeded", dir.display(), budget)
+            }
+            SkipReason::LangBudget { language, budget } => {
+                write!(
+                    f,
+                    "Token budget of {} files ({}) exceeded",
+                    language, budget
+                )
+            }
+            SkipReason::MaxTokens(budget) => write!(f, "Token budget ({}) exceeded", budget),
+            SkipReason::MaxOutputBytes(limit) => {
+                write!(f, "Output size limit ({} bytes) reached", limit)
+            }
+            SkipReason::Output => write!(f, "Output file of this run"),
+        }
+    }
+}
+
+/// The outcome of running a path through the filtering pipeline.
+#[derive(Debug, Clone, Default)]
+pub struct Decision {
+    /// Rules that were evaluated and let the path through, in order.
+    pub passed: Vec<Rule>,
+    /// The first rule that excluded the path, if any.
+    pub skip: Option<SkipReason>,
+}
+
+impl Decision {
+    /// Start the decision for a path found by the walker, which already applied
+    /// the hidden and ignore rules.
+    pub fn walked() -> Self {
+        Decision {
+            passed: vec![
+                Rule::Hidden,
+                Rule::Ignore,
+                Rule::IgnorePattern,
+                Rule::ExcludeDir,
+            ],
+            skip: None,
+        }
+    }
+
+    fn pass(&mut self, rule: Rule) {
+        self.passed.push(rule);
+    }
+
+    fn exclude(mut self, reason: SkipReason) -> Self {
+        self.skip = Some(reason);
+        self
+    }
+
+    /// Record a reason for excluding the path found outside of the pipeline.
+    pub fn fail(&mut self, reason: SkipReason) {
+        self.skip = Some(reason);
+    }
+}
+
+/// Run a file through the filtering pipeline, stopping at the first rule that
+/// excludes it.
+///
+/// Text detection runs ahead of the pipeline, possibly in parallel, and its
+/// outcome is only taken into account once the limits let the file through, as
+/// is the reason its content looks generated, if any.
+pub fn evaluate(
+    mut decision: Decision,
+    path: &Path,
+    file_size: u64,
+    is_text: Result<bool>,
+    generated: Option<SkipReason>,
+    totals: &Totals,
+    limits: &Limits,
+) -> Result<Decision> {
+    // Check limits for number of files
+    if totals.files >= limits.max_files {
+        return Ok(decision.exclude(SkipReason::MaxFiles(limits.max_files)));
+    }
+    decision.pass(Rule::MaxFiles);
+
+    // Check limits for number of files with the same extension
+    if let Some(limit) = limits.max_files_per_extension {
+        let extension = extension(path);
+        if totals.per_extension.get(&extension).copied().unwrap_or(0) >= limit {
+            return Ok(decision.exclude(SkipReason::MaxFilesPerExtension { extension, limit }));
+        }
+        decision.pass(Rule::MaxFilesPerExtension);
+    }
+
+    // Check limits for total size
+    if totals.size + file_size > limits.max_total_size {
+        return Ok(decision.exclude(SkipReason::TotalSize(limits.max_total_size)));
+    }
+    decision.pass(Rule::TotalSize);
+
+    // Check limits for max file size
+    if file_size > limits.max_file_size {
+        return Ok(decision.exclude(SkipReason::FileSize {
+            size: file_size,
+            limit: limits.max_file_size,
+        }));
+    }
+    decision.pass(Rule::FileSize);
+
+    // Check limits for JSON files, a common sink of tokens that passes as text
+    if let Some(limit) = limits.max_json_size {
+        if JSON_EXTENSIONS.contains(&extension(path).as_str()) && file_size > limit {
+            return Ok(decision.exclude(SkipReason::LargeJson {
+                size: file_size,
+                limit,
+            }));
+        }
+        decision.pass(Rule::LargeJson);
+    }
+
+    // Only text files are processed
+    if !is_text.with_context(|| format!("Error checking if file is text: {}", path.display()))? {
+        return Ok(decision.exclude(SkipReason::NonText));
+    }
+    decision.pass(Rule::Text);
+
+    // Skip generated data and code when asked to
+    if let Some(reason) = generated {
+        return Ok(decision.exclude(reason));
+    }
+
+    Ok(decision)
+}
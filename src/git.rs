@@ -0,0 +1,267 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use git2::{Config, Oid, Repository};
+
+/// The HEAD commit of a repository.
+pub struct HeadCommit {
+    /// Abbreviated commit hash.
+    pub id: String,
+    /// Full commit message, subject and body.
+    pub message: String,
+}
+
+/// A commit listed in the recent history.
+pub struct LogEntry {
+    /// Abbreviated commit hash.
+    pub id: String,
+    /// Subject line of the commit message.
+    pub summary: String,
+    pub author: String,
+}
+
+/// The files changed by a range of revisions.
+pub struct RangeChanges {
+    /// The commits diffed, as `FROM..TO` with their full hashes, which change
+    /// when a revision of the range moves.
+    pub commits: String,
+    /// The paths of the files changed, made canonical.
+    pub paths: HashSet<PathBuf>,
+}
+
+/// Abbreviate a commit hash the way git does by default.
+pub fn short_id(oid: Oid) -> String {
+    let id = oid.to_string();
+    id[..7.min(id.len())].to_string()
+}
+
+/// Find the HEAD commit of the repository containing a path, if it is in a
+/// repository that has commits.
+pub fn head_commit(path: &Path) -> Option<HeadCommit> {
+    let repository = Repository::discover(path).ok()?;
+    let commit = repository.head().ok()?.peel_to_commit().ok()?;
+
+    Some(HeadCommit {
+        id: short_id(commit.id()),
+        message: commit.message()?.trim_end().to_string(),
+    })
+}
+
+/// List the last commits reachable from HEAD, most recent first, if the path is
+/// in a repository that has commits.
+pub fn recent_commits(path: &Path, count: usize) -> Option<Vec<LogEntry>> {
+    let repository = Repository::discover(path).ok()?;
+    let mut revwalk = repository.revwalk().ok()?;
+    revwalk.push_head().ok()?;
+
+    revwalk
+        .take(count)
+        .map(|oid| {
+            let commit = repository.find_commit(oid.ok()?).ok()?;
+            let author = commit.author().name().unwrap_or_default().to_string();
+            Some(LogEntry {
+                id: short_id(commit.id()),
+                summary: commit.summary().unwrap_or_default().to_string(),
+                author,
+            })
+        })
+        .collect()
+}
+
+/// A path with its directory resolved, to compare it with those listed by
+/// [`changed_in_range`] whatever symbolic links or `..` lead to it. The file
+/// itself is kept, so that a link to a file is not taken for its target.
+pub fn canonical(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => fs::canonicalize(parent)
+            .map(|parent| parent.join(name))
+            .unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// List the files changed between two revisions, given as `REV1..REV2`, or as
+/// `REV1...REV2` to diff from the commit the two histories split from, as for
+/// a pull request. An empty side stands for HEAD, as in git. The paths are
+/// those of the working tree of the repository containing a path, made
+/// canonical, and include the files deleted by the range.
+pub fn changed_in_range(path: &Path, range: &str) -> Result<RangeChanges> {
+    let (from, to, from_merge_base) = match range.split_once("...") {
+        Some((from, to)) => (from, to, true),
+        None => match range.split_once("..") {
+            Some((from, to)) => (from, to, false),
+            None => bail!("Invalid range: {} (expected REV1..REV2)", range),
+        },
+    };
+
+    let repository = Repository::discover(path)
+        .with_context(|| format!("Not in a git repository: {}", path.display()))?;
+    let Some(workdir) = repository.workdir() else {
+        bail!("--diff-range needs a working tree, not a bare repository");
+    };
+    let commit = |revision: &str| {
+        let revision = if revision.is_empty() {
+            "HEAD"
+        } else {
+            revision
+        };
+        repository
+            .revparse_single(revision)
+            .and_then(|object| object.peel_to_commit())
+            .with_context(|| format!("Unknown revision: {}", revision))
+    };
+    let (mut from_commit, to_commit) = (commit(from)?, commit(to)?);
+    if from_merge_base {
+        let base = repository
+            .merge_base(from_commit.id(), to_commit.id())
+            .with_context(|| format!("No common ancestor to {} and {}", from, to))?;
+        from_commit = repository
+            .find_commit(base)
+            .with_context(|| format!("Failed to read the common ancestor of {}", range))?;
+    }
+
+    let old = from_commit
+        .tree()
+        .with_context(|| format!("Failed to read the tree of revision: {}", from))?;
+    let new = to_commit
+        .tree()
+        .with_context(|| format!("Failed to read the tree of revision: {}", to))?;
+    let diff = repository
+        .diff_tree_to_tree(Some(&old), Some(&new), None)
+        .with_context(|| format!("Failed to diff {}", range))?;
+
+    // Paths are matched against the walked ones made canonical
+    let workdir = fs::canonicalize(workdir).unwrap_or_else(|_| workdir.to_path_buf());
+    Ok(RangeChanges {
+        commits: format!("{}..{}", from_commit.id(), to_commit.id()),
+        paths: diff
+            .deltas()
+            .filter_map(|delta| delta.new_file().path().map(|file| workdir.join(file)))
+            .collect(),
+    })
+}
+
+/// Find the global excludes file the way git does: `core.excludesFile` from the
+/// global configuration (`GIT_CONFIG_GLOBAL`, or `~/.gitconfig` then
+/// `$XDG_CONFIG_HOME/git/config`), defaulting to `$XDG_CONFIG_HOME/git/ignore`.
+pub fn global_excludes_file() -> Option<PathBuf> {
+    let home = env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from);
+    let xdg_config = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".config")));
+
+    let configs: Vec<PathBuf> = match env::var_os("GIT_CONFIG_GLOBAL") {
+        Some(config) => vec![PathBuf::from(config)],
+        None => home
+            .iter()
+            .map(|home| home.join(".gitconfig"))
+            .chain(xdg_config.iter().map(|dir| dir.join("git").join("config")))
+            .collect(),
+    };
+
+    configs
+        .iter()
+        .filter(|config| config.is_file())
+        .find_map(|config| {
+            Config::open(config)
+                .ok()?
+                .get_path("core.excludesFile")
+                .ok()
+        })
+        .or_else(|| xdg_config.map(|dir| dir.join("git").join("ignore")))
+        .filter(|file| file.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process::Command;
+
+    use crate::source::tests::fixture;
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=llmr", "-c", "user.email=llmr@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    #[test]
+    fn global_excludes_file_follows_git_config_global() {
+        let root = fixture(
+            "global-excludes",
+            &[
+                ("empty-config", "[user]\n\tname = llmr\n"),
+                ("home/custom-ignore", "*.swp\n"),
+                ("xdg/git/ignore", ".idea/\n"),
+            ],
+        );
+        let custom = root.join("home/custom-ignore");
+        fs::write(
+            root.join("config"),
+            format!("[core]\n\texcludesFile = {}\n", custom.display()),
+        )
+        .unwrap();
+        // The variables are only read by this test
+        env::set_var("HOME", root.join("home"));
+        env::set_var("XDG_CONFIG_HOME", root.join("xdg"));
+
+        env::set_var("GIT_CONFIG_GLOBAL", root.join("config"));
+        assert_eq!(global_excludes_file(), Some(custom));
+
+        // Without core.excludesFile, git's default file applies
+        env::set_var("GIT_CONFIG_GLOBAL", root.join("empty-config"));
+        assert_eq!(global_excludes_file(), Some(root.join("xdg/git/ignore")));
+
+        fs::remove_file(root.join("xdg/git/ignore")).unwrap();
+        assert_eq!(global_excludes_file(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn canonical_resolves_the_directory_but_not_the_file() {
+        let root = fixture("canonical", &[("real/a.rs", "")]);
+        std::os::unix::fs::symlink(root.join("real"), root.join("dir")).unwrap();
+        std::os::unix::fs::symlink("a.rs", root.join("real/link.rs")).unwrap();
+
+        let real = fs::canonicalize(root.join("real")).unwrap();
+        assert_eq!(canonical(&root.join("dir/../dir/a.rs")), real.join("a.rs"));
+        assert_eq!(canonical(&root.join("dir/link.rs")), real.join("link.rs"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn changed_files_match_paths_reached_through_a_link() {
+        let root = fixture(
+            "diff-range",
+            &[("repo/src/a.rs", "a\n"), ("repo/b.rs", "b\n")],
+        );
+        let repo = root.join("repo");
+        git(&repo, &["init", "-q"]);
+        git(&repo, &["add", "."]);
+        git(&repo, &["commit", "-q", "-m", "first"]);
+        fs::write(repo.join("src/a.rs"), "changed\n").unwrap();
+        git(&repo, &["commit", "-q", "-am", "second"]);
+        std::os::unix::fs::symlink(&repo, root.join("link")).unwrap();
+
+        // Walked from the link, with a detour through `..`
+        let walked = root.join("link/src/../src");
+        let changed = changed_in_range(&walked, "HEAD~1..HEAD").unwrap().paths;
+        assert!(changed.contains(&canonical(&walked.join("a.rs"))));
+        assert!(!changed.contains(&canonical(&root.join("link/b.rs"))));
+        assert_eq!(
+            canonical(&walked.join("a.rs")),
+            canonical(&repo.join("src/a.rs"))
+        );
+    }
+}
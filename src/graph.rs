@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+/// Most files whose imports `--dep-graph` reads, past which the graph is left
+/// out rather than drowning the files in it.
+pub const MAX_FILES: usize = 200;
+
+/// Extensions of the files whose imports are read.
+const EXTENSIONS: &[&str] = &["rs", "py", "js", "jsx", "mjs", "cjs", "ts", "tsx"];
+
+/// Extensions tried, in order, for a JavaScript or TypeScript import given
+/// without one.
+const SCRIPT_EXTENSIONS: &[&str] = &["ts", "tsx", "js", "jsx", "mjs", "cjs"];
+
+/// Whether the imports of a file can be read.
+pub fn is_supported(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| EXTENSIONS.contains(&extension))
+}
+
+/// The files imported by each file, as indices into the files given as their
+/// path on disk and their content. Imports of files not given, such as those of
+/// other packages, are left out.
+pub fn edges(files: &[(&Path, &str)]) -> Vec<Vec<usize>> {
+    let index: HashMap<&Path, usize> = files
+        .iter()
+        .enumerate()
+        .map(|(position, (path, _))| (*path, position))
+        .collect();
+    let find = |path: &Path| index.get(normalize(path).as_path()).copied();
+
+    files
+        .iter()
+        .enumerate()
+        .map(|(position, (path, content))| {
+            let extension = path.extension().and_then(|extension| extension.to_str());
+            let mut imported = match extension {
+                Some("rs") => rust(path, content, &find),
+                Some("py") => python(path, content, &find),
+                Some(_) if is_supported(path) => script(path, content, &find),
+                _ => Vec::new(),
+            };
+            imported.retain(|other| *other != position);
+            imported.sort_unstable();
+            imported.dedup();
+            imported
+        })
+        .collect()
+}
+
+/// Render the graph as an adjacency list, one line per file importing others.
+pub fn render(paths: &[PathBuf], edges: &[Vec<usize>]) -> String {
+    let mut output = String::new();
+    for (path, imported) in paths.iter().zip(edges) {
+        if imported.is_empty() {
+            continue;
+        }
+        let imported: Vec<String> = imported
+            .iter()
+            .map(|other| paths[*other].display().to_string())
+            .collect();
+        output.push_str(&format!("{} -> {}\n", path.display(), imported.join(", ")));
+    }
+    output
+}
+
+/// Resolve `.` and `..` without touching the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Modules of a Rust file: those declared with `mod`, and those used through
+/// `crate::`, `super::` or `self::`.
+fn rust(path: &Path, content: &str, find: &impl Fn(&Path) -> Option<usize>) -> Vec<usize> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let name = path
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+    // The directory holding the submodules of the file
+    let module_dir = match name {
+        "mod" | "lib" | "main" => dir.to_path_buf(),
+        name => dir.join(name),
+    };
+    // The directory of the crate root, the nearest holding a lib.rs or main.rs
+    let crate_dir = dir
+        .ancestors()
+        .find(|ancestor| {
+            find(&ancestor.join("lib.rs")).is_some() || find(&ancestor.join("main.rs")).is_some()
+        })
+        .unwrap_or(dir);
+    let module = |base: &Path, segments: &[&str]| -> Option<usize> {
+        (1..=segments.len()).rev().find_map(|length| {
+            let module: PathBuf = segments[..length].iter().collect();
+            find(&base.join(&module).with_extension("rs"))
+                .or_else(|| find(&base.join(&module).join("mod.rs")))
+        })
+    };
+
+    let mut imported = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        let line = line.strip_prefix("pub ").unwrap_or(line);
+        if let Some(name) = line
+            .strip_prefix("mod ")
+            .and_then(|rest| rest.strip_suffix(';'))
+        {
+            imported.extend(module(&module_dir, &[name.trim()]));
+            continue;
+        }
+        let Some(used) = line.strip_prefix("use ") else {
+            continue;
+        };
+        let (base, rest) = if let Some(rest) = used.strip_prefix("crate::") {
+            (crate_dir, rest)
+        } else if let Some(rest) = used.strip_prefix("super::") {
+            (module_dir.parent().unwrap_or(dir), rest)
+        } else if let Some(rest) = used.strip_prefix("self::") {
+            (module_dir.as_path(), rest)
+        } else {
+            continue;
+        };
+        // Each path of a group, such as `crate::{a::b, c}`, is resolved apart
+        let paths: Vec<&str> = match rest.strip_prefix('{') {
+            Some(group) => group.trim_end_matches([';', '}']).split(',').collect(),
+            None => vec![rest],
+        };
+        for used in paths {
+            let segments: Vec<&str> = used
+                .split("::")
+                .map(|segment| segment.trim().trim_end_matches(';'))
+                .take_while(|segment| {
+                    !segment.is_empty()
+                        && segment
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+                })
+                .collect();
+            if !segments.is_empty() {
+                imported.extend(module(base, &segments));
+            }
+        }
+    }
+    imported
+}
+
+/// Modules of a Python file, imported by `import` or `from`, relative ones
+/// from the package of the file and absolute ones from any directory above it.
+fn python(path: &Path, content: &str, find: &impl Fn(&Path) -> Option<usize>) -> Vec<usize> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let module = |base: &Path, name: &str| -> Option<usize> {
+        let module: PathBuf = name.split('.').collect();
+        find(&base.join(&module).with_extension("py"))
+            .or_else(|| find(&base.join(&module).join("__init__.py")))
+    };
+
+    let mut imported = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if let Some(names) = line.strip_prefix("import ") {
+            for name in names.split(',') {
+                let name = name.split_whitespace().next().unwrap_or("");
+                imported.extend(dir.ancestors().find_map(|base| module(base, name)));
+            }
+            continue;
+        }
+        let Some((from, names)) = line
+            .strip_prefix("from ")
+            .and_then(|rest| rest.split_once(" import "))
+        else {
+            continue;
+        };
+        let name = from.trim().trim_start_matches('.');
+        let dots = from.trim().len() - name.len();
+        if dots == 0 {
+            imported.extend(dir.ancestors().find_map(|base| module(base, name)));
+            continue;
+        }
+        let Some(base) = dir.ancestors().nth(dots - 1) else {
+            continue;
+        };
+        match name.is_empty() {
+            // `from . import a, b` imports modules of the package
+            true => {
+                for name in names.trim_matches(['(', ')', ' ']).split(',') {
+                    let name = name.split_whitespace().next().unwrap_or("");
+                    if !name.is_empty() {
+                        imported.extend(module(base, name));
+                    }
+                }
+            }
+            false => imported.extend(module(base, name)),
+        }
+    }
+    imported
+}
+
+/// Modules of a JavaScript or TypeScript file, imported by a relative path in
+/// an `import`, an `export ... from` or a `require`.
+fn script(path: &Path, content: &str, find: &impl Fn(&Path) -> Option<usize>) -> Vec<usize> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let module = |specifier: &str| -> Option<usize> {
+        let base = dir.join(specifier);
+        find(&base)
+            .or_else(|| {
+                SCRIPT_EXTENSIONS
+                    .iter()
+                    .find_map(|extension| find(&base.with_extension(extension)))
+            })
+            .or_else(|| {
+                SCRIPT_EXTENSIONS
+                    .iter()
+                    .find_map(|extension| find(&base.join("index").with_extension(extension)))
+            })
+    };
+
+    let mut imported = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        let specifier = if line.starts_with("import ") || line.starts_with("export ") {
+            line.rsplit_once(" from ")
+                .map(|(_, specifier)| specifier)
+                .or_else(|| line.strip_prefix("import "))
+        } else {
+            line.split_once("require(").map(|(_, specifier)| specifier)
+        };
+        let Some(specifier) = specifier else {
+            continue;
+        };
+        let specifier = specifier
+            .trim_start()
+            .trim_start_matches(['\'', '"'])
+            .split(['\'', '"'])
+            .next()
+            .unwrap_or("");
+        if specifier.starts_with('.') {
+            imported.extend(module(specifier));
+        }
+    }
+    imported
+}
@@ -0,0 +1,44 @@
+/// Upper bound of the first bucket, each next bucket being twice as wide.
+const FIRST_BUCKET: usize = 128;
+
+/// Width of the longest bar.
+const BAR_WIDTH: usize = 40;
+
+/// Render the number of files per bucket of tokens, the buckets doubling in
+/// width so that a few huge files stand out from the many small ones.
+pub fn render(tokens: &[usize]) -> String {
+    let mut counts: Vec<usize> = Vec::new();
+    for &count in tokens {
+        // Bucket 0 holds up to FIRST_BUCKET - 1 tokens, bucket n from
+        // FIRST_BUCKET << (n - 1)
+        let bucket = match count / FIRST_BUCKET {
+            0 => 0,
+            scaled => scaled.ilog2() as usize + 1,
+        };
+        if counts.len() <= bucket {
+            counts.resize(bucket + 1, 0);
+        }
+        counts[bucket] += 1;
+    }
+
+    let largest = counts.iter().copied().max().unwrap_or(0);
+    let first = counts.iter().position(|&count| count > 0).unwrap_or(0);
+    let mut rendered = String::new();
+    for (bucket, &count) in counts.iter().enumerate().skip(first) {
+        let low = match bucket {
+            0 => 0,
+            _ => FIRST_BUCKET << (bucket - 1),
+        };
+        let high = (FIRST_BUCKET << bucket) - 1;
+        let bar = "#".repeat((count * BAR_WIDTH).div_ceil(largest.max(1)));
+        let line = format!(
+            "    {:>15} {:>6} {}",
+            format!("{}-{}", low, high),
+            count,
+            bar
+        );
+        rendered.push_str(line.trim_end());
+        rendered.push('\n');
+    }
+    rendered
+}
@@ -0,0 +1,332 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+use git2::{ObjectType, Oid, Repository, TreeWalkMode, TreeWalkResult};
+use ignore::gitignore::Gitignore;
+use tiktoken_rs::{cl100k_base, CoreBPE};
+
+use crate::{git, is_text, snapshot, source, DEFAULT_MAX_FILE_SIZE};
+
+/// Name of the file, in the git directory, caching the tokens of each blob.
+const CACHE_FILE: &str = "llmr-tokens";
+
+/// Arguments of `llmr history`.
+#[derive(Args, Debug)]
+pub struct HistoryArgs {
+    /// Only go back to this revision, excluded, such as a tag or a commit.
+    #[arg(long, value_name = "REV")]
+    since: Option<String>,
+    /// Sample every Nth commit of the first-parent history.
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    step: usize,
+    /// Only sample tagged commits.
+    #[arg(long)]
+    tags: bool,
+    /// Maximum number of revisions sampled.
+    #[arg(long = "max-revisions", value_name = "N", default_value_t = 100)]
+    max_revisions: usize,
+    /// Format of the table printed.
+    #[arg(long, value_enum, default_value_t = HistoryFormat::Table)]
+    format: HistoryFormat,
+    /// Ignore paths matching a gitignore-style pattern (can be repeated, "!" re-includes).
+    #[arg(long = "ignore-pattern", value_name = "PATTERN")]
+    ignore_patterns: Vec<String>,
+    /// Skip the directories with this exact name, case-sensitive, at any depth (can be repeated).
+    #[arg(long = "exclude-dir", value_name = "NAME")]
+    exclude_dirs: Vec<String>,
+    /// Maximum file size to count (in bytes).
+    #[arg(short = 'f', long = "file-size", default_value_t = DEFAULT_MAX_FILE_SIZE)]
+    max_file_size: u64,
+}
+
+/// How the history is printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HistoryFormat {
+    /// Aligned columns.
+    Table,
+    /// Comma-separated values, after a header line.
+    Csv,
+}
+
+/// What is counted of a blob, the same at every revision it appears in.
+#[derive(Debug, Clone, Copy)]
+struct BlobInfo {
+    size: u64,
+    /// Number of tokens of the content, if it is text.
+    tokens: Option<usize>,
+}
+
+/// Tokens of the blobs already counted, kept across runs in the git directory.
+struct TokenCache {
+    path: PathBuf,
+    blobs: HashMap<Oid, BlobInfo>,
+    changed: bool,
+}
+
+impl TokenCache {
+    /// Load the cache, starting empty if it is missing or unreadable.
+    fn load(git_dir: &Path) -> Self {
+        let path = git_dir.join(CACHE_FILE);
+        let blobs = fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split(' ');
+                let oid = Oid::from_str(fields.next()?).ok()?;
+                let size = fields.next()?.parse().ok()?;
+                let tokens = match fields.next()? {
+                    "-" => None,
+                    tokens => Some(tokens.parse().ok()?),
+                };
+                Some((oid, BlobInfo { size, tokens }))
+            })
+            .collect();
+
+        TokenCache {
+            path,
+            blobs,
+            changed: false,
+        }
+    }
+
+    /// Count the tokens of a blob, reading it only if it was never counted.
+    fn count(&mut self, repository: &Repository, oid: Oid, bpe: &CoreBPE) -> Result<BlobInfo> {
+        if let Some(info) = self.blobs.get(&oid) {
+            return Ok(*info);
+        }
+
+        let blob = repository
+            .find_blob(oid)
+            .with_context(|| format!("Failed to read blob: {}", oid))?;
+        let content = blob.content();
+        let head = &content[..content.len().min(1024)];
+        let info = BlobInfo {
+            size: content.len() as u64,
+            tokens: is_text(head)
+                .then(|| bpe.encode_ordinary(&String::from_utf8_lossy(content)).len()),
+        };
+        self.blobs.insert(oid, info);
+        self.changed = true;
+        Ok(info)
+    }
+
+    /// Save the cache if blobs were counted.
+    fn save(&self) -> Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        let mut content = String::new();
+        for (oid, info) in &self.blobs {
+            let tokens = match info.tokens {
+                Some(tokens) => tokens.to_string(),
+                None => "-".to_string(),
+            };
+            content.push_str(&format!("{} {} {}\n", oid, info.size, tokens));
+        }
+        fs::write(&self.path, content)
+            .with_context(|| format!("Failed to write token cache: {}", self.path.display()))
+    }
+}
+
+/// Counts of one revision.
+struct Revision {
+    id: String,
+    date: String,
+    files: usize,
+    tokens: usize,
+}
+
+/// Count the files and tokens of a commit below a directory of the repository,
+/// reading the blobs from the object database.
+fn count(
+    repository: &Repository,
+    oid: Oid,
+    prefix: &Path,
+    args: &HistoryArgs,
+    ignore_patterns: &Gitignore,
+    cache: &mut TokenCache,
+    bpe: &CoreBPE,
+) -> Result<Revision> {
+    let commit = repository
+        .find_commit(oid)
+        .with_context(|| format!("Failed to find commit: {}", oid))?;
+    let tree = commit
+        .tree()
+        .with_context(|| format!("Failed to read the tree of commit: {}", oid))?;
+
+    // The walk cannot fail from its callback, so blobs are listed first
+    let mut blobs: Vec<(PathBuf, Oid)> = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() == Some(ObjectType::Blob) {
+            if let Some(name) = entry.name() {
+                blobs.push((Path::new(dir).join(name), entry.id()));
+            }
+        }
+        TreeWalkResult::Ok
+    })
+    .with_context(|| format!("Failed to walk the tree of commit: {}", oid))?;
+
+    let mut files = 0;
+    let mut tokens = 0;
+    for (path, blob) in blobs {
+        let Ok(relative) = path.strip_prefix(prefix) else {
+            continue;
+        };
+        if !snapshot::selected(relative, false, &args.exclude_dirs, ignore_patterns) {
+            continue;
+        }
+        let info = cache.count(repository, blob, bpe)?;
+        if let Some(blob_tokens) = info.tokens.filter(|_| info.size <= args.max_file_size) {
+            files += 1;
+            tokens += blob_tokens;
+        }
+    }
+
+    Ok(Revision {
+        id: git::short_id(oid),
+        date: date(commit.time().seconds()),
+        files,
+        tokens,
+    })
+}
+
+/// Format a Unix timestamp as a UTC date and time, such as
+/// "2024-05-17T09:30:00Z", which sorts as the times do.
+pub fn timestamp(seconds: i64) -> String {
+    let time = seconds.rem_euclid(86400);
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        date(seconds),
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Format a Unix timestamp as a UTC date, such as "2024-05-17".
+fn date(seconds: i64) -> String {
+    // Civil date from the number of days since 1970-01-01, after Howard Hinnant
+    let days = seconds.div_euclid(86400) + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Print the number of files and tokens of sampled revisions, most recent
+/// first, without touching the working tree.
+pub fn run(args: &HistoryArgs) -> Result<()> {
+    if args.step == 0 {
+        bail!("--step must be at least 1");
+    }
+
+    let current_dir = std::env::current_dir().context("Failed to get current directory")?;
+    let repository = Repository::discover(&current_dir)
+        .with_context(|| format!("Not in a git repository: {}", current_dir.display()))?;
+    let workdir = repository
+        .workdir()
+        .context("The repository has no working tree")?;
+
+    // Only the current directory is counted, as in a regular run
+    let prefix = current_dir
+        .strip_prefix(fs::canonicalize(workdir).unwrap_or_else(|_| workdir.to_path_buf()))
+        .unwrap_or(Path::new(""))
+        .to_path_buf();
+    let ignore_patterns = source::ignore_patterns(&current_dir, &args.ignore_patterns)?;
+
+    let mut revwalk = repository.revwalk().context("Failed to walk the history")?;
+    revwalk
+        .push_head()
+        .context("The repository has no commits")?;
+    revwalk
+        .simplify_first_parent()
+        .context("Failed to walk the history")?;
+    if let Some(since) = &args.since {
+        let commit = repository
+            .revparse_single(since)
+            .and_then(|object| object.peel_to_commit())
+            .with_context(|| format!("Unknown revision: {}", since))?;
+        revwalk
+            .hide(commit.id())
+            .context("Failed to walk the history")?;
+    }
+
+    let tagged: Option<HashSet<Oid>> = if args.tags {
+        let names = repository
+            .tag_names(None)
+            .context("Failed to list the tags")?;
+        Some(
+            names
+                .iter()
+                .flatten()
+                .filter_map(|name| {
+                    let tag = repository.revparse_single(&format!("refs/tags/{}", name));
+                    Some(tag.ok()?.peel_to_commit().ok()?.id())
+                })
+                .collect(),
+        )
+    } else {
+        None
+    };
+
+    let bpe = cl100k_base().context("Failed to get BPE tokenizer")?;
+    let mut cache = TokenCache::load(repository.path());
+
+    if args.format == HistoryFormat::Csv {
+        println!("commit,date,files,tokens");
+    } else {
+        println!(
+            "{:<8} {:<10} {:>8} {:>10}",
+            "commit", "date", "files", "tokens"
+        );
+    }
+
+    let commits = revwalk
+        .filter_map(|oid| oid.ok())
+        .filter(|oid| tagged.as_ref().is_none_or(|tagged| tagged.contains(oid)))
+        .step_by(args.step);
+    for (sampled, oid) in commits.enumerate() {
+        if sampled == args.max_revisions {
+            eprintln!(
+                "Stopped after {} revisions (--max-revisions)",
+                args.max_revisions
+            );
+            break;
+        }
+        let revision = count(
+            &repository,
+            oid,
+            &prefix,
+            args,
+            &ignore_patterns,
+            &mut cache,
+            &bpe,
+        )?;
+        if args.format == HistoryFormat::Csv {
+            println!(
+                "{},{},{},{}",
+                revision.id, revision.date, revision.files, revision.tokens
+            );
+        } else {
+            println!(
+                "{:<8} {:<10} {:>8} {:>10}",
+                revision.id, revision.date, revision.files, revision.tokens
+            );
+        }
+    }
+
+    cache.save()
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+/// Built-in table of file extensions and the language of the files.
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("bash", "bash"),
+    ("c", "c"),
+    ("cc", "cpp"),
+    ("cpp", "cpp"),
+    ("cs", "csharp"),
+    ("css", "css"),
+    ("go", "go"),
+    ("h", "c"),
+    ("hpp", "cpp"),
+    ("html", "html"),
+    ("java", "java"),
+    ("js", "javascript"),
+    ("json", "json"),
+    ("jsx", "javascript"),
+    ("kt", "kotlin"),
+    ("lua", "lua"),
+    ("md", "markdown"),
+    ("php", "php"),
+    ("py", "python"),
+    ("rb", "ruby"),
+    ("rs", "rust"),
+    ("rst", "rst"),
+    ("scala", "scala"),
+    ("sh", "bash"),
+    ("sql", "sql"),
+    ("swift", "swift"),
+    ("toml", "toml"),
+    ("ts", "typescript"),
+    ("tsx", "typescript"),
+    ("txt", "text"),
+    ("xml", "xml"),
+    ("yaml", "yaml"),
+    ("yml", "yaml"),
+    ("zig", "zig"),
+];
+
+/// Detects the language of files from their extension, using the built-in
+/// table extended or overridden by `--lang-map`.
+pub struct Languages {
+    overrides: HashMap<String, String>,
+}
+
+impl Languages {
+    /// Parse mappings of the form `ext=language`.
+    pub fn new(mappings: &[String]) -> Result<Self> {
+        let mut overrides = HashMap::new();
+        for mapping in mappings {
+            let (extension, language) = mapping
+                .split_once('=')
+                .with_context(|| format!("Invalid language mapping: {}", mapping))?;
+            let extension = extension.trim().trim_start_matches('.');
+            let language = language.trim();
+            if extension.is_empty() || language.is_empty() {
+                bail!("Invalid language mapping: {}", mapping);
+            }
+            overrides.insert(extension.to_lowercase(), language.to_string());
+        }
+
+        Ok(Languages { overrides })
+    }
+
+    /// The language of a file, if its extension is known.
+    pub fn detect(&self, path: &Path) -> Option<&str> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        if let Some(language) = self.overrides.get(&extension) {
+            return Some(language);
+        }
+
+        EXTENSIONS
+            .iter()
+            .find(|(known, _)| *known == extension)
+            .map(|(_, language)| *language)
+    }
+
+    /// The language given by its name or by one of its extensions, if known.
+    pub fn resolve(&self, name: &str) -> Option<String> {
+        let name = name.trim().trim_start_matches('.').to_lowercase();
+        if let Some(language) = self.detect(Path::new(&format!("file.{}", name))) {
+            return Some(language.to_string());
+        }
+        EXTENSIONS
+            .iter()
+            .map(|(_, language)| *language)
+            .chain(self.overrides.values().map(String::as_str))
+            .find(|language| language.to_lowercase() == name)
+            .map(str::to_string)
+    }
+}
@@ -0,0 +1,71 @@
+use std::borrow::Cow;
+use std::fs::{self, File, Metadata};
+use std::io;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+/// Length from which Windows refuses paths without the extended-length prefix.
+#[cfg(windows)]
+const MAX_PATH: usize = 260;
+
+/// Windows errors returned for a path too long, or whose directories cannot be
+/// found once it is cut.
+#[cfg(windows)]
+const PATH_ERRORS: [i32; 2] = [3, 206];
+
+/// The path to open a file with. On Windows, long absolute paths get the
+/// extended-length prefix `\\?\`, which lifts the MAX_PATH limit.
+pub fn extended(path: &Path) -> Cow<'_, Path> {
+    #[cfg(windows)]
+    if let Some(text) = path.to_str() {
+        if path.is_absolute() && text.len() >= MAX_PATH && !text.starts_with(r"\\?\") {
+            // The prefix turns off the translation of forward slashes, and
+            // network shares take the \\?\UNC\ form
+            let text = text.replace('/', r"\");
+            let extended = match text.strip_prefix(r"\\") {
+                Some(share) => format!(r"\\?\UNC\{}", share),
+                None => format!(r"\\?\{}", text),
+            };
+            return Cow::Owned(extended.into());
+        }
+    }
+
+    Cow::Borrowed(path)
+}
+
+/// Explain an error of the filesystem caused by the length of the path, as
+/// Windows reports it as a missing path.
+fn hint(path: &Path, error: io::Error) -> anyhow::Error {
+    #[cfg(windows)]
+    if path.as_os_str().len() >= MAX_PATH
+        && error
+            .raw_os_error()
+            .is_some_and(|code| PATH_ERRORS.contains(&code))
+    {
+        return anyhow::Error::new(error).context(format!(
+            "The path is longer than the {} characters Windows allows, enable long paths \
+             (LongPathsEnabled) or move the directory closer to the root of the drive",
+            MAX_PATH
+        ));
+    }
+
+    #[cfg(not(windows))]
+    let _ = path;
+
+    anyhow::Error::new(error)
+}
+
+/// Open a file for reading, whatever the length of its path.
+pub fn open(path: &Path) -> Result<File> {
+    File::open(extended(path))
+        .map_err(|e| hint(path, e))
+        .with_context(|| format!("Failed to open file: {}", path.display()))
+}
+
+/// Get the metadata of a file, whatever the length of its path.
+pub fn metadata(path: &Path) -> Result<Metadata> {
+    fs::metadata(extended(path))
+        .map_err(|e| hint(path, e))
+        .with_context(|| format!("Failed to get metadata for file: {}", path.display()))
+}
@@ -1,55 +1,662 @@
-use std::ffi::OsStr;
-use std::fs::{metadata, File};
-use std::io::Read;
-use std::path::{Path, PathBuf};
-use std::time::Instant;
-
-use anyhow::{Context, Result};
-use clap::Parser;
-use ignore::WalkBuilder;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
 use tiktoken_rs::{cl100k_base, CoreBPE};
 
+mod ansi;
+mod budget;
+mod cache;
+mod chat;
+mod chunk;
+mod codegen;
+mod collision;
+mod command;
+mod data;
+mod deps;
+mod diagnostics;
+#[cfg(feature = "docx")]
+mod docx;
+mod encoded;
+mod explain;
+mod filter;
+mod git;
+mod graph;
+mod histogram;
+mod history;
+mod language;
+mod longpath;
+mod manifest;
+mod merge;
+mod owners;
+mod pack;
+mod plan;
+mod progress;
+mod prompt;
+mod redact;
+mod report;
+mod select;
+mod snapshot;
+mod source;
+mod state;
+mod timings;
+mod transform;
+mod tree;
+mod verify;
+mod workspace;
+mod yaml;
+
+use budget::Budgets;
+use cache::{CachedOutput, OutputCache};
+use chunk::Chunk;
+use codegen::Codegen;
+use data::DataThresholds;
+use diagnostics::{Destination, Diagnostics};
+use filter::{Decision, Limits, SkipReason, Totals};
+use language::Languages;
+use manifest::{FileHash, HashManifest};
+use owners::CodeOwners;
+use pack::{Framer, Section};
+use plan::Plan;
+use progress::Progress;
+use redact::Redactor;
+use report::{JsonTree, ReplayOptions, Report, ReportFile, TreeEntry};
+use select::{Priority, Slice, SortKey};
+use snapshot::Snapshot;
+use source::{Candidate, Deadline, ListFormat, WalkOptions};
+use state::State;
+use timings::{Phase, Timings};
+use transform::Transforms;
+use tree::{RenderOptions, Tree, TreeSort, Weight};
+
 /// Default values for the limits.
 const DEFAULT_MAX_FILE_SIZE: u64 = 1024 * 1024; // 1MB
 const DEFAULT_MAX_TOTAL_SIZE: u64 = 1024 * 1024 * 100; // 100MB
 const DEFAULT_MAX_FILES: usize = 10000;
 
+/// Size of the output on stdout above which clipboards and paste services may
+/// truncate it.
+const LARGE_OUTPUT_BYTES: u64 = 1024 * 1024 * 10; // 10MB
+
+/// Files read ahead by each thread reading files, bounding the content held in
+/// memory before the limits apply to it.
+const PROBES_PER_THREAD: usize = 16;
+
+/// Line framing the headers of the output.
+const SEPARATOR: &str = "==================================================";
+
+/// Header of the block of files merged by `--merge-small-files`.
+const SMALL_FILES: &str = "Small files";
+
 #[derive(Parser, Debug)]
-#[command(author, version, about = "Feed your codebase into any LLM.")]
+#[command(
+    author,
+    version,
+    about = "Feed your codebase into any LLM.",
+    args_conflicts_with_subcommands = true
+)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Output the report.
     #[arg(short, long)]
     report: bool,
+    /// Print a one-line summary of the files, size and tokens, for a badge or a CI comment.
+    #[arg(long = "report-line")]
+    report_line: bool,
+    /// Write a JSON report listing the files included and skipped.
+    #[arg(long = "report-json", value_name = "FILE")]
+    report_json: Option<PathBuf>,
+    /// Forms of the tree held by the JSON report: nested entries, the text written, both or none.
+    #[arg(
+        long = "json-tree",
+        value_enum,
+        value_name = "FORM",
+        default_value_t = JsonTree::Object,
+        requires = "report_json"
+    )]
+    json_tree: JsonTree,
+    /// Write a JSON manifest of the hash of each file included and of the whole output, to check later that a dump matches.
+    #[arg(long = "content-hash-manifest", value_name = "FILE")]
+    content_hash_manifest: Option<PathBuf>,
+    /// Compare the files included with a manifest written by --content-hash-manifest, listing those that drifted and failing if any did.
+    #[arg(long = "compare-manifest", value_name = "FILE")]
+    compare_manifest: Option<PathBuf>,
+    /// Where to print the skipped files and warnings: stderr, stdout or file:PATH.
+    #[arg(long, value_name = "WHERE", default_value = "stderr")]
+    errors: Destination,
+    /// List every skipped file instead of a few examples per reason.
+    #[arg(short, long)]
+    verbose: bool,
+    /// Write the output to a file instead of stdout (can be repeated, once for each --format).
+    #[arg(short, long, value_name = "FILE")]
+    output: Vec<PathBuf>,
+    /// Also write a ready-to-send prompt holding the tree and the files to a file.
+    #[arg(long = "prompt-file", value_name = "FILE")]
+    prompt_file: Option<PathBuf>,
+    /// Template of the prompt written by --prompt-file, with {{directory}}, {{summary}}, {{tree}} and {{files}} placeholders.
+    #[arg(
+        long = "prompt-template",
+        value_name = "FILE",
+        requires = "prompt_file"
+    )]
+    prompt_template: Option<PathBuf>,
+    /// Append to the output file instead of overwriting it.
+    #[arg(long, requires = "output")]
+    append: bool,
+    /// Leave out the commit message and the tree from the appended section.
+    #[arg(long = "append-minimal", requires = "append")]
+    append_minimal: bool,
+    /// Rewrite the output file, refusing to if it was not written by llmr or was edited since, and report the files that changed since.
+    #[arg(long, requires = "output", conflicts_with = "append")]
+    update: bool,
+    /// Write the output of the last run again without reading the files when none changed since, as told by their size and modification time.
+    #[arg(
+        long = "resume-from-cache",
+        conflicts_with_all = ["append", "update", "dry_run", "state", "report", "report_json", "prompt_file", "git_ref", "stream_over", "content_hash_manifest", "compare_manifest", "dump_tree_sizes_only", "tree_depth_colors", "filter_command", "include_deps"]
+    )]
+    resume_from_cache: bool,
+    /// List the files that would be included, with their size and projected tokens, without reading them.
+    #[arg(
+        long = "dry-run",
+        conflicts_with_all = ["append", "update", "state", "report_json", "prompt_file", "dump_tree_sizes_only"]
+    )]
+    dry_run: bool,
+    /// Format of the output, text unless given (can be repeated, each format going to the --output at the same position).
+    #[arg(long, value_enum)]
+    format: Vec<OutputFormat>,
+    /// Preamble sent as the system message by --format chat.
+    #[arg(long, value_name = "TEXT")]
+    prompt: Option<String>,
+    /// Instructions sent as a last user message by --format chat.
+    #[arg(long, value_name = "TEXT")]
+    instructions: Option<String>,
+    /// Send each file in its own user message with --format chat.
+    #[arg(long = "chat-split-files")]
+    chat_split_files: bool,
     /// Maximum file size to process (in bytes).
     #[arg(short = 'f', long = "file-size", default_value_t = DEFAULT_MAX_FILE_SIZE)]
     max_file_size: u64,
+    /// Copy text files larger than BYTES from disk to the output as it is written, instead of reading them into memory, leaving them untransformed and their tokens uncounted.
+    #[arg(
+        long = "stream-over",
+        value_name = "BYTES",
+        conflicts_with_all = ["update", "prompt_file", "max_output_bytes", "max_tokens", "dir_budget", "lang_budget", "chunk_tokens", "merge_small_files", "omit_content_over", "content_hash_manifest", "compare_manifest", "preview_lines"]
+    )]
+    stream_over: Option<u64>,
     /// Maximum total size of files to process (in bytes).
     #[arg(short = 't', long = "total-size", default_value_t = DEFAULT_MAX_TOTAL_SIZE)]
     max_total_size: u64,
     /// Maximum number of files to process.
     #[arg(short = 'n', long = "num-files", default_value_t = DEFAULT_MAX_FILES)]
     max_files: usize,
+    /// Maximum number of files to process with the same extension.
+    #[arg(long = "max-files-per-ext", value_name = "N")]
+    max_files_per_extension: Option<usize>,
+    /// Skip the .json, .jsonl and .geojson files larger than BYTES, such as fixtures and snapshots.
+    #[arg(long = "exclude-large-json", value_name = "BYTES")]
+    exclude_large_json: Option<u64>,
+    /// Explain why a path is included or excluded (can be repeated).
+    #[arg(long = "explain", value_name = "PATH")]
+    explain: Vec<PathBuf>,
+    /// Explain the inclusion decision for every file found.
+    #[arg(long = "explain-all")]
+    explain_all: bool,
+    /// Skip JSON and YAML files that look like generated data, such as large arrays or highly repetitive keys.
+    #[arg(long = "skip-data-files")]
+    skip_data_files: bool,
+    /// Average number of occurrences of each key above which --skip-data-files skips a file.
+    #[arg(
+        long = "data-key-repetition",
+        value_name = "RATIO",
+        default_value_t = 20.0
+    )]
+    data_key_repetition: f64,
+    /// Number of elements of a top-level array above which --skip-data-files skips a file.
+    #[arg(long = "data-array-length", value_name = "N", default_value_t = 1000)]
+    data_array_length: usize,
+    /// Skip generated code, such as protobuf or OpenAPI stubs, found from its path or a marker at the top of the file.
+    #[arg(long = "skip-codegen")]
+    skip_codegen: bool,
+    /// Glob of the paths --skip-codegen skips, replacing the default ones (can be repeated).
+    #[arg(
+        long = "codegen-pattern",
+        value_name = "GLOB",
+        requires = "skip_codegen"
+    )]
+    codegen_patterns: Vec<String>,
+    /// Leave out the files whose content is the same as that of a file kept, such as copied configurations or symbolic links to a file.
+    #[arg(long = "exclude-symlinked-dupes")]
+    exclude_duplicates: bool,
+    /// Skip text files made mostly of base64 or hex data, such as embedded images or dumps, which take far more tokens than their size suggests.
+    #[arg(long = "skip-blobs")]
+    skip_blobs: bool,
+    /// Read the files to process from a list instead of walking the current directory ("-" for stdin).
+    #[arg(long = "files-from", value_name = "LIST")]
+    files_from: Option<PathBuf>,
+    /// Format of the list given to --files-from.
+    #[arg(
+        long = "files-from-format",
+        alias = "input-list-format",
+        value_enum,
+        default_value_t = ListFormat::Auto
+    )]
+    files_from_format: ListFormat,
+    /// Process the files included by the run that wrote a JSON report, ignoring files that appeared since.
+    #[arg(long, value_name = "REPORT", conflicts_with_all = ["files_from", "workspace"])]
+    replay: Option<PathBuf>,
+    /// Fail if files of the replayed report no longer exist or new files appeared.
+    #[arg(long = "replay-strict", requires = "replay")]
+    replay_strict: bool,
+    /// Also process the files that appeared since the replayed report.
+    #[arg(long = "replay-additions", requires = "replay")]
+    replay_additions: bool,
+    /// Read the files from a git revision, such as a branch, a tag or a commit, instead of the working tree.
+    #[arg(
+        long = "ref",
+        value_name = "REV",
+        conflicts_with_all = ["files_from", "replay", "workspace", "packages", "include", "tree_includes_ignored", "recent"]
+    )]
+    git_ref: Option<String>,
+    /// Ignore paths matching a gitignore-style pattern (can be repeated, "!" re-includes).
+    #[arg(
+        long = "ignore-pattern",
+        value_name = "PATTERN",
+        allow_hyphen_values = true
+    )]
+    ignore_patterns: Vec<String>,
+    /// Skip the directories with this exact name, case-sensitive, at any depth without walking them (can be repeated).
+    #[arg(long = "exclude-dir", value_name = "NAME")]
+    exclude_dirs: Vec<String>,
+    /// Show the paths relative to the root of the enclosing git repository, while only walking the current directory.
+    #[arg(long = "root-from-git")]
+    root_from_git: bool,
+    /// Show the paths relative to a directory, while only walking the current directory.
+    #[arg(
+        long = "relative-to",
+        value_name = "DIR",
+        conflicts_with = "root_from_git"
+    )]
+    relative_to: Option<PathBuf>,
+    /// Write a summary of the dependencies declared by the Cargo.toml, package.json, pyproject.toml and go.mod files after the tree, even if the manifests are not included.
+    #[arg(long = "include-deps")]
+    include_deps: bool,
+    /// Write the imports between the Rust, Python, JavaScript and TypeScript files included after the tree, listing those each file imports (up to 200 files).
+    #[arg(long = "dep-graph")]
+    dep_graph: bool,
+    /// Also walk the other members and the local path dependencies of the workspace.
+    #[arg(long, conflicts_with = "files_from")]
+    workspace: bool,
+    /// Show the files of a root walked with --workspace, or of the current directory, under PREFIX instead of their relative path, given as ROOT=PREFIX (can be repeated).
+    #[arg(
+        long = "path-prefix",
+        value_name = "ROOT=PREFIX",
+        requires = "workspace"
+    )]
+    path_prefixes: Vec<String>,
+    /// Only process the package with this name, found from the Cargo.toml and package.json files (can be repeated).
+    #[arg(long = "package", value_name = "NAME")]
+    packages: Vec<String>,
+    /// Also process the packages the selected packages depend on.
+    #[arg(long = "package-deps", requires = "packages")]
+    package_deps: bool,
+    /// Only process the files owned by an owner in the CODEOWNERS file of the repository (can be repeated).
+    #[arg(long, value_name = "OWNER")]
+    owner: Vec<String>,
+    /// Only process the files that have no owner in the CODEOWNERS file of the repository.
+    #[arg(long = "owner-unowned", conflicts_with = "owner")]
+    owner_unowned: bool,
+    /// Only process the files changed between two revisions, given as REV1..REV2, or REV1...REV2 to diff from their common ancestor.
+    #[arg(
+        long = "diff-range",
+        alias = "only-changed-in-range",
+        value_name = "RANGE"
+    )]
+    diff_range: Option<String>,
+    /// Walk into symbolic links to directories, and show files reached through several paths only once.
+    #[arg(long = "follow-symlinks", conflicts_with = "git_ref")]
+    follow_symlinks: bool,
+    /// Include paths matching a glob even if hidden or ignored (can be repeated).
+    #[arg(long = "include", value_name = "GLOB")]
+    include: Vec<String>,
+    /// Do not read the `include` and `exclude` files of the `.llmr` directory.
+    #[arg(long = "no-llmr-dir")]
+    no_llmr_dir: bool,
+    /// Do not apply `.llmrignore` files.
+    #[arg(long = "no-llmrignore")]
+    no_llmrignore: bool,
+    /// Do not apply `.ignore` files.
+    #[arg(long = "no-dot-ignore")]
+    no_dot_ignore: bool,
+    /// Do not apply `.gitignore` files and the repository's `.git/info/exclude`.
+    #[arg(long = "no-gitignore")]
+    no_gitignore: bool,
+    /// Do not apply the user's global git excludes file (core.excludesFile).
+    #[arg(long = "no-global-gitignore")]
+    no_global_gitignore: bool,
+    /// Process files matching a glob first, in the order given (can be repeated).
+    #[arg(long, value_name = "GLOB")]
+    priority: Vec<String>,
+    /// Maximum number of tokens of the file contents, dropping files that match no priority glob from the end.
+    #[arg(long = "max-tokens", alias = "max-total-tokens", value_name = "TOKENS")]
+    max_tokens: Option<usize>,
+    /// Write the output in parts of --max-tokens across runs, recording the files sent in a state file to continue from.
+    #[arg(long, value_name = "FILE", requires = "max_tokens")]
+    state: Option<PathBuf>,
+    /// Start over from the first part, ignoring the files recorded in the --state file.
+    #[arg(long = "state-reset", requires = "state")]
+    state_reset: bool,
+    /// Maximum number of tokens of the files below a directory, dropping the files that do not fit (can be repeated).
+    #[arg(long = "dir-budget", value_name = "PATH=TOKENS")]
+    dir_budget: Vec<String>,
+    /// Maximum number of tokens of the files of a language, given by name or extension, dropping the files that do not fit (can be repeated or comma-separated).
+    #[arg(
+        long = "lang-budget",
+        alias = "token-limit-per-language",
+        value_name = "LANG=TOKENS",
+        value_delimiter = ','
+    )]
+    lang_budget: Vec<String>,
+    /// Maximum number of bytes written, including the headers and the tree, dropping the files that do not fit from the end.
+    #[arg(long = "max-output-bytes", value_name = "BYTES")]
+    max_output_bytes: Option<u64>,
+    /// Only show the first N lines of every file, followed by a marker, for an overview of all the files at a fraction of their tokens.
+    #[arg(long = "preview-lines", value_name = "N")]
+    preview_lines: Option<usize>,
+    /// Replace the content of files over a number of tokens by their first lines.
+    #[arg(long = "omit-content-over", value_name = "TOKENS")]
+    omit_content_over: Option<usize>,
+    /// Do not apply ignore files from the directories above the current directory.
+    #[arg(long = "no-parent-ignore")]
+    no_parent_ignore: bool,
+    /// Only apply `.gitignore` files and git's excludes inside a git repository.
+    #[arg(long = "require-git")]
+    require_git: bool,
+    /// Print the ignore sources that apply, from the one that decides first, and exit.
+    #[arg(long = "show-config")]
+    show_config: bool,
+    /// Order in which files are processed and emitted.
+    #[arg(long, value_enum, default_value_t = SortKey::Walk)]
+    sort: SortKey,
+    /// Reverse the order given by --sort, such as the largest files first with --sort size.
+    #[arg(long, alias = "sort-desc")]
+    desc: bool,
+    /// Start at the file with this path, such as src/lib.rs, skipping the files sorted before it, to process a large repository in slices across runs.
+    #[arg(long = "start-at", value_name = "PATH")]
+    start_at: Option<PathBuf>,
+    /// Only process the first N files in sort order.
+    #[arg(long = "head-files", value_name = "N", conflicts_with = "tail_files")]
+    head_files: Option<usize>,
+    /// Only process the last N files in sort order.
+    #[arg(long = "tail-files", value_name = "N")]
+    tail_files: Option<usize>,
+    /// Only process the N most recently modified files, as with --sort mtime --tail-files N.
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["sort", "desc", "head_files", "tail_files"]
+    )]
+    recent: Option<usize>,
+    /// Include the text of Word documents (.docx), converted to Markdown.
+    #[cfg(feature = "docx")]
+    #[arg(long = "extract-docx")]
+    extract_docx: bool,
+    /// Remove the indentation shared by all the lines of each file.
+    #[arg(long)]
+    dedent: bool,
+    /// Remove the comment block holding the license (SPDX tag, copyright) at the start of each file.
+    #[arg(long = "strip-license-headers")]
+    strip_license_headers: bool,
+    /// Remove the null, empty array and empty object fields of .json files, written back compactly.
+    #[arg(long = "compact-json", alias = "strip-empty-json-fields")]
+    compact_json: bool,
+    /// Remove the blank lines at the start and the end of each file.
+    #[arg(long = "strip-empty-lines-at-bounds")]
+    strip_empty_lines_at_bounds: bool,
+    /// Wrap the lines of prose files (.md, .txt, .rst) longer than WIDTH.
+    #[arg(long, alias = "wrap-width", value_name = "WIDTH")]
+    reflow: Option<usize>,
+    /// Emit the content of each file exactly, instead of removing its trailing whitespace.
+    #[arg(long = "no-trim")]
+    no_trim: bool,
+    /// Fail on the first text file that is not valid UTF-8, telling the offset of its first invalid byte, instead of skipping it.
+    #[arg(long = "verify-utf8")]
+    verify_utf8: bool,
+    /// Keep the UTF-8 byte-order mark starting a file, which is removed by default.
+    #[arg(long = "preserve-bom")]
+    preserve_bom: bool,
+    /// Pipe the content of each file through a shell command, with the path in LLMR_FILE, keeping it if the command fails.
+    #[arg(long = "filter-cmd", value_name = "COMMAND")]
+    filter_command: Option<String>,
+    /// Show only file names in the headers and leave out the tree.
+    #[arg(long)]
+    flatten: bool,
+    /// Replace the names of the files and directories matching a regex by placeholders wherever paths are shown, still reading the files (can be repeated).
+    #[arg(long = "redact-paths-matching", value_name = "REGEX")]
+    redact_paths_matching: Vec<String>,
+    /// Print the tree to stderr, leaving only the file contents on stdout.
+    #[arg(long = "tree-to-stderr")]
+    tree_to_stderr: bool,
+    /// Show the paths ignored by git or --exclude-dir in the tree, marked [ignored], without their content.
+    #[arg(long = "tree-includes-ignored", conflicts_with = "files_from")]
+    tree_includes_ignored: bool,
+    /// Gather the files smaller than BYTES in a single block, each under a short sub-header instead of a full header.
+    #[arg(
+        long = "merge-small-files",
+        value_name = "BYTES",
+        conflicts_with_all = ["file_index", "update"]
+    )]
+    merge_small_files: Option<u64>,
+    /// Write the files in chunks of at most N tokens, each under a header telling the tokens of the file it holds.
+    #[arg(
+        long = "chunk-tokens",
+        value_name = "N",
+        conflicts_with_all = ["file_index", "update", "merge_small_files"]
+    )]
+    chunk_tokens: Option<usize>,
+    /// Start each chunk of a file with the last N tokens of the previous one.
+    #[arg(
+        long = "split-tokens-overlap",
+        value_name = "N",
+        requires = "chunk_tokens"
+    )]
+    split_tokens_overlap: Option<usize>,
+    /// Number the files, list them with their tokens after the tree, and show their number in the headers.
+    #[arg(long = "file-index")]
+    file_index: bool,
+    /// Write the time of the run, and end the header of each file, chunk and commit message with the number of lines of its content and the start of their SHA-256, for llmr verify and llmr merge.
+    #[arg(long = "pack-metadata")]
+    pack_metadata: bool,
+    /// Gather the files by language, each language under its own header, keeping the sort order within them.
+    #[arg(long = "group-by-language", conflicts_with = "merge_small_files")]
+    group_by_language: bool,
+    /// Shade the entries of the tree by depth when it is shown in a terminal, leaving the files written plain (disabled by NO_COLOR).
+    #[arg(long = "tree-depth-colors")]
+    tree_depth_colors: bool,
+    /// Show the number of lines of each file and directory in the tree.
+    #[arg(long = "annotate-line-count")]
+    annotate_line_count: bool,
+    /// Only print the tree, with the size and the tokens of each file and directory, for an audit of where the weight is.
+    #[arg(
+        long = "dump-tree-sizes-only",
+        conflicts_with_all = ["output", "format", "report", "report_json", "prompt_file", "flatten"]
+    )]
+    dump_tree_sizes_only: bool,
+    /// List the non-text files with their size after the files, instead of in the tree.
+    #[arg(long = "list-binaries", alias = "include-binary-names-only")]
+    list_binaries: bool,
+    /// Collapse runs of at least MIN consecutive non-text files in a directory into a single tree entry.
+    #[arg(
+        long = "collapse-binaries",
+        value_name = "MIN",
+        num_args = 0..=1,
+        default_missing_value = "2"
+    )]
+    collapse_binaries: Option<usize>,
+    /// Show at most N entries in the tree, counting the others in a last line, whatever the files included.
+    #[arg(long = "max-tree-entries", value_name = "N")]
+    max_tree_entries: Option<usize>,
+    /// Order of the entries of the tree among their siblings: as found, by name, or the heaviest directories first by tokens.
+    #[arg(long = "tree-sort", value_enum, value_name = "ORDER", default_value_t = TreeSort::Walk)]
+    tree_sort: TreeSort,
+    /// Show the root of the tree as an absolute path instead of `.`, for `llmr merge` to place the files of outputs written from different directories.
+    #[arg(long = "absolute-root")]
+    absolute_root: bool,
+    /// Print the message of the HEAD commit before the tree.
+    #[arg(long = "include-commit-message")]
+    include_commit_message: bool,
+    /// Print the subject lines of the last N commits after the files.
+    #[arg(long = "include-git-log", value_name = "N")]
+    include_git_log: Option<usize>,
+    /// Stop walking after a duration, such as 30s or 2m, and process the files found until then.
+    #[arg(long, value_name = "DURATION")]
+    timeout: Option<String>,
+    /// Number of threads reading files and counting tokens (defaults to the number of CPUs).
+    #[arg(short = 'j', long)]
+    jobs: Option<usize>,
+    /// Number of threads reading files, fewer suiting slow or network filesystems (defaults to --jobs).
+    #[arg(long = "io-jobs", alias = "max-concurrency-io", value_name = "JOBS")]
+    io_jobs: Option<usize>,
+    /// Number of threads counting tokens (defaults to --jobs).
+    #[arg(long = "cpu-jobs", value_name = "JOBS")]
+    cpu_jobs: Option<usize>,
+    /// Print a histogram of the number of files by tokens in the report, in buckets doubling in width.
+    #[arg(long = "token-histogram", requires = "report")]
+    token_histogram: bool,
+    /// Print a table of the files, lines, size and tokens of each language in the report.
+    #[arg(long = "languages-report", requires = "report")]
+    languages_report: bool,
+    /// How sizes are rendered in the report.
+    #[arg(long = "size-format", value_enum, default_value_t = SizeFormat::Human)]
+    size_format: SizeFormat,
+    /// Map a file extension to a language, overriding the built-in detection (can be repeated).
+    #[arg(long = "lang-map", value_name = "EXT=LANGUAGE")]
+    lang_map: Vec<String>,
+    /// Print the time spent in each phase of the run to stderr.
+    #[arg(long, conflicts_with = "no_timing")]
+    timings: bool,
+    /// Leave out the time elapsed from the report, so that it is the same across runs.
+    #[arg(
+        long = "no-timing",
+        alias = "deterministic-timing",
+        requires = "report"
+    )]
+    no_timing: bool,
+    /// Do not show the number of files whose tokens are counted on a terminal.
+    #[arg(long = "no-progress")]
+    no_progress: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Merge files written by llmr into one, with a single tree.
+    Merge(merge::MergeArgs),
+    /// Count the files and tokens of past revisions, read from the git history.
+    History(history::HistoryArgs),
+    /// Check that a file written by llmr is intact, exiting with 1 if it has
+    /// problems and 2 if it cannot be read.
+    Verify(verify::VerifyArgs),
+}
+
+/// The slice of the files written in a part with `--state`.
+struct Part {
+    /// Number of the part, from 1.
+    number: usize,
+    /// Files sent unchanged in the previous parts.
+    sent: usize,
+    /// Files sent again as they changed since.
+    changed: usize,
+    /// Files of this part.
+    kept: usize,
+    /// Files to send across all the parts.
+    total: usize,
+}
+
+/// A file whose content is included in the output.
+struct FileEntry {
+    /// Index of the candidate the file comes from.
+    source: usize,
+    /// Path shown in the file header.
+    display: PathBuf,
+    /// Short tag telling apart headers that would otherwise collide.
+    tag: Option<String>,
+    /// Size of the file on disk, in bytes.
+    size: u64,
+    /// Number of tokens of the content, once counted.
+    tokens: Option<usize>,
+    content: String,
+    /// Indentation removed from each line of the content by `--dedent`.
+    indent: String,
+    /// License header removed from the content by `--strip-license-headers`.
+    license: String,
+    /// Whether the content is left on disk, and copied to the output as it is
+    /// written, with `--stream-over`.
+    streamed: bool,
 }
 
 /// Check if a file is likely a text file.
 fn is_text_file(path: &Path) -> Result<bool> {
-    let mut file =
-        File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
+    let mut file = longpath::open(path)?;
     let mut buffer = [0u8; 1024];
 
     let bytes_read = file
         .read(&mut buffer)
         .with_context(|| format!("Failed to read file: {}", path.display()))?;
 
-    Ok(!buffer[..bytes_read]
+    Ok(is_text(&buffer[..bytes_read]))
+}
+
+/// Check if the first bytes of a file hold no control characters other than
+/// whitespace.
+fn is_text(bytes: &[u8]) -> bool {
+    !bytes
         .iter()
-        .any(|&byte| byte < 0x20 && byte != 0x09 && byte != 0x0a && byte != 0x0d))
+        .any(|&byte| byte < 0x20 && byte != 0x09 && byte != 0x0a && byte != 0x0d)
+}
+
+/// Find the offset of the first byte of a file that is not valid UTF-8, if
+/// any, reading it in blocks.
+fn first_invalid_utf8(path: &Path) -> Result<Option<usize>> {
+    let mut file = longpath::open(path)?;
+    let mut buffer = vec![0u8; 64 * 1024];
+    // Offset of the start of the buffer in the file, and bytes of a sequence
+    // cut by the end of the previous block, kept at the start of the buffer
+    let mut offset = 0;
+    let mut pending = 0;
+    loop {
+        let read = file
+            .read(&mut buffer[pending..])
+            .with_context(|| format!("Failed to read file: {}", path.display()))?;
+        if read == 0 {
+            return Ok((pending > 0).then_some(offset));
+        }
+        let end = pending + read;
+        match std::str::from_utf8(&buffer[..end]) {
+            Ok(_) => {
+                offset += end;
+                pending = 0;
+            }
+            Err(error) if error.error_len().is_none() => {
+                let valid = error.valid_up_to();
+                buffer.copy_within(valid..end, 0);
+                offset += valid;
+                pending = end - valid;
+            }
+            Err(error) => return Ok(Some(offset + error.valid_up_to())),
+        }
+    }
 }
 
 /// Read the content of a file.
 fn read_file_content(path: &Path) -> Result<String> {
-    let mut file =
-        File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
+    let mut file = longpath::open(path)?;
     let mut content = String::new();
 
     file.read_to_string(&mut content)
@@ -58,170 +665,2497 @@ fn read_file_content(path: &Path) -> Result<String> {
     Ok(content)
 }
 
-/// Helper function to format size in bytes, KB, MB.
+/// Extract the text of a document in a binary format, when asked to.
+fn extract_text(path: &Path, extract_docx: bool) -> Option<String> {
+    #[cfg(feature = "docx")]
+    if extract_docx && docx::is_docx(path) {
+        return docx::extract(path).ok();
+    }
+
+    #[cfg(not(feature = "docx"))]
+    let _ = (path, extract_docx);
+
+    None
+}
+
+/// What is known about a file before it runs through the filtering pipeline.
+struct Probe {
+    size: u64,
+    is_text: Result<bool>,
+    /// Content of the file, read up front when it is text and within the size limit.
+    content: Option<Result<String>>,
+    /// Indentation removed from each line of the content by `--dedent`.
+    indent: String,
+    /// License header removed from the content by `--strip-license-headers`.
+    license: String,
+    /// Why the file looks like generated data or code, with `--skip-data-files`,
+    /// `--skip-codegen` or `--skip-blobs`.
+    generated: Option<SkipReason>,
+    /// Why the content could not be filtered by `--filter-cmd`.
+    warning: Option<String>,
+    /// Whether the content is left on disk, to be copied to the output.
+    streamed: bool,
+    timings: Timings,
+}
+
+/// How files are probed, the same for every file.
+struct ProbeOptions<'a> {
+    max_file_size: u64,
+    extract_docx: bool,
+    transforms: Transforms,
+    /// Shell command given with `--filter-cmd`, if any.
+    filter_command: Option<String>,
+    /// Thresholds of `--skip-data-files`, if enabled.
+    data_thresholds: Option<DataThresholds>,
+    /// Generated code recognized by `--skip-codegen`, if enabled.
+    codegen: Option<Codegen>,
+    /// Whether binary data encoded as text is skipped, with `--skip-blobs`.
+    skip_blobs: bool,
+    /// Size above which the content of files is copied to the output instead
+    /// of read, with `--stream-over`.
+    stream_over: Option<u64>,
+    /// Whether a text file that is not valid UTF-8 fails the run, with
+    /// `--verify-utf8`.
+    verify_utf8: bool,
+    /// The revision the files are read from with `--ref`.
+    snapshot: Option<&'a Snapshot>,
+}
+
+/// Gather the size, kind and content of a file, independently of the other files.
+fn probe_file(candidate: &Candidate, options: &ProbeOptions) -> Result<Probe> {
+    let path = candidate.path.as_path();
+    let blob = options.snapshot.and_then(|snapshot| snapshot.file(path));
+    let size = match blob {
+        Some(blob) => blob.size,
+        None => longpath::metadata(path)?.len(),
+    };
+
+    let mut timings = Timings::default();
+    let mut indent = String::new();
+    let mut license = String::new();
+    let mut generated = None;
+    let mut warning = None;
+
+    // Documents in binary formats are probed as their extracted text, and as
+    // non-text files when the extraction fails. Files of a revision are probed
+    // as their blob, read up front
+    let extracted = match blob {
+        Some(blob) => blob.content.clone(),
+        None => timings.time(Phase::Read, || extract_text(path, options.extract_docx)),
+    };
+    let (size, is_text) = match (blob, &extracted) {
+        (Some(blob), _) => (size, Ok(blob.is_text)),
+        (None, Some(text)) => (text.len() as u64, Ok(true)),
+        (None, None) => (size, timings.time(Phase::Detect, || is_text_file(path))),
+    };
+
+    // Files on disk larger than the threshold are copied to the output as it is
+    // written, whole and untransformed, instead of read into memory
+    let streamed = blob.is_none()
+        && extracted.is_none()
+        && candidate.range.is_none()
+        && matches!(is_text, Ok(true))
+        && size <= options.max_file_size
+        && options
+            .stream_over
+            .is_some_and(|threshold| size > threshold);
+
+    // Text files that are not valid UTF-8 fail the run when asked, instead of
+    // being skipped or, for those of a revision, read with replacements
+    if options.verify_utf8 && matches!(is_text, Ok(true)) && size <= options.max_file_size {
+        let invalid = match blob {
+            Some(blob) => blob.invalid_utf8,
+            None if extracted.is_none() => first_invalid_utf8(path)?,
+            None => None,
+        };
+        if let Some(offset) = invalid {
+            bail!(
+                "Invalid UTF-8 in {}: first invalid byte at offset {}",
+                path.display(),
+                offset
+            );
+        }
+    }
+
+    let content = match is_text {
+        Ok(true) if streamed => Some(Ok(String::new())),
+        Ok(true) if size <= options.max_file_size => {
+            let content = match extracted {
+                Some(text) => Ok(text),
+                None => timings.time(Phase::Read, || read_file_content(path)),
+            };
+            Some(content.map(|content| {
+                timings.time(Phase::Transform, || {
+                    let content = options.transforms.strip_bom(content);
+                    let mut content = match candidate.range {
+                        Some(range) => range.apply(&content),
+                        None => content,
+                    };
+                    if let Some(filter) = &options.filter_command {
+                        match command::pipe(filter, path, &content) {
+                            Ok(filtered) => content = filtered,
+                            Err(e) => {
+                                warning = Some(format!(
+                                    "Warning: --filter-cmd failed on {}, keeping its content: {:#}",
+                                    path.display(),
+                                    e
+                                ))
+                            }
+                        }
+                    }
+                    if let Some(thresholds) = &options.data_thresholds {
+                        generated =
+                            data::data_like(path, &content, thresholds).map(SkipReason::DataFile);
+                    }
+                    if let Some(codegen) = options.codegen.as_ref().filter(|_| generated.is_none())
+                    {
+                        generated = codegen
+                            .detect(&candidate.display, &content)
+                            .map(SkipReason::Codegen);
+                    }
+                    if options.skip_blobs && generated.is_none() {
+                        generated = encoded::encoded_like(&content).map(SkipReason::Encoded);
+                    }
+                    let (content, header) = options.transforms.strip_license_header(path, content);
+                    license = header;
+                    let (content, removed) = options.transforms.dedent(content);
+                    indent = removed;
+                    options.transforms.apply(path, content)
+                })
+            }))
+        }
+        _ => None,
+    };
+
+    Ok(Probe {
+        size,
+        is_text,
+        content,
+        indent,
+        license,
+        generated,
+        warning,
+        streamed,
+        timings,
+    })
+}
+
+/// Helper function to format size in bytes, KB, MB, GB.
 fn format_size(size: u64) -> String {
     if size < 1024 {
         format!("{} bytes", size)
     } else if size < 1024 * 1024 {
         format!("{:.2} KB", size as f64 / 1024.0)
-    } else {
+    } else if size < 1024 * 1024 * 1024 {
         format!("{:.2} MB", size as f64 / (1024.0 * 1024.0))
+    } else {
+        format!("{:.2} GB", size as f64 / (1024.0 * 1024.0 * 1024.0))
+    }
+}
+
+/// Shorten a number to its leading digits and a suffix of its magnitude, such
+/// as "1.4M" or "402k", keeping a decimal below 10.
+fn compact(value: f64, base: f64, suffixes: [&str; 4]) -> String {
+    let mut value = value;
+    let mut magnitude = 0;
+    while value >= base && magnitude < suffixes.len() - 1 {
+        value /= base;
+        magnitude += 1;
+    }
+    if magnitude > 0 && value < 10.0 {
+        format!("{:.1}{}", value, suffixes[magnitude])
+    } else {
+        format!("{:.0}{}", value, suffixes[magnitude])
+    }
+}
+
+/// How the output is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// The tree followed by the files, as plain text.
+    Text,
+    /// A JSON array of chat messages holding the text, ready to be sent to a
+    /// model API.
+    Chat,
+    /// The files planned by --dry-run, as JSON.
+    Json,
+    /// A YAML document with the root, the tree and each file with its
+    /// language, tokens and content.
+    Yaml,
+}
+
+/// How sizes are rendered in the report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SizeFormat {
+    /// Human readable sizes, such as "1.23 MB".
+    Human,
+    /// Exact byte counts.
+    Bytes,
+}
+
+impl SizeFormat {
+    fn render(self, size: u64) -> String {
+        match self {
+            SizeFormat::Human => format_size(size),
+            SizeFormat::Bytes => size.to_string(),
+        }
+    }
+}
+
+/// Check that a file being appended to was produced in the format of this run.
+fn check_appendable(existing: &str, path: &Path) -> Result<()> {
+    if existing.is_empty() || existing.starts_with("└── ") || existing.starts_with(SEPARATOR)
+    {
+        return Ok(());
+    }
+    bail!(
+        "Refusing to append to {}: it was not produced in this output format",
+        path.display()
+    )
+}
+
+/// Count the tokens the indentation removed by `--dedent` would have taken,
+/// by restoring it on each line that is not blank.
+fn dedent_savings(files: &[FileEntry], bpe: &CoreBPE) -> usize {
+    files
+        .iter()
+        .filter(|file| !file.indent.is_empty())
+        .map(|file| {
+            let original = file
+                .content
+                .lines()
+                .map(|line| {
+                    if line.is_empty() {
+                        line.to_string()
+                    } else {
+                        format!("{}{}", file.indent, line)
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join("\n");
+            let dedented = file
+                .tokens
+                .unwrap_or_else(|| bpe.encode_ordinary(&file.content).len());
+            bpe.encode_ordinary(&original)
+                .len()
+                .saturating_sub(dedented)
+        })
+        .sum()
+}
+
+/// Count the tokens of the license headers removed by `--strip-license-headers`.
+fn license_savings(files: &[FileEntry], bpe: &CoreBPE) -> usize {
+    files
+        .iter()
+        .map(|file| bpe.encode_ordinary(&file.license).len())
+        .sum()
+}
+
+/// The owners of a file, given by its path relative to the root of the
+/// repository, none if it lies outside of it.
+fn owners_of<'a>(codeowners: &'a CodeOwners, repo_root: &Path, path: &Path) -> &'a [String] {
+    match path.strip_prefix(repo_root) {
+        Ok(relative) => codeowners.owners(relative),
+        Err(_) => &[],
+    }
+}
+
+/// Count the included files of each owner, most common first, such as
+/// "@org/payments (12), @alice (3), unowned (1)". A file with several owners
+/// counts for each of them.
+fn owner_counts(
+    files: &[FileEntry],
+    candidates: &[Candidate],
+    codeowners: &CodeOwners,
+    repo_root: &Path,
+) -> String {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for file in files {
+        let owners = owners_of(codeowners, repo_root, &candidates[file.source].path);
+        if owners.is_empty() {
+            *counts.entry("unowned").or_default() += 1;
+        }
+        for owner in owners {
+            *counts.entry(owner).or_default() += 1;
+        }
+    }
+
+    let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    counts
+        .iter()
+        .map(|(owner, count)| format!("{} ({})", owner, count))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Count the included files of each language, most common first, such as
+/// "rust (12), markdown (3), other (1)".
+fn language_counts(files: &[FileEntry], languages: &Languages) -> String {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for file in files {
+        let language = languages.detect(&file.display).unwrap_or("other");
+        *counts.entry(language).or_default() += 1;
+    }
+
+    let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    counts
+        .iter()
+        .map(|(language, count)| format!("{} ({})", language, count))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Render a table of the files, lines, size and tokens of each language, the
+/// languages taking the most tokens first.
+fn language_table(files: &[FileEntry], languages: &Languages, size_format: SizeFormat) -> String {
+    #[derive(Default)]
+    struct Row {
+        files: usize,
+        lines: usize,
+        size: u64,
+        tokens: usize,
+    }
+
+    let mut rows: HashMap<&str, Row> = HashMap::new();
+    for file in files {
+        let language = languages.detect(&file.display).unwrap_or("other");
+        let row = rows.entry(language).or_default();
+        row.files += 1;
+        row.lines += file.content.lines().count();
+        row.size += file.size;
+        row.tokens += file.tokens.unwrap_or(0);
+    }
+    let total = rows.values().map(|row| row.tokens).sum::<usize>().max(1);
+
+    let mut rows: Vec<(&str, Row)> = rows.into_iter().collect();
+    rows.sort_by(|a, b| b.1.tokens.cmp(&a.1.tokens).then(a.0.cmp(b.0)));
+    let mut table = format!(
+        "    {:<16} {:>7} {:>9} {:>12} {:>10} {:>6}\n",
+        "Language", "Files", "Lines", "Size", "Tokens", "%"
+    );
+    for (language, row) in rows {
+        table.push_str(&format!(
+            "    {:<16} {:>7} {:>9} {:>12} {:>10} {:>6.1}\n",
+            language,
+            row.files,
+            row.lines,
+            size_format.render(row.size),
+            row.tokens,
+            row.tokens as f64 * 100.0 / total as f64
+        ));
+    }
+    table
+}
+
+/// Write the files with their headers, numbered and framed if asked to. The
+/// content is followed by a newline, after removing its trailing whitespace if
+/// asked to.
+fn write_files(
+    out: &mut dyn Write,
+    files: &[FileEntry],
+    numbered: bool,
+    trim: bool,
+    framed: bool,
+) -> io::Result<()> {
+    for (index, file) in files.iter().enumerate() {
+        write_file(out, file, numbered.then_some(index + 1), trim, framed)?;
+    }
+    Ok(())
+}
+
+/// Write an output to a file, appending to it if asked to, or to stdout,
+/// removing ANSI escape sequences unless stdout is a terminal.
+fn write_output(output: Option<&PathBuf>, bytes: &[u8], append: bool) -> Result<()> {
+    let bytes = match strips_ansi(output) {
+        true => ansi::strip(bytes),
+        false => Cow::Borrowed(bytes),
+    };
+    open_output(output, append)?
+        .write_all(&bytes)
+        .with_context(|| write_error(output))
+}
+
+/// Write an output holding files streamed with `--stream-over`, copying the
+/// content of each of them after its section.
+fn write_streamed(
+    output: Option<&PathBuf>,
+    head: &[u8],
+    sections: &[(&[u8], Option<&Path>)],
+    trailer: &[u8],
+    append: bool,
+    trim: bool,
+) -> Result<()> {
+    let strip = strips_ansi(output);
+    let mut writer = io::BufWriter::new(open_output(output, append)?);
+    let mut write = |bytes: &[u8]| match strip {
+        true => writer.write_all(&ansi::strip(bytes)),
+        false => writer.write_all(bytes),
+    };
+
+    write(head).with_context(|| write_error(output))?;
+    for (section, streamed) in sections {
+        write(section).with_context(|| write_error(output))?;
+        if let Some(path) = streamed {
+            copy_streamed(path, trim, &mut |bytes| {
+                write(bytes).with_context(|| write_error(output))
+            })?;
+        }
+    }
+    write(trailer).with_context(|| write_error(output))?;
+    writer.flush().with_context(|| write_error(output))
+}
+
+/// Copy the content of a file streamed with `--stream-over` line by line,
+/// ending it with a newline after removing its trailing whitespace if asked
+/// to, the same as files read in memory.
+fn copy_streamed(
+    path: &Path,
+    trim: bool,
+    write: &mut dyn FnMut(&[u8]) -> Result<()>,
+) -> Result<()> {
+    let mut reader = io::BufReader::new(longpath::open(path)?);
+    let mut line = Vec::new();
+    // Whitespace is held back until more content follows it, so that the
+    // whitespace ending the file is left out when trimming
+    let mut pending = Vec::new();
+    while reader
+        .read_until(b'\n', &mut line)
+        .with_context(|| format!("Failed to read file: {}", path.display()))?
+        > 0
+    {
+        let end = match trim {
+            true => line.iter().rposition(|byte| !byte.is_ascii_whitespace()),
+            false => line.len().checked_sub(1),
+        };
+        match end {
+            Some(end) => {
+                write(&pending)?;
+                write(&line[..=end])?;
+                pending = line[end + 1..].to_vec();
+            }
+            None => pending.extend_from_slice(&line),
+        }
+        line.clear();
+    }
+    if !trim {
+        write(&pending)?;
+    }
+    write(b"\n")
+}
+
+/// The frame of a file streamed with `--stream-over`, read ahead of copying
+/// it so that its header tells where it ends.
+fn streamed_frame(path: &Path, trim: bool) -> Result<String> {
+    let mut framer = Framer::default();
+    copy_streamed(path, trim, &mut |bytes| {
+        framer.update(bytes);
+        Ok(())
+    })?;
+    Ok(framer.finish())
+}
+
+/// Open an output file, appending to it if asked to, or stdout.
+fn open_output(output: Option<&PathBuf>, append: bool) -> Result<Box<dyn Write>> {
+    match output {
+        Some(output) => {
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .append(append)
+                .truncate(!append)
+                .open(output)
+                .with_context(|| format!("Failed to open output file: {}", output.display()))?;
+            Ok(Box::new(file))
+        }
+        None => Ok(Box::new(io::stdout().lock())),
+    }
+}
+
+/// Whether ANSI escape sequences are removed from an output: they would
+/// corrupt a file, and are only kept for a terminal.
+fn strips_ansi(output: Option<&PathBuf>) -> bool {
+    output.is_some() || !io::stdout().is_terminal()
+}
+
+/// The message of an error writing an output.
+fn write_error(output: Option<&PathBuf>) -> String {
+    match output {
+        Some(output) => format!("Failed to write output file: {}", output.display()),
+        None => "Failed to write output".to_string(),
+    }
+}
+
+/// The line listing a file in the index of `--file-index`.
+fn index_line(number: usize, file: &FileEntry) -> String {
+    format!(
+        "[{}] {} ({} tokens)\n",
+        number,
+        file.display.display(),
+        file.tokens.unwrap_or_default()
+    )
+}
+
+/// The frame ending the header of a section holding this content, when asked
+/// for with `--pack-metadata`.
+fn frame(body: &[u8], framed: bool) -> String {
+    match framed {
+        true => pack::frame(body),
+        false => String::new(),
+    }
+}
+
+/// Write the header and the content of a file, numbered if given a number.
+fn write_file(
+    out: &mut dyn Write,
+    file: &FileEntry,
+    number: Option<usize>,
+    trim: bool,
+    framed: bool,
+) -> io::Result<()> {
+    let mut body = Vec::new();
+    write_content(&mut body, file, trim)?;
+    write_header(out, file, number, &frame(&body, framed))?;
+    out.write_all(&body)
+}
+
+/// Write the header of a file, numbered if given a number, ending with the
+/// frame of its content if given one.
+fn write_header(
+    out: &mut dyn Write,
+    file: &FileEntry,
+    number: Option<usize>,
+    frame: &str,
+) -> io::Result<()> {
+    let label = match number {
+        Some(number) => format!("File [{}]", number),
+        None => "File".to_string(),
+    };
+    writeln!(out, "{}", SEPARATOR)?;
+    writeln!(out, "{}: {}{}", label, header_path(file), frame)?;
+    writeln!(out, "{}", SEPARATOR)
+}
+
+/// Write a file merged with the other small files by `--merge-small-files`,
+/// under a short sub-header, starting the block if it is the first of them.
+fn write_small_file(
+    out: &mut dyn Write,
+    file: &FileEntry,
+    first: bool,
+    trim: bool,
+    framed: bool,
+) -> io::Result<()> {
+    if first {
+        writeln!(out, "{}", SEPARATOR)?;
+        writeln!(out, "{}", SMALL_FILES)?;
+        writeln!(out, "{}", SEPARATOR)?;
+    }
+    let mut body = Vec::new();
+    write_content(&mut body, file, trim)?;
+    writeln!(out, "--- {}{}", header_path(file), frame(&body, framed))?;
+    out.write_all(&body)
+}
+
+/// Write a chunk of a file given by `--chunk-tokens`, under a header telling
+/// its number, the file and the tokens of the file it holds.
+fn write_chunk(
+    out: &mut dyn Write,
+    file: &FileEntry,
+    chunk: &Chunk,
+    number: usize,
+    trim: bool,
+    framed: bool,
+) -> io::Result<()> {
+    let body = match trim {
+        true => format!("{}\n", chunk.text.trim_end()),
+        false => format!("{}\n", chunk.text),
+    };
+    writeln!(out, "{}", SEPARATOR)?;
+    writeln!(
+        out,
+        "Chunk {}: {}, tokens {}..{} of {}{}",
+        number,
+        header_path(file),
+        chunk.start,
+        chunk.end,
+        file.tokens.unwrap_or(chunk.end),
+        frame(body.as_bytes(), framed)
+    )?;
+    writeln!(out, "{}", SEPARATOR)?;
+    out.write_all(body.as_bytes())
+}
+
+/// The path of a file as shown in its header, with its tag if it has one.
+fn header_path(file: &FileEntry) -> String {
+    match &file.tag {
+        Some(tag) => format!("{} [{}]", file.display.display(), tag),
+        None => file.display.display().to_string(),
+    }
+}
+
+/// Write the content of a file, followed by a newline.
+fn write_content(out: &mut dyn Write, file: &FileEntry, trim: bool) -> io::Result<()> {
+    if trim {
+        writeln!(out, "{}", file.content.trim_end())
+    } else {
+        writeln!(out, "{}", file.content)
+    }
+}
+
+/// Count the tokens of the headers saved by merging the small files, less the
+/// header of the block holding them.
+fn merge_savings(merged: &[FileEntry], bpe: &CoreBPE) -> usize {
+    let block = format!("{}\n{}\n{}\n", SEPARATOR, SMALL_FILES, SEPARATOR);
+    merged
+        .iter()
+        .map(|file| {
+            let header = format!(
+                "{}\nFile: {}\n{}\n",
+                SEPARATOR,
+                header_path(file),
+                SEPARATOR
+            );
+            let sub_header = format!("--- {}\n", header_path(file));
+            bpe.encode_ordinary(&header)
+                .len()
+                .saturating_sub(bpe.encode_ordinary(&sub_header).len())
+        })
+        .sum::<usize>()
+        .saturating_sub(bpe.encode_ordinary(&block).len())
+}
+
+/// Find the files whose content is the same as that of a file emitted before
+/// them, such as copied configurations, with the index of that file. Empty
+/// files, and streamed ones whose content is not read, are left out.
+fn duplicates(files: &[FileEntry]) -> Vec<(usize, usize)> {
+    let mut first: HashMap<String, usize> = HashMap::new();
+    let mut found = Vec::new();
+    for (index, file) in files.iter().enumerate() {
+        if file.streamed || file.content.trim().is_empty() {
+            continue;
+        }
+        let original = *first
+            .entry(state::content_hash(&file.content))
+            .or_insert(index);
+        if original != index && files[original].content == file.content {
+            found.push((index, original));
+        }
+    }
+    found
+}
+
+/// Record the files left out once read, each with its reason: reported as
+/// skipped, explained, and no longer counted in the totals.
+fn record_skips(
+    dropped: impl IntoIterator<Item = (FileEntry, SkipReason)>,
+    candidates: &[Candidate],
+    diagnostics: &mut Diagnostics,
+    explained: &mut [(PathBuf, Decision)],
+    totals: &mut Totals,
+) {
+    for (file, reason) in dropped {
+        let path = &candidates[file.source].path;
+        diagnostics.skip(path, reason.clone());
+        if let Some((_, decision)) = explained
+            .iter_mut()
+            .find(|(explained, _)| explained == path)
+        {
+            decision.fail(reason);
+        }
+        totals.remove(path, file.size);
+    }
+}
+
+/// How a file found by the walker appears in the tree.
+enum Listed {
+    /// Included in the output, at the given index of the file contents.
+    Included(usize),
+    NonText,
+}
+
+/// Find the paths to process, either listed by the user or a replayed report,
+/// or by walking the current directory and, with `--workspace`, the rest of the
+/// workspace.
+/// Returns them with the paths ignored by git, found with
+/// `--tree-includes-ignored` to be shown in the tree.
+fn find_candidates(
+    args: &Args,
+    root: &Path,
+    options: &WalkOptions,
+    diagnostics: &mut Diagnostics,
+) -> Result<(Vec<Candidate>, Vec<Candidate>)> {
+    if let Some(list) = &args.files_from {
+        let candidates = source::files_from(root, list, args.files_from_format)?;
+        return Ok((candidates, Vec::new()));
+    }
+    if let Some(report) = &args.replay {
+        let replay_options = ReplayOptions {
+            strict: args.replay_strict,
+            additions: args.replay_additions,
+        };
+        let candidates = report::replay(report, root, options, &replay_options, diagnostics)?;
+        return Ok((candidates, Vec::new()));
+    }
+
+    let mut candidates = source::walk(root, options)?;
+    let mut ignored = Vec::new();
+    if args.tree_includes_ignored {
+        ignored = source::ignored(root, options, &candidates)?;
+    }
+    source::include(&mut candidates, root, options, &args.include)?;
+    ignored.retain(|path| {
+        !candidates
+            .iter()
+            .any(|candidate| candidate.path == path.path)
+    });
+
+    // Keep the selected packages, and the directories leading to them
+    if !args.packages.is_empty() {
+        let (dirs, warnings) = workspace::select_packages(root, &args.packages, args.package_deps)?;
+        for warning in warnings {
+            diagnostics.warn(warning);
+        }
+        let selected = |candidate: &Candidate| {
+            dirs.iter().any(|dir| {
+                candidate.path.starts_with(dir)
+                    || (candidate.is_dir && dir.starts_with(&candidate.path))
+            })
+        };
+        candidates.retain(selected);
+        ignored.retain(selected);
+    }
+
+    // Each root is shown under the prefix given for it, the other members of
+    // the workspace defaulting to their path from the current directory
+    let prefixes = workspace::path_prefixes(&args.path_prefixes)?;
+    let canonical = |dir: &Path| fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    let prefix_of = |dir: &Path| {
+        let dir = canonical(dir);
+        prefixes
+            .iter()
+            .find(|prefix| prefix.root == dir)
+            .map(|prefix| prefix.prefix.clone())
+    };
+    if let Some(prefix) = prefix_of(root) {
+        for candidate in candidates.iter_mut().chain(&mut ignored) {
+            candidate.display = prefix.join(&candidate.display);
+        }
+    }
+
+    if args.workspace {
+        let dirs = workspace::outside_dirs(root)?;
+        let roots: Vec<PathBuf> = dirs.iter().map(|dir| canonical(dir)).collect();
+        if let Some(unknown) = prefixes
+            .iter()
+            .find(|prefix| prefix.root != canonical(root) && !roots.contains(&prefix.root))
+        {
+            bail!(
+                "--path-prefix {}: not a root of the workspace (the current directory, or a member or path dependency outside it)",
+                unknown.root.display()
+            );
+        }
+        for dir in dirs {
+            let prefix = prefix_of(&dir).unwrap_or_else(|| workspace::relative_to(&dir, root));
+            let walked = source::walk(&dir, options)?;
+            if args.tree_includes_ignored {
+                for mut path in source::ignored(&dir, options, &walked)? {
+                    path.display = prefix.join(&path.display);
+                    ignored.push(path);
+                }
+            }
+            for mut candidate in walked {
+                candidate.display = prefix.join(&candidate.display);
+                candidates.push(candidate);
+            }
+        }
+    }
+
+    Ok((candidates, ignored))
+}
+
+/// Start the decision for a candidate, depending on whether the walker already filtered it.
+fn start_decision(candidate: &Candidate) -> Decision {
+    if candidate.walked {
+        Decision::walked()
+    } else {
+        Decision::default()
     }
 }
 
 fn main() -> Result<()> {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    match &args.command {
+        Some(Command::Merge(merge_args)) => return merge::run(merge_args),
+        Some(Command::History(history_args)) => return history::run(history_args),
+        Some(Command::Verify(verify_args)) => return verify::run(verify_args),
+        None => {}
+    }
     let start_time = Instant::now();
     let current_dir = std::env::current_dir().context("Failed to get current directory")?;
 
-    let mut total_files: usize = 0;
-    let mut total_size: u64 = 0;
-    let mut file_contents: Vec<(PathBuf, String)> = Vec::new();
-    let mut tree_structure: String = String::new();
-    let mut errors: Vec<String> = Vec::new();
-
-    // Build the file walker, respecting gitignore
-    let walker = WalkBuilder::new(&current_dir).git_ignore(true).build();
-
-    // Iterate through all entries (files and directories) found by the walker
-    for entry in walker {
-        let entry = entry.with_context(|| "Error during directory traversal")?;
-        let path = entry.path();
-
-        // Skip the root path of the current directory
-        if path == current_dir {
-            tree_structure.push_str(&format!(
-                "└── {}\n",
-                path.file_name().and_then(OsStr::to_str).unwrap_or(".")
+    // The patterns of the `.llmr` directory come before those of the flags,
+    // which can override them
+    if !args.no_llmr_dir {
+        let mut include = source::llmr_patterns(&current_dir, "include")?;
+        include.append(&mut args.include);
+        args.include = include;
+        let mut exclude = source::llmr_patterns(&current_dir, "exclude")?;
+        exclude.append(&mut args.ignore_patterns);
+        args.ignore_patterns = exclude;
+    }
+
+    // Each format is written to the output given at the same position
+    let formats = match args.format.is_empty() {
+        true => vec![OutputFormat::Text],
+        false => args.format.clone(),
+    };
+    if (formats.len() > 1 || args.output.len() > 1) && formats.len() != args.output.len() {
+        bail!(
+            "Got {} --format for {} --output: give one output for each format",
+            formats.len(),
+            args.output.len()
+        );
+    }
+    if args.append && args.output.len() > 1 {
+        bail!("--append can only be used with a single --output");
+    }
+    if args.update && (args.output.len() > 1 || formats[0] != OutputFormat::Text) {
+        bail!("--update can only be used with a single --output in the text format");
+    }
+    // The bytes are counted on the text, which the other formats escape
+    if args.max_output_bytes.is_some() && formats.iter().any(|f| *f != OutputFormat::Text) {
+        bail!("--max-output-bytes can only be used with the text format, which it counts the bytes of");
+    }
+    let targets: Vec<(OutputFormat, Option<&PathBuf>)> = match args.output.is_empty() {
+        true => vec![(formats[0], None)],
+        false => formats
+            .iter()
+            .copied()
+            .zip(args.output.iter().map(Some))
+            .collect(),
+    };
+
+    // The messages of the chat format and the YAML document cannot be
+    // appended to
+    if args.append && formats.contains(&OutputFormat::Yaml) {
+        bail!("--append cannot be used with --format yaml");
+    }
+    if formats.contains(&OutputFormat::Chat) {
+        if args.append {
+            bail!("--append cannot be used with --format chat");
+        }
+    } else if args.prompt.is_some() || args.instructions.is_some() || args.chat_split_files {
+        bail!("--prompt, --instructions and --chat-split-files require --format chat");
+    }
+    if args.stream_over.is_some()
+        && (formats.contains(&OutputFormat::Chat) || formats.contains(&OutputFormat::Yaml))
+    {
+        bail!("--stream-over copies files to a text output, and cannot be used with --format chat or yaml");
+    }
+    if args.dry_run && formats.contains(&OutputFormat::Chat) {
+        bail!("--dry-run lists the files as text or JSON, not as chat messages");
+    } else if args.dry_run && formats.contains(&OutputFormat::Yaml) {
+        bail!("--dry-run lists the files as text or JSON, not as a YAML document");
+    } else if !args.dry_run && formats.contains(&OutputFormat::Json) {
+        bail!("--format json lists the files planned by --dry-run, and requires it");
+    }
+    if args.git_ref.is_some() && matches!(args.sort, SortKey::Size | SortKey::Mtime) {
+        bail!("--sort size and mtime read the working tree, and cannot be used with --ref");
+    }
+
+    // Paths are shown relative to the directory asked for, if any, else to the
+    // current directory
+    let display_root = match &args.relative_to {
+        Some(dir) => fs::canonicalize(dir)
+            .with_context(|| format!("Failed to find directory: {}", dir.display()))?,
+        None if args.root_from_git => source::repo_root(&current_dir)
+            .unwrap_or(&current_dir)
+            .to_path_buf(),
+        None => current_dir.clone(),
+    };
+    let display_prefix = workspace::relative_to(&current_dir, &display_root);
+
+    let mut totals = Totals::default();
+    let mut file_contents: Vec<FileEntry> = Vec::new();
+    // The root is shown as `.` for the current directory and as given for
+    // another one, leaving out the directories above unless asked for them
+    let mut redactor = Redactor::new(&args.redact_paths_matching)?;
+    let tree_root = match &args.relative_to {
+        _ if args.absolute_root => redactor.path(&display_root).display().to_string(),
+        Some(dir) => tree::root_name(&redactor.path(dir)),
+        None => {
+            tree::root_name(&redactor.path(&workspace::relative_to(&display_root, &current_dir)))
+        }
+    };
+    let mut tree = Tree::new(&tree_root);
+    let mut diagnostics = Diagnostics::new(args.verbose, args.errors.clone());
+    let mut explained: Vec<(PathBuf, Decision)> = Vec::new();
+    let mut timings = Timings::default();
+
+    let limits = Limits {
+        max_file_size: args.max_file_size,
+        max_total_size: args.max_total_size,
+        max_files: args.max_files,
+        max_files_per_extension: args.max_files_per_extension,
+        max_json_size: args.exclude_large_json,
+    };
+    let explain_targets: Vec<PathBuf> = args
+        .explain
+        .iter()
+        .map(|path| current_dir.join(path))
+        .collect();
+
+    // Read the file being appended to, refusing to mix output formats
+    let existing = match args.output.first() {
+        Some(output) if args.append && output.exists() => {
+            let existing = fs::read_to_string(output)
+                .with_context(|| format!("Failed to read output file: {}", output.display()))?;
+            check_appendable(&existing, output)?;
+            existing
+        }
+        _ => String::new(),
+    };
+
+    // Read the file being updated, refusing to overwrite anything else
+    let previous = match args.output.first() {
+        Some(output) if args.update && output.exists() => {
+            let previous = fs::read_to_string(output)
+                .with_context(|| format!("Failed to read output file: {}", output.display()))?;
+            // A pack whose sections no longer match their frame cannot be
+            // compared section by section
+            let problem = match pack::sections(&previous, output) {
+                Ok(sections) => sections.iter().find_map(Section::problem),
+                Err(e) => Some(format!("{:#}", e)),
+            };
+            if let Some(problem) = problem {
+                bail!(
+                    "Refusing to update {}: {}. Remove it or write to another file",
+                    output.display(),
+                    problem
+                );
+            }
+            Some(previous)
+        }
+        _ => None,
+    };
+
+    let codeowners = if !args.owner.is_empty() || args.owner_unowned {
+        let repo_root = source::repo_root(&current_dir)
+            .context("--owner and --owner-unowned must be used inside a git repository")?
+            .to_path_buf();
+        let codeowners = CodeOwners::find(&repo_root)?.with_context(|| {
+            format!(
+                "No CODEOWNERS file in {}, its .github or its docs directory",
+                repo_root.display()
+            )
+        })?;
+        Some((repo_root, codeowners))
+    } else {
+        None
+    };
+
+    let timeout = args
+        .timeout
+        .as_deref()
+        .map(timings::parse_duration)
+        .transpose()?;
+    let walk_options = WalkOptions {
+        ignore_patterns: source::ignore_patterns(&current_dir, &args.ignore_patterns)?,
+        exclude_dirs: args.exclude_dirs.clone(),
+        llmrignore: !args.no_llmrignore,
+        dot_ignore: !args.no_dot_ignore,
+        gitignore: !args.no_gitignore,
+        global_excludes: if args.no_global_gitignore {
+            None
+        } else {
+            git::global_excludes_file()
+        },
+        parents: !args.no_parent_ignore,
+        require_git: args.require_git,
+        follow_symlinks: args.follow_symlinks,
+        deadline: timeout.map(|timeout| Deadline::new(start_time + timeout)),
+    };
+    if args.show_config {
+        for source in source::ignore_sources(
+            &current_dir,
+            &walk_options,
+            &args.include,
+            &args.ignore_patterns,
+        ) {
+            println!("{}", source);
+        }
+        return Ok(());
+    }
+
+    // Read the files of a revision from the git objects, when asked to
+    let mut snapshot = match &args.git_ref {
+        Some(revision) => Some(timings.time(Phase::Walk, || {
+            snapshot::read(&current_dir, revision, &walk_options, args.max_file_size)
+        })?),
+        None => None,
+    };
+
+    // Find the paths to process, either read from a revision, listed by the
+    // user or by walking the current directory
+    let (mut candidates, mut ignored) = match &mut snapshot {
+        Some(snapshot) => (std::mem::take(&mut snapshot.candidates), Vec::new()),
+        None => timings.time(Phase::Walk, || {
+            find_candidates(&args, &current_dir, &walk_options, &mut diagnostics)
+        })?,
+    };
+    for candidate in candidates.iter_mut().chain(&mut ignored) {
+        candidate.display = redactor.path(&display_prefix.join(&candidate.display));
+    }
+    if let (Some(timeout), Some(deadline)) = (&args.timeout, &walk_options.deadline) {
+        if deadline.cut_short() {
+            diagnostics.warn(format!(
+                "Warning: stopped walking after {} (--timeout), the output only holds the files found until then",
+                timeout
             ));
-            continue;
         }
+    }
 
-        // Handle directories
-        if entry.file_type().map_or(false, |ft| ft.is_dir()) {
-            let relative_path = path.strip_prefix(&current_dir).with_context(|| {
-                format!("Failed to strip prefix for directory: {}", path.display())
-            })?;
-            let indent_level = relative_path.iter().count();
-            let indent = "    ".repeat(indent_level - 1);
-            let file_name = relative_path
-                .file_name()
-                .and_then(OsStr::to_str)
-                .unwrap_or(".");
-
-            tree_structure.push_str(&format!("{}├── {}\n", indent, file_name));
-            continue;
+    // Leave out named pipes, sockets and devices, found by the walk or listed,
+    // which reading could hang on
+    if snapshot.is_none() {
+        candidates.retain(|candidate| {
+            if candidate.is_dir {
+                return true;
+            }
+            let Some(kind) = source::special_kind(&candidate.path) else {
+                return true;
+            };
+            diagnostics.skip(&candidate.path, SkipReason::Special(kind));
+            if args.explain_all || explain_targets.contains(&candidate.path) {
+                let mut decision = start_decision(candidate);
+                decision.fail(SkipReason::Special(kind));
+                explained.push((candidate.path.clone(), decision));
+            }
+            false
+        });
+    }
+
+    // Show the files reached through several paths only once
+    let links = if args.follow_symlinks && snapshot.is_none() {
+        source::dedup_links(&mut candidates)
+    } else {
+        Vec::new()
+    };
+    for link in &links {
+        let path = &link.candidate.path;
+        if args.explain_all || explain_targets.contains(path) {
+            let mut decision = start_decision(&link.candidate);
+            decision.fail(SkipReason::Symlink(link.shown.clone()));
+            explained.push((path.clone(), decision));
         }
+    }
 
-        // Process files
-        if path.is_file() {
-            let relative_path = path
-                .strip_prefix(&current_dir)
-                .with_context(|| format!("Failed to strip prefix for file: {}", path.display()))?;
-            let indent_level = relative_path.iter().count();
-            let indent = "    ".repeat(indent_level - 1);
-            let file_name = relative_path
-                .file_name()
-                .and_then(OsStr::to_str)
-                .unwrap_or(".");
-
-            let file_size = metadata(path)
-                .with_context(|| format!("Failed to get metadata for file: {}", path.display()))?
-                .len();
-
-            // Check limits for number of files
-            if total_files >= args.max_files {
-                errors.push(format!(
-                    "Skipping file {}: Maximum file limit ({}) reached",
-                    path.display(),
-                    args.max_files
-                ));
-                continue;
+    // Leave out the files written by this run, which a previous run may have
+    // written in the current directory
+    let output_paths: Vec<PathBuf> = args
+        .output
+        .iter()
+        .chain(&args.prompt_file)
+        .chain(&args.report_json)
+        .chain(&args.content_hash_manifest)
+        .chain(&args.state)
+        .chain(args.errors.file())
+        .map(|output| {
+            let path = current_dir.join(output);
+            fs::canonicalize(&path).unwrap_or(path)
+        })
+        .collect();
+    if !output_paths.is_empty() {
+        candidates.retain(|candidate| {
+            if !output_paths.contains(&candidate.path) {
+                return true;
             }
+            if args.explain_all || explain_targets.contains(&candidate.path) {
+                let mut decision = start_decision(candidate);
+                decision.fail(SkipReason::Output);
+                explained.push((candidate.path.clone(), decision));
+            }
+            false
+        });
+    }
+
+    // Resolve the revisions of the range before the cache, whose key holds
+    // them
+    let range_changes = match &args.diff_range {
+        Some(range) => Some(git::changed_in_range(&current_dir, range)?),
+        None => None,
+    };
 
-            // Check limits for total size
-            if total_size + file_size > args.max_total_size {
-                errors.push(format!(
-                    "Skipping file {}: Total size limit ({}) reached",
-                    path.display(),
-                    format_size(args.max_total_size)
+    // Write the output of the last run again when the options, the HEAD commit,
+    // the commits of --diff-range, the CODEOWNERS file of --owner and the files
+    // found are the same, comparing their size and modification time only
+    let output_cache = match args.resume_from_cache {
+        true => {
+            let path = cache::path(&current_dir);
+            if path.is_none() {
+                diagnostics.warn(
+                    "Warning: --resume-from-cache keeps its cache in the git directory, running without it outside a repository"
+                        .to_string(),
+                );
+            }
+            path
+        }
+        false => None,
+    };
+    let run_stamps = match output_cache {
+        Some(_) => cache::stamps(&candidates),
+        None => Vec::new(),
+    };
+    let run_key = match output_cache {
+        Some(_) => state::content_hash(&format!(
+            "{:?}\n{}\n{}\n{}\n{}",
+            args,
+            current_dir.display(),
+            git::head_commit(&current_dir)
+                .map(|head| head.id)
+                .unwrap_or_default(),
+            range_changes
+                .as_ref()
+                .map(|changes| changes.commits.as_str())
+                .unwrap_or_default(),
+            codeowners
+                .as_ref()
+                .and_then(|(repo_root, _)| owners::path(repo_root))
+                .and_then(|path| fs::read_to_string(path).ok())
+                .unwrap_or_default()
+        )),
+        None => String::new(),
+    };
+    if let Some(path) = &output_cache {
+        if let Some(cached) = cache::load(path) {
+            if cached.key == run_key && cached.files == run_stamps {
+                for output in &cached.outputs {
+                    write_output(output.path.as_ref(), output.content.as_bytes(), false)?;
+                }
+                diagnostics.info(format!(
+                    "No file changed since the last run, wrote its output again from {}",
+                    path.display()
                 ));
-                continue;
+                diagnostics.print()?;
+                return Ok(());
             }
+        }
+    }
 
-            // Check limits for max file size
-            if file_size > args.max_file_size {
-                errors.push(format!(
-                    "Skipping file {}: File exceeds maximum size ({})",
-                    path.display(),
-                    format_size(args.max_file_size)
-                ));
-                continue;
+    // Keep the files of the given owners, and the directories leading to them
+    if let Some((repo_root, codeowners)) = &codeowners {
+        candidates.retain(|candidate| {
+            if candidate.is_dir {
+                return true;
             }
+            let owners = owners_of(codeowners, repo_root, &candidate.path);
+            let selected = if args.owner_unowned {
+                owners.is_empty()
+            } else {
+                owners.iter().any(|owner| args.owner.contains(owner))
+            };
+            if !selected && (args.explain_all || explain_targets.contains(&candidate.path)) {
+                let owned = match owners {
+                    [] => "no owner".to_string(),
+                    owners => format!("owned by {}", owners.join(", ")),
+                };
+                let mut decision = start_decision(candidate);
+                decision.fail(SkipReason::Owner(owned));
+                explained.push((candidate.path.clone(), decision));
+            }
+            selected
+        });
+    }
 
-            // If the file is a text file, then we process it
-            if is_text_file(path)
-                .with_context(|| format!("Error checking if file is text: {}", path.display()))?
-            {
-                match read_file_content(path) {
+    // Keep the files changed in the range, and the directories leading to them
+    if let (Some(range), Some(changes)) = (&args.diff_range, &range_changes) {
+        candidates.retain(|candidate| {
+            if candidate.is_dir || changes.paths.contains(&git::canonical(&candidate.path)) {
+                return true;
+            }
+            if args.explain_all || explain_targets.contains(&candidate.path) {
+                let mut decision = start_decision(candidate);
+                decision.fail(SkipReason::DiffRange(range.clone()));
+                explained.push((candidate.path.clone(), decision));
+            }
+            false
+        });
+    }
+    if codeowners.is_some() || args.diff_range.is_some() {
+        let files: Vec<PathBuf> = candidates
+            .iter()
+            .filter(|candidate| !candidate.is_dir)
+            .map(|candidate| candidate.path.clone())
+            .collect();
+        candidates.retain(|candidate| {
+            !candidate.is_dir || files.iter().any(|file| file.starts_with(&candidate.path))
+        });
+    }
+
+    // Order the files and keep the requested slice of them
+    let (sort, slice) = match (args.recent, args.head_files, args.tail_files) {
+        (Some(count), _, _) => {
+            diagnostics.info(format!(
+                "--recent {} resolved to --sort mtime --tail-files {}",
+                count, count
+            ));
+            (SortKey::Mtime, Some(Slice::Tail(count)))
+        }
+        (None, Some(count), _) => (args.sort, Some(Slice::Head(count))),
+        (None, None, Some(count)) => (args.sort, Some(Slice::Tail(count))),
+        (None, None, None) => (args.sort, None),
+    };
+    let priority = Priority::new(&args.priority)?;
+    let languages = Languages::new(&args.lang_map)?;
+    let mut budgets = Budgets {
+        dirs: budget::dir_budgets(&args.dir_budget)?,
+        languages: budget::lang_budgets(&args.lang_budget, &languages)?,
+        max_tokens: args.max_tokens,
+    };
+    let mut state = match &args.state {
+        Some(_) if args.state_reset => Some(State::default()),
+        Some(path) => Some(state::read(path)?),
+        None => None,
+    };
+    let template = match &args.prompt_template {
+        Some(path) => prompt::load(path)?,
+        None => prompt::DEFAULT_TEMPLATE.to_string(),
+    };
+    let mut order = select::sort(&candidates, sort, args.desc)?;
+    select::prioritize(&mut order, &candidates, &priority);
+
+    // Resume from a file in a deterministic order, the slice being taken from
+    // there, the walk order depending on the filesystem
+    let mut before_start = Vec::new();
+    if let Some(start) = &args.start_at {
+        if sort == SortKey::Walk {
+            bail!("--start-at needs a deterministic order: give --sort path, size or mtime");
+        }
+        let start: PathBuf = start
+            .components()
+            .filter(|component| *component != Component::CurDir)
+            .collect();
+        let by_path = sort == SortKey::Path && !args.desc && args.priority.is_empty();
+        (order, before_start) = select::start_at(order, &candidates, &start, by_path)?;
+    }
+    let (selected, dropped) = select::slice(order, slice);
+
+    // List the files that pass the limits, from their size alone
+    if args.dry_run {
+        let mut plan = Plan::new(current_dir.clone());
+        for index in selected {
+            let candidate = &candidates[index];
+            let path = candidate.path.as_path();
+            let size = match snapshot.as_ref().and_then(|snapshot| snapshot.file(path)) {
+                Some(blob) => blob.size,
+                None => longpath::metadata(path)?.len(),
+            };
+            let decision = filter::evaluate(
+                start_decision(candidate),
+                path,
+                size,
+                Ok(true),
+                None,
+                &totals,
+                &limits,
+            )?;
+            match decision.skip {
+                Some(reason) => diagnostics.skip(path, reason),
+                None => {
+                    plan.add(candidate.display.clone(), size);
+                    totals.add(path, size);
+                }
+            }
+        }
+
+        diagnostics.print()?;
+        for (format, output) in &targets {
+            let rendered = match format {
+                OutputFormat::Json => plan.to_json()?,
+                _ => plan.to_text(),
+            };
+            write_output(*output, rendered.as_bytes(), false)?;
+        }
+        return Ok(());
+    }
+
+    // Files shown in the tree
+    let mut listed: HashMap<usize, Listed> = HashMap::new();
+    // Non-text files listed after the files with `--list-binaries`
+    let mut binaries: Vec<(PathBuf, u64)> = Vec::new();
+
+    // Read the files in parallel, keeping the results in sort order. Reading
+    // waits on the disk and tokenizing on the CPUs, so each has its own threads
+    let io_pool = ThreadPoolBuilder::new()
+        .num_threads(args.io_jobs.or(args.jobs).unwrap_or(0))
+        .build()
+        .context("Failed to build the thread pool reading files")?;
+    let cpu_pool = ThreadPoolBuilder::new()
+        .num_threads(args.cpu_jobs.or(args.jobs).unwrap_or(0))
+        .build()
+        .context("Failed to build the thread pool counting tokens")?;
+    let probe_options = ProbeOptions {
+        max_file_size: args.max_file_size,
+        #[cfg(feature = "docx")]
+        extract_docx: args.extract_docx,
+        #[cfg(not(feature = "docx"))]
+        extract_docx: false,
+        transforms: Transforms {
+            dedent: args.dedent,
+            strip_blank_bounds: args.strip_empty_lines_at_bounds,
+            reflow: args.reflow,
+            strip_license_headers: args.strip_license_headers,
+            preserve_bom: args.preserve_bom,
+            compact_json: args.compact_json,
+        },
+        filter_command: args.filter_command.clone(),
+        data_thresholds: args.skip_data_files.then_some(DataThresholds {
+            key_repetition: args.data_key_repetition,
+            array_length: args.data_array_length,
+        }),
+        codegen: if args.skip_codegen {
+            Some(Codegen::new(&args.codegen_patterns)?)
+        } else {
+            None
+        },
+        skip_blobs: args.skip_blobs,
+        stream_over: args.stream_over,
+        verify_utf8: args.verify_utf8,
+        snapshot: snapshot.as_ref(),
+    };
+    // Read the files a window at a time, in parallel, and apply the limits to
+    // each window in order, so the result matches a serial run while no more
+    // than a window of files is held in memory. Once the number of files
+    // reaches its limit, the rest are skipped without being read
+    let window = io_pool.current_num_threads() * PROBES_PER_THREAD;
+    for batch in selected.chunks(window) {
+        if totals.files >= limits.max_files {
+            for &index in batch {
+                let candidate = &candidates[index];
+                let reason = SkipReason::MaxFiles(limits.max_files);
+                diagnostics.skip(&candidate.path, reason.clone());
+                if args.explain_all || explain_targets.contains(&candidate.path) {
+                    let mut decision = start_decision(candidate);
+                    decision.fail(reason);
+                    explained.push((candidate.path.clone(), decision));
+                }
+            }
+            continue;
+        }
+
+        let probes: Vec<Result<Probe>> = io_pool.install(|| {
+            batch
+                .par_iter()
+                .map(|&index| probe_file(&candidates[index], &probe_options))
+                .collect()
+        });
+        for (&index, probe) in batch.iter().zip(probes) {
+            let candidate = &candidates[index];
+            let path = candidate.path.as_path();
+            let probe = probe?;
+            let file_size = probe.size;
+            timings.merge(&probe.timings);
+            if let Some(warning) = probe.warning {
+                diagnostics.warn(warning);
+            }
+
+            // Run the file through the filtering pipeline
+            let decision = start_decision(candidate);
+            let mut decision = filter::evaluate(
+                decision,
+                path,
+                file_size,
+                probe.is_text,
+                probe.generated,
+                &totals,
+                &limits,
+            )?;
+
+            match &decision.skip {
+                None => match probe
+                    .content
+                    .unwrap_or_else(|| timings.time(Phase::Read, || read_file_content(path)))
+                {
                     Ok(content) => {
                         // Push the content to the vector
-                        file_contents.push((path.to_path_buf(), content));
+                        file_contents.push(FileEntry {
+                            source: index,
+                            display: candidate.display.clone(),
+                            tag: None,
+                            size: file_size,
+                            tokens: None,
+                            content,
+                            indent: probe.indent,
+                            license: probe.license,
+                            streamed: probe.streamed,
+                        });
                         // Increment counters
-                        total_size += file_size;
-                        total_files += 1;
-                        tree_structure.push_str(&format!("{}└── {}\n", indent, file_name));
+                        totals.add(path, file_size);
                     }
                     Err(e) => {
-                        errors.push(format!("Error reading file {}: {}", path.display(), e));
+                        let reason = SkipReason::Read(e.to_string());
+                        diagnostics.skip(path, reason.clone());
+                        decision.fail(reason);
                     }
+                },
+                Some(SkipReason::NonText) if args.list_binaries => {
+                    binaries.push((candidate.display.clone(), file_size));
                 }
-            } else {
-                tree_structure.push_str(&format!("{}└── {} [Non-text file]\n", indent, file_name));
+                Some(SkipReason::NonText) => {
+                    listed.insert(index, Listed::NonText);
+                }
+                Some(reason) => diagnostics.skip(path, reason.clone()),
+            }
+
+            if args.explain_all || explain_targets.iter().any(|target| target == path) {
+                explained.push((path.to_path_buf(), decision));
+            }
+        }
+    }
+
+    // Files left out of the slice are only reported when explained
+    if let Some(start) = &args.start_at {
+        for index in before_start {
+            let path = &candidates[index].path;
+            if args.explain_all || explain_targets.contains(path) {
+                let mut decision = start_decision(&candidates[index]);
+                decision.fail(SkipReason::StartAt(start.clone()));
+                explained.push((path.clone(), decision));
+            }
+        }
+    }
+    if let Some(slice) = slice {
+        for index in dropped {
+            let path = &candidates[index].path;
+            if args.explain_all || explain_targets.contains(path) {
+                let mut decision = start_decision(&candidates[index]);
+                decision.fail(SkipReason::Sliced(slice));
+                explained.push((path.clone(), decision));
             }
         }
     }
 
+    // Count the tokens of each file when needed
+    let bpe: Option<CoreBPE> = if args.report
+        || args.file_index
+        || args.prompt_file.is_some()
+        || args.report_json.is_some()
+        || args.max_tokens.is_some()
+        || !args.dir_budget.is_empty()
+        || !args.lang_budget.is_empty()
+        || args.tree_sort == TreeSort::Weight
+        || args.dump_tree_sizes_only
+        || args.omit_content_over.is_some()
+        || args.preview_lines.is_some()
+        || args.chunk_tokens.is_some()
+        || args.report_line
+        || formats.contains(&OutputFormat::Yaml)
+    {
+        Some(cl100k_base().context("Failed to get BPE tokenizer")?)
+    } else {
+        None
+    };
+    if let Some(bpe) = &bpe {
+        let progress = Progress::new("Counting tokens", file_contents.len(), !args.no_progress);
+        let durations: Vec<Duration> = cpu_pool.install(|| {
+            file_contents
+                .par_iter_mut()
+                .map(|file| {
+                    let start = Instant::now();
+                    file.tokens = Some(bpe.encode_ordinary(&file.content).len());
+                    progress.tick();
+                    start.elapsed()
+                })
+                .collect()
+        });
+        progress.finish();
+        for duration in durations {
+            timings.add(Phase::Tokenize, duration);
+        }
+    }
+
+    // Keep the first lines of every file, for an overview of all of them
+    let mut preview_saved = 0;
+    if let (Some(lines), Some(bpe)) = (args.preview_lines, &bpe) {
+        preview_saved = budget::preview(&mut file_contents, lines, bpe);
+    }
+
+    // Stub the files too large to be worth their tokens
+    if let (Some(threshold), Some(bpe)) = (args.omit_content_over, &bpe) {
+        budget::stub(&mut file_contents, threshold, bpe);
+    }
+
+    // Leave out the files sent in the previous parts, unless they changed since
+    let mut part = None;
+    if let Some(state) = &state {
+        let mut unchanged = Vec::new();
+        let mut changed = Vec::new();
+        for file in std::mem::take(&mut file_contents) {
+            let Some(record) = state.sent(&file.display) else {
+                file_contents.push(file);
+                continue;
+            };
+            if record.hash != state::content_hash(&file.content) {
+                changed.push(file.display.display().to_string());
+                file_contents.push(file);
+                continue;
+            }
+
+            unchanged.push((file, SkipReason::Sent(record.part)));
+        }
+        let sent = unchanged.len();
+        record_skips(
+            unchanged,
+            &candidates,
+            &mut diagnostics,
+            &mut explained,
+            &mut totals,
+        );
+        if file_contents.is_empty() {
+            bail!(
+                "All the files were sent in {} parts, use --state-reset to start over",
+                state.parts
+            );
+        }
+        if !changed.is_empty() {
+            diagnostics.warn(format!(
+                "Sending again {} files changed since they were sent: {}",
+                changed.len(),
+                changed.join(", ")
+            ));
+        }
+        part = Some(Part {
+            number: state.parts + 1,
+            sent,
+            changed: changed.len(),
+            total: sent + file_contents.len(),
+            kept: 0,
+        });
+    }
+
+    // Leave out the copies of files kept, before the budgets count them
+    if args.exclude_duplicates {
+        let duplicated = duplicates(&file_contents);
+        if !duplicated.is_empty() {
+            diagnostics.warn(format!(
+                "Left out {} files with the same content as another file",
+                duplicated.len()
+            ));
+        }
+        let originals: HashMap<usize, PathBuf> = duplicated
+            .iter()
+            .map(|(index, original)| (*index, file_contents[*original].display.clone()))
+            .collect();
+        let mut dropped = Vec::new();
+        for (index, file) in std::mem::take(&mut file_contents).into_iter().enumerate() {
+            match originals.get(&index) {
+                Some(original) => dropped.push((file, SkipReason::Duplicate(original.clone()))),
+                None => file_contents.push(file),
+            }
+        }
+        record_skips(
+            dropped,
+            &candidates,
+            &mut diagnostics,
+            &mut explained,
+            &mut totals,
+        );
+    }
+
+    // Drop the files that do not fit in the token budgets
+    let paths: Vec<PathBuf> = candidates
+        .iter()
+        .map(|candidate| workspace::relative_to(&candidate.path, &current_dir))
+        .collect();
+    let trimmed = budgets.apply(&mut file_contents, &paths, &languages, &priority);
+    for warning in trimmed.warnings {
+        diagnostics.warn(warning);
+    }
+    record_skips(
+        trimmed.dropped,
+        &candidates,
+        &mut diagnostics,
+        &mut explained,
+        &mut totals,
+    );
+    if let Some(part) = &mut part {
+        part.kept = file_contents.len();
+    }
+    for (file, entry) in file_contents.iter().enumerate() {
+        listed.insert(entry.source, Listed::Included(file));
+    }
+
+    // Build the tree in the order the paths were found, whatever the sort order
+    for (index, candidate) in candidates.iter().enumerate() {
+        if candidate.is_dir {
+            tree.add_dir(&candidate.display);
+        } else if let Some(listed) = listed.get(&index) {
+            match listed {
+                Listed::Included(file) => {
+                    let file = &file_contents[*file];
+                    let weight = Weight {
+                        lines: file.content.lines().count(),
+                        size: file.size,
+                        tokens: file.tokens,
+                    };
+                    tree.add_file(&candidate.display, false, Some(weight));
+                }
+                Listed::NonText => tree.add_file(&candidate.display, true, None),
+            }
+        }
+    }
+    for path in &ignored {
+        tree.add_ignored(&path.display, path.is_dir);
+    }
+    for link in &links {
+        let target = redactor.path(&link.target);
+        tree.add_link(&link.candidate.display, &target, &link.shown);
+    }
+    if let Some(snapshot) = &snapshot {
+        for (path, marker) in &snapshot.marked {
+            tree.add_marked(&redactor.path(&display_prefix.join(path)), marker);
+        }
+    }
+    tree.sort(args.tree_sort);
+
+    // Print only the tree annotated with sizes and tokens, for an audit
+    // The tree is shaded by depth only where it is shown in a terminal
+    let depth_colors = args.tree_depth_colors && std::env::var_os("NO_COLOR").is_none();
+    if args.dump_tree_sizes_only {
+        let rendered = tree.render(&RenderOptions {
+            line_counts: args.annotate_line_count,
+            sizes: true,
+            max_entries: args.max_tree_entries,
+            depth_colors: depth_colors && io::stdout().is_terminal(),
+            ..RenderOptions::default()
+        });
+        diagnostics.print()?;
+        print!("{}", rendered);
+        return Ok(());
+    }
+
+    // Tell apart files whose headers would be indistinguishable
+    if args.flatten {
+        collision::flatten(&mut file_contents);
+    }
+    for warning in collision::disambiguate(&mut file_contents) {
+        diagnostics.warn(warning);
+    }
+
+    let write_start = Instant::now();
+    // The output is rendered once, then written in each format asked for: the
+    // parts before the files, the section of each file, and the history after
+    // them
+    let mut out: Vec<u8> = Vec::new();
+
+    // Separate the appended section from the previous ones
+    if !existing.is_empty() {
+        let flags: Vec<String> = std::env::args().skip(1).collect();
+        writeln!(out, "{}", SEPARATOR)?;
+        writeln!(
+            out,
+            "Appended: {} {}",
+            current_dir.display(),
+            flags.join(" ")
+        )?;
+        writeln!(out, "{}", SEPARATOR)?;
+    }
+
+    // Print when the pack was written, for llmr merge to keep the newer copy
+    // of a file
+    if args.pack_metadata {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as i64);
+        writeln!(out, "{}", SEPARATOR)?;
+        writeln!(out, "Packed: {}", history::timestamp(seconds))?;
+        writeln!(out, "{}", SEPARATOR)?;
+    }
+
+    // Print the revision the files were read from
+    if let (Some(revision), Some(snapshot)) = (&args.git_ref, &snapshot) {
+        writeln!(out, "{}", SEPARATOR)?;
+        writeln!(out, "Revision: {} {}", revision, snapshot.commit)?;
+        writeln!(out, "{}", SEPARATOR)?;
+    }
+
+    // Print which slice of the files this part holds
+    if let Some(part) = &part {
+        writeln!(out, "{}", SEPARATOR)?;
+        write!(
+            out,
+            "Part: {}, files {}–{} of {}",
+            part.number,
+            part.sent + 1,
+            part.sent + part.kept,
+            part.total
+        )?;
+        if part.changed > 0 {
+            write!(out, " ({} changed since sent)", part.changed)?;
+        }
+        writeln!(out)?;
+        writeln!(out, "{}", SEPARATOR)?;
+    }
+
+    // Print the message of the HEAD commit, when in a repository with commits
+    if args.include_commit_message && !args.append_minimal {
+        if let Some(commit) = git::head_commit(&current_dir) {
+            let message = format!("{}\n", commit.message);
+            writeln!(out, "{}", SEPARATOR)?;
+            writeln!(
+                out,
+                "Commit: {}{}",
+                commit.id,
+                frame(message.as_bytes(), args.pack_metadata)
+            )?;
+            writeln!(out, "{}", SEPARATOR)?;
+            out.write_all(message.as_bytes())?;
+        }
+    }
+
     // Print the directory structure
-    println!("{}", tree_structure.trim_end());
+    let render_options = RenderOptions {
+        line_counts: args.annotate_line_count,
+        sizes: false,
+        collapse_binaries: args.collapse_binaries,
+        max_entries: args.max_tree_entries,
+        depth_colors: false,
+    };
+    let rendered = tree.render(&render_options);
+    let colored = depth_colors.then(|| {
+        tree.render(&RenderOptions {
+            depth_colors: true,
+            ..render_options.clone()
+        })
+    });
+    let mut tree_span = None;
+    if !args.flatten && !args.append_minimal {
+        if args.tree_to_stderr {
+            match &colored {
+                Some(colored) if io::stderr().is_terminal() => eprintln!("{}", colored.trim_end()),
+                _ => eprintln!("{}", rendered.trim_end()),
+            }
+        } else {
+            let start = out.len();
+            writeln!(out, "{}", rendered.trim_end())?;
+            tree_span = Some(start..out.len());
+        }
+    }
 
-    // Print all the file content
-    for (path, content) in &file_contents {
-        println!("==================================================");
-        println!(
-            "File: {}",
-            path.strip_prefix(&current_dir)
-                .with_context(|| format!("Failed to strip prefix for file: {}", path.display()))?
-                .display()
+    // Sum up the dependencies of the manifests, found whatever the filters
+    if args.include_deps {
+        let (manifests, warnings) = deps::find(&current_dir)?;
+        for warning in warnings {
+            diagnostics.warn(warning);
+        }
+        let manifests: Vec<deps::Manifest> = manifests
+            .into_iter()
+            .map(|manifest| deps::Manifest {
+                path: redactor.path(&display_prefix.join(&manifest.path)),
+                ..manifest
+            })
+            .collect();
+        if !manifests.is_empty() {
+            writeln!(out, "{}", SEPARATOR)?;
+            writeln!(out, "Dependencies")?;
+            writeln!(out, "{}", SEPARATOR)?;
+            write!(out, "{}", deps::render(&manifests))?;
+        }
+    }
+
+    // List the files each file imports, when there are few enough to read
+    if args.dep_graph {
+        let supported: Vec<&FileEntry> = file_contents
+            .iter()
+            .filter(|file| graph::is_supported(&candidates[file.source].path))
+            .collect();
+        if supported.len() > graph::MAX_FILES {
+            diagnostics.warn(format!(
+                "Warning: --dep-graph reads the imports of up to {} files, leaving out the graph of {}",
+                graph::MAX_FILES,
+                supported.len()
+            ));
+        } else {
+            let files: Vec<(&Path, &str)> = supported
+                .iter()
+                .map(|file| {
+                    (
+                        candidates[file.source].path.as_path(),
+                        file.content.as_str(),
+                    )
+                })
+                .collect();
+            let paths: Vec<PathBuf> = supported.iter().map(|file| file.display.clone()).collect();
+            let rendered = graph::render(&paths, &graph::edges(&files));
+            if !rendered.is_empty() {
+                writeln!(out, "{}", SEPARATOR)?;
+                writeln!(out, "Dependency graph")?;
+                writeln!(out, "{}", SEPARATOR)?;
+                write!(out, "{}", rendered)?;
+            }
+        }
+    }
+
+    // Files are gathered by language, the languages in the order of their first
+    // file, each starting with a header, keeping the sort order within them
+    let mut headings: HashMap<usize, String> = HashMap::new();
+    if args.group_by_language {
+        let language = |file: &FileEntry| languages.detect(&file.display).unwrap_or("other");
+        let mut order: Vec<&str> = Vec::new();
+        for file in &file_contents {
+            if !order.contains(&language(file)) {
+                order.push(language(file));
+            }
+        }
+        file_contents.sort_by_cached_key(|file| {
+            order
+                .iter()
+                .position(|other| *other == language(file))
+                .unwrap_or_default()
+        });
+        for (index, file) in file_contents.iter().enumerate() {
+            let previous = index
+                .checked_sub(1)
+                .map(|previous| &file_contents[previous]);
+            if previous.map(language) != Some(language(file)) {
+                headings.insert(index, language(file).to_string());
+            }
+        }
+    }
+
+    // Small files are gathered at the place of the first one, in order
+    let mut merged = 0..0;
+    if let Some(threshold) = args.merge_small_files {
+        let small = |file: &FileEntry| (file.content.len() as u64) < threshold;
+        if file_contents.iter().filter(|file| small(file)).count() > 1 {
+            let first = file_contents.iter().position(small).unwrap_or(0);
+            let rest = file_contents.split_off(first);
+            let (small, large): (Vec<FileEntry>, Vec<FileEntry>) =
+                rest.into_iter().partition(small);
+            merged = first..first + small.len();
+            file_contents.extend(small);
+            file_contents.extend(large);
+        }
+    }
+
+    // Render the file sections and the history after them up front, so that
+    // the bytes they take are known before writing them
+    let mut sections: Vec<Vec<u8>> = Vec::new();
+    let mut chunk_number = 0;
+    for (index, file) in file_contents.iter().enumerate() {
+        let mut section = Vec::new();
+        if let Some(language) = headings.get(&index) {
+            writeln!(section, "{}", SEPARATOR)?;
+            writeln!(section, "Language: {}", language)?;
+            writeln!(section, "{}", SEPARATOR)?;
+        }
+        if let (Some(size), Some(bpe)) = (args.chunk_tokens, &bpe) {
+            let overlap = args.split_tokens_overlap.unwrap_or(0);
+            for chunk in chunk::split(&file.content, size, overlap, bpe)? {
+                chunk_number += 1;
+                write_chunk(
+                    &mut section,
+                    file,
+                    &chunk,
+                    chunk_number,
+                    !args.no_trim,
+                    args.pack_metadata,
+                )?;
+            }
+        } else if merged.contains(&index) {
+            write_small_file(
+                &mut section,
+                file,
+                index == merged.start,
+                !args.no_trim,
+                args.pack_metadata,
+            )?;
+        } else if file.streamed {
+            let frame = match args.pack_metadata {
+                true => streamed_frame(&candidates[file.source].path, !args.no_trim)?,
+                false => String::new(),
+            };
+            write_header(
+                &mut section,
+                file,
+                args.file_index.then_some(index + 1),
+                &frame,
+            )?;
+        } else {
+            let number = args.file_index.then_some(index + 1);
+            write_file(
+                &mut section,
+                file,
+                number,
+                !args.no_trim,
+                args.pack_metadata,
+            )?;
+        }
+        sections.push(section);
+    }
+    let mut trailer: Vec<u8> = Vec::new();
+    if !binaries.is_empty() {
+        writeln!(trailer, "{}", SEPARATOR)?;
+        writeln!(trailer, "Binary files")?;
+        writeln!(trailer, "{}", SEPARATOR)?;
+        for (path, size) in &binaries {
+            writeln!(
+                trailer,
+                "{} ({})",
+                path.display(),
+                args.size_format.render(*size)
+            )?;
+        }
+    }
+    if let Some(count) = args.include_git_log {
+        if let Some(commits) = git::recent_commits(&current_dir, count) {
+            writeln!(trailer, "{}", SEPARATOR)?;
+            writeln!(trailer, "Git log")?;
+            writeln!(trailer, "{}", SEPARATOR)?;
+            for commit in &commits {
+                writeln!(
+                    trailer,
+                    "{} {} ({})",
+                    commit.id, commit.summary, commit.author
+                )?;
+            }
+        }
+    }
+
+    // Keep the files that fit in the output size limit, in order, each taking
+    // its section and its line of the index
+    if let Some(limit) = args.max_output_bytes {
+        let index_header = if args.file_index {
+            2 * (SEPARATOR.len() + 1) + "Index\n".len()
+        } else {
+            0
+        };
+        let mut used = out.len() + index_header + trailer.len();
+        if used as u64 > limit {
+            bail!(
+                "The tree and the sections around the files take {} bytes, over the output size limit ({} bytes): raise it, or shorten the tree with --max-tree-entries or --flatten",
+                used,
+                limit
+            );
+        }
+        let mut kept = 0;
+        for (file, section) in file_contents.iter().zip(&sections) {
+            let mut size = section.len();
+            if args.file_index {
+                size += index_line(kept + 1, file).len();
+            }
+            if (used + size) as u64 > limit {
+                break;
+            }
+            used += size;
+            kept += 1;
+        }
+
+        sections.truncate(kept);
+        let dropped = file_contents.split_off(kept);
+        if !dropped.is_empty() {
+            diagnostics.warn(format!(
+                "Dropped {} files to fit the output size limit ({} bytes)",
+                dropped.len(),
+                limit
+            ));
+        }
+        record_skips(
+            dropped
+                .into_iter()
+                .map(|file| (file, SkipReason::MaxOutputBytes(limit))),
+            &candidates,
+            &mut diagnostics,
+            &mut explained,
+            &mut totals,
         );
-        println!("==================================================");
-        println!("{}", content.trim_end());
     }
 
-    // Print the errors
-    for error in &errors {
-        eprintln!("{}", error);
+    // Print the explanations, in the order requested, once every limit applied
+    if args.explain_all {
+        for (path, decision) in &explained {
+            explain::print(&current_dir, path, &explain::render(decision));
+        }
+    }
+    for target in &explain_targets {
+        if args.explain_all && explained.iter().any(|(path, _)| path == target) {
+            continue;
+        }
+        let explanation = match explained.iter().find(|(path, _)| path == target) {
+            Some((_, decision)) => explain::render(decision),
+            None => explain::unvisited(&current_dir, target, &walk_options),
+        };
+        explain::print(&current_dir, target, &explanation);
     }
 
-    if args.report {
-        // Combine all the content of the files in a String
+    // Print the index of the files, numbered in the order they are printed
+    if args.file_index {
+        writeln!(out, "{}", SEPARATOR)?;
+        writeln!(out, "Index")?;
+        writeln!(out, "{}", SEPARATOR)?;
+        for (number, file) in file_contents.iter().enumerate() {
+            write!(out, "{}", index_line(number + 1, file))?;
+        }
+    }
+
+    // Print all the file content, then the recent history
+    let mut text = out.clone();
+    for section in &sections {
+        text.extend_from_slice(section);
+    }
+    text.extend_from_slice(&trailer);
+
+    // The updated pack must read back as written, the content of streamed
+    // files aside as it is not in memory
+    let streaming = file_contents.iter().any(|file| file.streamed);
+    if let (Some(_), Some(output), false) = (&previous, args.output.first(), streaming) {
+        let text = String::from_utf8_lossy(&text);
+        if let Some(problem) = pack::sections(&text, output)?
+            .iter()
+            .find_map(Section::problem)
+        {
+            bail!(
+                "Refusing to update {}: the new version would not read back as written ({})",
+                output.display(),
+                problem
+            );
+        }
+    }
+
+    let streamed: Vec<(&[u8], Option<&Path>)> = file_contents
+        .iter()
+        .zip(&sections)
+        .map(|(file, section)| {
+            let path = candidates[file.source].path.as_path();
+            (section.as_slice(), file.streamed.then_some(path))
+        })
+        .collect();
+    let mut cached_outputs = Vec::new();
+    for (format, output) in &targets {
+        // The tree is shaded only in the text shown in a terminal
+        let head: Cow<[u8]> = match (&colored, &tree_span) {
+            (Some(colored), Some(span))
+                if output.is_none()
+                    && *format == OutputFormat::Text
+                    && io::stdout().is_terminal() =>
+            {
+                let mut head = out[..span.start].to_vec();
+                writeln!(head, "{}", colored.trim_end())?;
+                head.extend_from_slice(&out[span.end..]);
+                Cow::Owned(head)
+            }
+            _ => Cow::Borrowed(&out),
+        };
+        if streaming && *format == OutputFormat::Text {
+            write_streamed(
+                *output,
+                &head,
+                &streamed,
+                &trailer,
+                args.append,
+                !args.no_trim,
+            )?;
+            continue;
+        }
+        let bytes = match format {
+            OutputFormat::Text if matches!(head, Cow::Owned(_)) => {
+                let mut bytes = head.into_owned();
+                for section in &sections {
+                    bytes.extend_from_slice(section);
+                }
+                bytes.extend_from_slice(&trailer);
+                bytes
+            }
+            OutputFormat::Text => text.clone(),
+            // Wrap the text in messages, escaped by the serializer, with each
+            // file apart when they are split
+            OutputFormat::Chat => {
+                let parts = if args.chat_split_files {
+                    let mut rest = out.clone();
+                    rest.extend_from_slice(&trailer);
+                    std::iter::once(&rest)
+                        .chain(&sections)
+                        .map(|part| String::from_utf8_lossy(part).into_owned())
+                        .collect()
+                } else {
+                    vec![String::from_utf8_lossy(&text).into_owned()]
+                };
+                let messages =
+                    chat::messages(args.prompt.as_deref(), parts, args.instructions.as_deref());
+                (chat::to_json(&messages)? + "\n").into_bytes()
+            }
+            // Give each file apart, its content as emitted in the text
+            OutputFormat::Yaml => {
+                let files = file_contents
+                    .iter()
+                    .map(|file| yaml::File {
+                        path: header_path(file),
+                        language: languages.detect(&file.display).map(str::to_string),
+                        tokens: file.tokens.unwrap_or_default(),
+                        content: match args.no_trim {
+                            true => file.content.clone(),
+                            false => file.content.trim_end().to_string(),
+                        },
+                    })
+                    .collect();
+                let document = yaml::Document {
+                    root: tree_root.clone(),
+                    tree: (!args.flatten).then(|| rendered.trim_end().to_string()),
+                    files,
+                };
+                yaml::to_yaml(&document)?.into_bytes()
+            }
+            OutputFormat::Json => unreachable!("--format json requires --dry-run"),
+        };
+
+        // Clipboards and paste services may silently truncate a large output
+        let written = bytes.len() as u64;
+        if output.is_none() && args.max_output_bytes.is_none() && written > LARGE_OUTPUT_BYTES {
+            diagnostics.warn(format!(
+                "Warning: wrote {} to stdout, which clipboards and paste services may truncate (use --max-output-bytes to cap it)",
+                format_size(written)
+            ));
+        }
+        write_output(*output, &bytes, args.append)?;
+        if output_cache.is_some() {
+            cached_outputs.push((output.cloned(), bytes));
+        }
+    }
+    timings.add(Phase::Write, write_start.elapsed());
+
+    // Keep the output for the next run, unless it is too large to
+    if let Some(path) = &output_cache {
+        let size: usize = cached_outputs.iter().map(|(_, bytes)| bytes.len()).sum();
+        let outputs: Option<Vec<CachedOutput>> = cached_outputs
+            .into_iter()
+            .map(|(path, bytes)| {
+                Some(CachedOutput {
+                    path,
+                    content: String::from_utf8(bytes).ok()?,
+                })
+            })
+            .collect();
+        match outputs {
+            Some(_) if size > cache::MAX_CACHE_BYTES => {
+                let _ = fs::remove_file(path);
+                diagnostics.warn(format!(
+                    "Warning: the output is over {}, not caching it for --resume-from-cache",
+                    format_size(cache::MAX_CACHE_BYTES as u64)
+                ));
+            }
+            Some(outputs) => {
+                let cached = OutputCache {
+                    key: run_key,
+                    files: run_stamps,
+                    outputs,
+                };
+                cache::save(path, &cached)?;
+            }
+            None => {
+                let _ = fs::remove_file(path);
+                diagnostics.warn(
+                    "Warning: the output is not valid UTF-8, not caching it for --resume-from-cache"
+                        .to_string(),
+                );
+            }
+        }
+    }
+
+    // Sections are rendered the same from the same files, so only those of
+    // the files that changed differ from the previous version
+    if let (Some(previous), Some(output)) = (&previous, args.output.first()) {
+        let changes = pack::changes(previous, &String::from_utf8_lossy(&text), output)?;
+        diagnostics.warn(format!(
+            "Updated {}: {} files changed, {} added, {} removed, {} unchanged",
+            output.display(),
+            changes.changed,
+            changes.added,
+            changes.removed,
+            changes.unchanged
+        ));
+    }
+
+    // Assemble the prompt from the same tree and files
+    if let Some(path) = &args.prompt_file {
+        let mut files = Vec::new();
+        write_files(
+            &mut files,
+            &file_contents,
+            args.file_index,
+            !args.no_trim,
+            false,
+        )?;
+        let summary = format!(
+            "{} files, {}, in {}",
+            file_contents.len(),
+            args.size_format.render(totals.size),
+            language_counts(&file_contents, &languages)
+        );
+        let directory = current_dir.display().to_string();
+        let sections = prompt::Sections {
+            directory: &directory,
+            summary: &summary,
+            tree: &rendered,
+            files: &String::from_utf8_lossy(&files),
+        };
+        let assembled = prompt::render(&template, &sections)?;
+        fs::write(path, &assembled)
+            .with_context(|| format!("Failed to write prompt file: {}", path.display()))?;
+        let tokens = timings.time(Phase::Tokenize, || match &bpe {
+            Some(bpe) => bpe.encode_ordinary(&assembled).len(),
+            None => 0,
+        });
+        diagnostics.warn(format!(
+            "Prompt written to {} ({} tokens)",
+            path.display(),
+            tokens
+        ));
+    }
+
+    // Write the JSON report, with paths that a later run can replay
+    if let Some(path) = &args.report_json {
+        let files: Vec<ReportFile> = file_contents
+            .iter()
+            .map(|file| ReportFile {
+                path: workspace::relative_to(&candidates[file.source].path, &current_dir),
+                language: languages.detect(&file.display).map(str::to_string),
+                size: file.size,
+                lines: file.content.lines().count(),
+                bytes: file.content.len(),
+                tokens: file.tokens,
+            })
+            .collect();
+        let skipped = candidates
+            .iter()
+            .enumerate()
+            .filter(|(index, candidate)| {
+                !candidate.is_dir && !matches!(listed.get(index), Some(Listed::Included(_)))
+            })
+            .map(|(_, candidate)| workspace::relative_to(&candidate.path, &current_dir))
+            .collect();
+        let report = Report {
+            directory: current_dir.clone(),
+            revision: snapshot.as_ref().map(|snapshot| snapshot.commit.clone()),
+            estimated_tokens: files.iter().filter_map(|file| file.tokens).sum(),
+            files,
+            skipped,
+            total_size: totals.size,
+            tree: args
+                .json_tree
+                .text()
+                .then(|| rendered.trim_end().to_string()),
+            tree_object: args.json_tree.object().then(|| TreeEntry::from(&tree.root)),
+        };
+        report::write(path, &report)?;
+    }
+
+    // Hash the files as emitted, and the whole output, to tell later whether a
+    // dump still matches
+    let mut drifted = 0;
+    if args.content_hash_manifest.is_some() || args.compare_manifest.is_some() {
+        let hashes = HashManifest::new(
+            current_dir.clone(),
+            manifest::hash(&text),
+            file_contents
+                .iter()
+                .map(|file| FileHash {
+                    path: workspace::relative_to(&candidates[file.source].path, &current_dir),
+                    hash: manifest::hash(file.content.as_bytes()),
+                })
+                .collect(),
+        );
+        if let Some(path) = &args.compare_manifest {
+            let previous = manifest::read(path)?;
+            let drift = manifest::drift(&previous, &hashes);
+            drifted = drift.len();
+            for line in drift {
+                diagnostics.warn(line);
+            }
+            if drifted == 0 && previous.hash != hashes.hash {
+                diagnostics.warn(format!(
+                    "The files match {}, but the output differs, such as in its tree or options",
+                    path.display()
+                ));
+            }
+        }
+        if let Some(path) = &args.content_hash_manifest {
+            manifest::write(path, &hashes)?;
+        }
+    }
+
+    // Record the files of this part for the next run
+    if let (Some(state), Some(part), Some(path)) = (&mut state, &part, &args.state) {
+        for file in &file_contents {
+            state.record(&file.display, &file.content, part.number);
+        }
+        state.parts = part.number;
+        state.complete = part.sent + part.kept == part.total;
+        state::write(path, state)?;
+        if state.complete {
+            diagnostics.warn(format!(
+                "Wrote the last part ({}), all the files were sent",
+                part.number
+            ));
+        } else {
+            diagnostics.warn(format!(
+                "Wrote part {}, run again to write the next one",
+                part.number
+            ));
+        }
+    }
+
+    // Print the skipped files and warnings
+    diagnostics.print()?;
+
+    // Estimate the tokens of all the content of the files combined
+    let mut estimated_tokens = 0;
+    if args.report || args.report_line {
         let combined_content = file_contents
             .iter()
-            .map(|(_, content)| content.as_str())
+            .map(|file| file.content.as_str())
             .collect::<Vec<&str>>()
             .join("");
+        estimated_tokens = timings.time(Phase::Tokenize, || match &bpe {
+            Some(bpe) => bpe.encode_ordinary(&combined_content).len(),
+            None => 0,
+        });
+    }
 
-        // Estimate tokens
-        let bpe: CoreBPE = cl100k_base().context("Failed to get BPE tokenizer")?;
-        let estimated_tokens = bpe.encode_ordinary(&combined_content).len();
+    // Print the summary line, the same across runs over the same files
+    if args.report_line {
+        println!(
+            "llmr: {} files, {}, ~{} tokens (cl100k_base)",
+            totals.files,
+            compact(totals.size as f64, 1024.0, ["B", "K", "M", "G"]),
+            compact(estimated_tokens as f64, 1000.0, ["", "k", "M", "B"])
+        );
+    }
 
+    if args.report {
         let elapsed_time = start_time.elapsed();
 
         // Print the report at the end
-        println!("Analyzing: {}", current_dir.display());
-        println!("Files analyzed: {}", total_files);
+        println!("Analyzing: {}", redactor.path(&current_dir).display());
+        if let Some(snapshot) = &snapshot {
+            println!("Revision: {}", snapshot.commit);
+        }
+        println!("Files analyzed: {}", totals.files);
+        println!("Languages: {}", language_counts(&file_contents, &languages));
+        if args.languages_report {
+            print!(
+                "{}",
+                language_table(&file_contents, &languages, args.size_format)
+            );
+        }
+        if let Some((repo_root, codeowners)) = &codeowners {
+            println!(
+                "Owners: {}",
+                owner_counts(&file_contents, &candidates, codeowners, repo_root)
+            );
+        }
+        println!("Total size: {}", args.size_format.render(totals.size));
         println!("Estimated tokens: {}", estimated_tokens);
-        println!("Time elapsed: {:.2?}", elapsed_time);
+        let streamed = file_contents.iter().filter(|file| file.streamed).count();
+        if streamed > 0 {
+            println!("Files streamed, without their tokens: {}", streamed);
+        }
+        if !budgets.dirs.is_empty() {
+            println!("Directory budgets:");
+            for budget in &budgets.dirs {
+                println!(
+                    "    {}: {} / {} tokens",
+                    budget.dir.display(),
+                    budget.used,
+                    budget.budget
+                );
+            }
+        }
+        if !budgets.languages.is_empty() {
+            println!("Language budgets:");
+            for budget in &budgets.languages {
+                println!(
+                    "    {}: {} / {} tokens",
+                    budget.language, budget.used, budget.budget
+                );
+            }
+        }
+        // Identical files are all emitted, but are worth pointing out as
+        // redundancy to trim
+        let duplicated = duplicates(&file_contents);
+        if !duplicated.is_empty() {
+            let tokens: usize = duplicated
+                .iter()
+                .filter_map(|(index, _)| file_contents[*index].tokens)
+                .sum();
+            println!(
+                "Duplicate files: {} ({} tokens), same content as another file:",
+                duplicated.len(),
+                tokens
+            );
+            for (index, original) in &duplicated {
+                println!(
+                    "    {} = {}",
+                    header_path(&file_contents[*index]),
+                    header_path(&file_contents[*original])
+                );
+            }
+        }
+        if args.token_histogram {
+            let tokens: Vec<usize> = file_contents
+                .iter()
+                .filter_map(|file| file.tokens)
+                .collect();
+            println!("Files by tokens:");
+            print!("{}", histogram::render(&tokens));
+        }
+        if args.dedent {
+            let saved = timings.time(Phase::Tokenize, || match &bpe {
+                Some(bpe) => dedent_savings(&file_contents, bpe),
+                None => 0,
+            });
+            println!("Tokens saved by --dedent: {}", saved);
+        }
+        if args.merge_small_files.is_some() {
+            let saved = timings.time(Phase::Tokenize, || match &bpe {
+                Some(bpe) => merge_savings(&file_contents[merged.clone()], bpe),
+                None => 0,
+            });
+            println!("Tokens saved by --merge-small-files: {}", saved);
+        }
+        if args.preview_lines.is_some() {
+            println!("Tokens saved by --preview-lines: {}", preview_saved);
+        }
+        if args.strip_license_headers {
+            let saved = timings.time(Phase::Tokenize, || match &bpe {
+                Some(bpe) => license_savings(&file_contents, bpe),
+                None => 0,
+            });
+            println!("Tokens saved by --strip-license-headers: {}", saved);
+        }
+        if !existing.is_empty() {
+            let existing_tokens = timings.time(Phase::Tokenize, || match &bpe {
+                Some(bpe) => bpe.encode_ordinary(&existing).len(),
+                None => 0,
+            });
+            println!(
+                "Estimated tokens of the combined file: {}",
+                existing_tokens + estimated_tokens
+            );
+        }
+        if !args.no_timing {
+            println!("Time elapsed: {:.2?}", elapsed_time);
+        }
+    }
+
+    if args.timings {
+        timings.print();
+    }
+
+    if let Some(path) = &args.compare_manifest {
+        if drifted > 0 {
+            bail!("{} files drifted from {}", drifted, path.display());
+        }
     }
 
     Ok(())
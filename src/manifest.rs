@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The hash algorithm of the manifests written, recorded in them.
+const ALGORITHM: &str = "sha256";
+
+/// The hashes written with `--content-hash-manifest`, and compared against by
+/// `--compare-manifest`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HashManifest {
+    /// The hash algorithm, missing from the manifests of older versions, which
+    /// used a 64-bit hash.
+    #[serde(default)]
+    pub algorithm: String,
+    /// The directory the run started from.
+    pub directory: PathBuf,
+    /// Hash of the whole text output, tree and headers included.
+    pub hash: String,
+    /// The files included, in the order they were emitted.
+    pub files: Vec<FileHash>,
+}
+
+/// The hash of the content of a file, as emitted.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileHash {
+    /// Path relative to the directory the run started from.
+    pub path: PathBuf,
+    pub hash: String,
+}
+
+/// SHA-256 of a content, in hexadecimal.
+pub fn hash(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+impl HashManifest {
+    pub fn new(directory: PathBuf, hash: String, files: Vec<FileHash>) -> Self {
+        HashManifest {
+            algorithm: ALGORITHM.to_string(),
+            directory,
+            hash,
+            files,
+        }
+    }
+}
+
+pub fn write(path: &Path, manifest: &HashManifest) -> Result<()> {
+    let json =
+        serde_json::to_string_pretty(manifest).context("Failed to serialize hash manifest")?;
+    fs::write(path, json + "\n")
+        .with_context(|| format!("Failed to write hash manifest: {}", path.display()))
+}
+
+pub fn read(path: &Path) -> Result<HashManifest> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read hash manifest: {}", path.display()))?;
+    let manifest: HashManifest = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse hash manifest: {}", path.display()))?;
+    check(&manifest, path)?;
+    Ok(manifest)
+}
+
+/// Refuse a manifest whose hashes cannot be compared with those of this run.
+fn check(manifest: &HashManifest, path: &Path) -> Result<()> {
+    if manifest.algorithm != ALGORITHM {
+        bail!(
+            "Hash manifest {} was written by an older llmr, write it again with --content-hash-manifest",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Describe how the files of a run drifted from those of a manifest, one line
+/// per file changed, added or removed, in the order of the current run then of
+/// the manifest.
+pub fn drift(previous: &HashManifest, current: &HashManifest) -> Vec<String> {
+    let hashes: HashMap<&Path, &str> = previous
+        .files
+        .iter()
+        .map(|file| (file.path.as_path(), file.hash.as_str()))
+        .collect();
+
+    let mut lines = Vec::new();
+    for file in &current.files {
+        match hashes.get(file.path.as_path()) {
+            Some(hash) if *hash == file.hash => {}
+            Some(_) => lines.push(format!("Changed: {}", file.path.display())),
+            None => lines.push(format!("Added: {}", file.path.display())),
+        }
+    }
+    for file in &previous.files {
+        if !current.files.iter().any(|other| other.path == file.path) {
+            lines.push(format!("Removed: {}", file.path.display()));
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(files: &[(&str, &str)]) -> HashManifest {
+        HashManifest::new(
+            PathBuf::from("/project"),
+            hash(b""),
+            files
+                .iter()
+                .map(|(path, content)| FileHash {
+                    path: PathBuf::from(path),
+                    hash: hash(content.as_bytes()),
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn hashes_are_sha256() {
+        assert_eq!(
+            hash(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn drift_lists_changed_added_and_removed_files() {
+        let previous = manifest(&[("a.rs", "a"), ("b.rs", "b")]);
+        let current = manifest(&[("a.rs", "changed"), ("c.rs", "c")]);
+        assert_eq!(
+            drift(&previous, &current),
+            ["Changed: a.rs", "Added: c.rs", "Removed: b.rs"]
+        );
+    }
+
+    #[test]
+    fn manifests_of_older_versions_are_refused() {
+        let path = Path::new("context.hashes.json");
+        assert!(check(&manifest(&[("a.rs", "a")]), path).is_ok());
+
+        // The field is missing from older manifests
+        let older = HashManifest {
+            algorithm: String::new(),
+            ..manifest(&[("a.rs", "a")])
+        };
+        let error = check(&older, path).unwrap_err();
+        assert!(error.to_string().contains("older llmr"));
+    }
+}
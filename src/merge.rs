@@ -0,0 +1,484 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use tiktoken_rs::cl100k_base;
+
+use crate::collision;
+use crate::pack::{self, Header, Section};
+use crate::tree::{RenderOptions, Tree, Weight};
+use crate::workspace::{normalize, relative_to};
+use crate::{write_files, FileEntry, SEPARATOR};
+
+/// Arguments of `llmr merge`.
+#[derive(Args, Debug)]
+pub struct MergeArgs {
+    /// Files written by llmr to merge. A file found in several of them is kept
+    /// from the one written last, with a warning when the copies differ.
+    #[arg(required = true, value_name = "PACK")]
+    inputs: Vec<PathBuf>,
+    /// Write the merged output to a file instead of stdout.
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+}
+
+/// A file read back from a pack.
+struct PackedFile {
+    /// Path of the file, absolute if the pack tells the directory it was
+    /// written from.
+    path: PathBuf,
+    content: String,
+    /// The hash of the content, as given by its header if it has a frame.
+    hash: String,
+    /// Whether its header has a frame, written with `--pack-metadata`.
+    framed: bool,
+    /// When its section of the pack was written, given by `--pack-metadata`.
+    packed: Option<String>,
+    /// The pack the file comes from.
+    input: usize,
+}
+
+/// Read the files of a pack, resolving their paths against the root of the
+/// tree of their section when there is one. A pack whose sections no longer
+/// match their frame is rejected.
+fn parse(text: &str, input: usize, path: &Path) -> Result<Vec<PackedFile>> {
+    let sections = pack::sections(text, path)?;
+    if let Some(problem) = sections.iter().find_map(Section::problem) {
+        bail!("Cannot merge {}: {}", path.display(), problem);
+    }
+
+    let mut files = Vec::new();
+    let mut root: Option<PathBuf> = None;
+    let mut packed: Option<&str> = None;
+    for section in sections {
+        match section.header {
+            // The number given by --file-index is dropped, as files are
+            // renumbered
+            Some(Header::File { path: display, .. } | Header::SmallFile { path: display, .. }) => {
+                let display = Path::new(display);
+                files.push(PackedFile {
+                    path: match &root {
+                        Some(root) => normalize(&root.join(display)),
+                        None => display.to_path_buf(),
+                    },
+                    content: section.body.to_string(),
+                    hash: match section.frame {
+                        Some(frame) => frame.hash.to_string(),
+                        None => pack::frame_hash(section.body),
+                    },
+                    framed: section.frame.is_some(),
+                    packed: packed.map(str::to_string),
+                    input,
+                });
+                continue;
+            }
+            Some(Header::Appended) => (root, packed) = (None, None),
+            Some(Header::Packed { time }) => packed = Some(time),
+            _ => {}
+        }
+
+        // The root of the tree is the directory the section was written from
+        for line in section.lines() {
+            if let Some(name) = line.strip_prefix("└── ") {
+                if root.is_none() && Path::new(name).is_absolute() {
+                    root = Some(PathBuf::from(name));
+                }
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// The deepest directory containing all the absolute paths.
+fn common_root(paths: &[&Path]) -> Option<PathBuf> {
+    let mut paths = paths.iter().filter(|path| path.is_absolute());
+    let mut root = paths.next()?.parent()?.to_path_buf();
+    for path in paths {
+        while !path.starts_with(&root) {
+            if !root.pop() {
+                break;
+            }
+        }
+    }
+    Some(root)
+}
+
+/// The result of merging packs.
+struct Merged {
+    text: Vec<u8>,
+    files: usize,
+    /// Copies of files dropped for a later one.
+    duplicates: usize,
+    warnings: Vec<String>,
+}
+
+/// The directory under which the files of each pack are kept when packs
+/// written from relative roots hold the same paths: the name of the pack
+/// without its extension, or its whole path if names are shared.
+fn prefixes(packs: &[(PathBuf, String)]) -> Vec<PathBuf> {
+    let stems: Vec<PathBuf> = packs
+        .iter()
+        .map(|(path, _)| PathBuf::from(path.file_stem().unwrap_or_default()))
+        .collect();
+    let unique = stems
+        .iter()
+        .enumerate()
+        .all(|(index, stem)| !stems[..index].contains(stem));
+    match unique {
+        true => stems,
+        false => packs
+            .iter()
+            .map(|(path, _)| {
+                path.with_extension("")
+                    .components()
+                    .filter(|component| matches!(component, Component::Normal(_)))
+                    .collect()
+            })
+            .collect(),
+    }
+}
+
+/// Place the files of packs written from relative roots under a directory of
+/// their own when two of them hold the same path, as nothing tells whether
+/// they come from the same directory.
+fn separate(
+    packs: &[(PathBuf, String)],
+    files: &mut [Vec<PackedFile>],
+    warnings: &mut Vec<String>,
+) {
+    let mut first: HashMap<&Path, usize> = HashMap::new();
+    let mut collision = None;
+    for (input, files) in files.iter().enumerate() {
+        for file in files.iter().filter(|file| file.path.is_relative()) {
+            match first.get(file.path.as_path()) {
+                Some(&other) if other != input => {
+                    collision.get_or_insert((file.path.clone(), other, input));
+                }
+                _ => {
+                    first.insert(&file.path, input);
+                }
+            }
+        }
+    }
+    let Some((path, first, second)) = collision else {
+        return;
+    };
+
+    warnings.push(format!(
+        "Warning: {} is found in {} and {}, written from relative roots, keeping the files of each under the name of its pack (write them with --absolute-root to merge them by path)",
+        path.display(),
+        packs[first].0.display(),
+        packs[second].0.display()
+    ));
+    for (files, prefix) in files.iter_mut().zip(prefixes(packs)) {
+        for file in files.iter_mut().filter(|file| file.path.is_relative()) {
+            file.path = prefix.join(&file.path);
+        }
+    }
+}
+
+/// Merge packs, given by their path and content, into one, keeping the copy
+/// written last of files found in several of them.
+fn merge(packs: &[(PathBuf, String)]) -> Result<Merged> {
+    let mut parsed = Vec::new();
+    for (input, (path, text)) in packs.iter().enumerate() {
+        parsed.push(parse(text, input, path)?);
+    }
+    let mut warnings = Vec::new();
+    separate(packs, &mut parsed, &mut warnings);
+
+    let mut files: Vec<PackedFile> = Vec::new();
+    let mut duplicates = 0;
+    for file in parsed.into_iter().flatten() {
+        let Some(other) = files.iter_mut().find(|other| other.path == file.path) else {
+            files.push(file);
+            continue;
+        };
+        duplicates += 1;
+
+        // The newer copy replaces the other in place, keeping the first
+        // order. Without the time of both packs, the last one given is taken
+        // as the newer
+        let (older, newer) = (&packs[other.input].0, &packs[file.input].0);
+        let (kept, reason) = match (&other.packed, &file.packed) {
+            (Some(old), Some(new)) if old > new => (older, "written later"),
+            (Some(_), Some(_)) => (newer, "written later"),
+            _ => (
+                newer,
+                "given last, the packs not telling when they were written",
+            ),
+        };
+        if other.hash != file.hash {
+            warnings.push(format!(
+                "Warning: {} differs between {} and {}, keeping the copy from {}, {}",
+                file.path.display(),
+                older.display(),
+                newer.display(),
+                kept.display(),
+                reason
+            ));
+        }
+        if kept == newer {
+            *other = file;
+        }
+    }
+
+    // The merged pack has frames only if all the files had one
+    let framed = files.iter().all(|file| file.framed);
+
+    // Show the paths relative to the directory containing them all
+    let paths: Vec<&Path> = files.iter().map(|file| file.path.as_path()).collect();
+    let root = common_root(&paths);
+    let mut entries: Vec<FileEntry> = files
+        .into_iter()
+        .enumerate()
+        .map(|(source, file)| FileEntry {
+            source,
+            display: match &root {
+                Some(root) if file.path.is_absolute() => relative_to(&file.path, root),
+                _ => file.path,
+            },
+            tag: None,
+            size: file.content.len() as u64,
+            tokens: None,
+            content: file.content,
+            indent: String::new(),
+            license: String::new(),
+            streamed: false,
+        })
+        .collect();
+
+    let root_name = match &root {
+        Some(root) => root.display().to_string(),
+        None => ".".to_string(),
+    };
+    let mut tree = Tree::new(&root_name);
+    for entry in &entries {
+        let weight = Weight {
+            lines: entry.content.lines().count(),
+            size: entry.size,
+            tokens: None,
+        };
+        tree.add_file(&entry.display, false, Some(weight));
+    }
+    warnings.extend(collision::disambiguate(&mut entries));
+
+    let inputs: Vec<String> = packs
+        .iter()
+        .map(|(path, _)| path.display().to_string())
+        .collect();
+    let mut text = Vec::new();
+    writeln!(text, "{}", SEPARATOR)?;
+    writeln!(text, "Merged: {}", inputs.join(" "))?;
+    writeln!(text, "{}", SEPARATOR)?;
+    writeln!(
+        text,
+        "{}",
+        tree.render(&RenderOptions::default()).trim_end()
+    )?;
+    write_files(&mut text, &entries, false, true, framed)?;
+
+    Ok(Merged {
+        text,
+        files: entries.len(),
+        duplicates,
+        warnings,
+    })
+}
+
+/// Merge packs into one, keeping the copy written last of files found in
+/// several of them, and print the number of tokens of the result.
+pub fn run(args: &MergeArgs) -> Result<()> {
+    let mut packs = Vec::new();
+    for path in &args.inputs {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read pack: {}", path.display()))?;
+        packs.push((path.clone(), text));
+    }
+    let merged = merge(&packs)?;
+    for warning in &merged.warnings {
+        eprintln!("{}", warning);
+    }
+
+    match &args.output {
+        Some(output) => fs::write(output, &merged.text)
+            .with_context(|| format!("Failed to write output file: {}", output.display()))?,
+        None => io::stdout()
+            .write_all(&merged.text)
+            .context("Failed to write output")?,
+    }
+
+    let bpe = cl100k_base().context("Failed to get BPE tokenizer")?;
+    let tokens = bpe
+        .encode_ordinary(&String::from_utf8_lossy(&merged.text))
+        .len();
+    eprintln!(
+        "Merged {} files from {} packs ({} duplicates dropped)",
+        merged.files,
+        args.inputs.len(),
+        merged.duplicates
+    );
+    eprintln!("Estimated tokens: {}", tokens);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A pack of files written from `/project`, as llmr writes it.
+    fn pack(files: &[(&str, &str)]) -> String {
+        written("/project", None, files)
+    }
+
+    /// A pack of files written from a root at a time, if given.
+    fn written(root: &str, time: Option<&str>, files: &[(&str, &str)]) -> String {
+        let entries: Vec<FileEntry> = files
+            .iter()
+            .enumerate()
+            .map(|(source, (path, content))| FileEntry {
+                source,
+                display: PathBuf::from(path),
+                tag: None,
+                size: content.len() as u64,
+                tokens: None,
+                content: content.to_string(),
+                indent: String::new(),
+                license: String::new(),
+                streamed: false,
+            })
+            .collect();
+        let mut text = Vec::new();
+        if let Some(time) = time {
+            writeln!(text, "{}\nPacked: {}\n{}", SEPARATOR, time, SEPARATOR).unwrap();
+        }
+        writeln!(text, "└── {}", root).unwrap();
+        write_files(&mut text, &entries, false, true, true).unwrap();
+        String::from_utf8(text).unwrap()
+    }
+
+    fn contents(text: &str) -> Vec<(PathBuf, String)> {
+        parse(text, 0, Path::new("merged.txt"))
+            .unwrap()
+            .into_iter()
+            .map(|file| (file.path, file.content))
+            .collect()
+    }
+
+    #[test]
+    fn round_trip_keeps_files_whole() {
+        // Content shaped like a header must stay in its file
+        let readme = format!("{}\nFile: fake.rs\n{}\nfake\n", SEPARATOR, SEPARATOR);
+        let text = pack(&[("README.md", &readme), ("src/main.rs", "fn main() {}\n")]);
+
+        let merged = merge(&[(PathBuf::from("a.txt"), text.clone())]).unwrap();
+        assert!(merged.warnings.is_empty());
+        assert_eq!(
+            contents(&String::from_utf8(merged.text).unwrap()),
+            contents(&text)
+        );
+        assert_eq!(contents(&text)[0].1, readme);
+    }
+
+    #[test]
+    fn differing_copies_are_pointed_out() {
+        let old = pack(&[("a.rs", "old\n"), ("b.rs", "same\n")]);
+        let new = pack(&[("a.rs", "new\n"), ("b.rs", "same\n")]);
+
+        let merged = merge(&[
+            (PathBuf::from("old.txt"), old),
+            (PathBuf::from("new.txt"), new),
+        ])
+        .unwrap();
+        assert_eq!((merged.files, merged.duplicates), (2, 2));
+        assert_eq!(merged.warnings.len(), 1);
+        assert!(merged.warnings[0].contains("a.rs differs between old.txt and new.txt"));
+
+        let files = contents(&String::from_utf8(merged.text).unwrap());
+        assert_eq!(
+            files[0],
+            (PathBuf::from("/project/a.rs"), "new\n".to_string())
+        );
+    }
+
+    #[test]
+    fn copy_written_last_is_kept() {
+        let old = written(
+            "/project",
+            Some("2026-01-01T00:00:00Z"),
+            &[("a.rs", "old\n")],
+        );
+        let new = written(
+            "/project",
+            Some("2026-02-01T00:00:00Z"),
+            &[("a.rs", "new\n")],
+        );
+
+        // Whatever the order the packs are given in
+        let merged = merge(&[
+            (PathBuf::from("new.txt"), new),
+            (PathBuf::from("old.txt"), old),
+        ])
+        .unwrap();
+        assert_eq!(merged.warnings.len(), 1);
+        assert!(merged.warnings[0].contains("keeping the copy from new.txt, written later"));
+        assert_eq!(
+            contents(&String::from_utf8(merged.text).unwrap())[0].1,
+            "new\n"
+        );
+    }
+
+    #[test]
+    fn relative_roots_holding_the_same_paths_are_kept_apart() {
+        let backend = written(".", None, &[("src/main.rs", "backend\n")]);
+        let frontend = written(".", None, &[("src/main.rs", "frontend\n"), ("a.js", "")]);
+
+        let merged = merge(&[
+            (PathBuf::from("out/backend.txt"), backend),
+            (PathBuf::from("frontend.txt"), frontend),
+        ])
+        .unwrap();
+        assert_eq!((merged.files, merged.duplicates), (3, 0));
+        assert!(merged.warnings[0].contains("written from relative roots"));
+        assert_eq!(
+            contents(&String::from_utf8(merged.text).unwrap()),
+            [
+                (
+                    PathBuf::from("backend/src/main.rs"),
+                    "backend\n".to_string()
+                ),
+                (
+                    PathBuf::from("frontend/src/main.rs"),
+                    "frontend\n".to_string()
+                ),
+                (PathBuf::from("frontend/a.js"), "\n".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unframed_packs_are_merged_without_frames() {
+        let text = format!(
+            "└── /project\n{}\nFile: a.rs\n{}\na\n",
+            SEPARATOR, SEPARATOR
+        );
+
+        let merged = merge(&[(PathBuf::from("a.txt"), text.clone())]).unwrap();
+        let merged = String::from_utf8(merged.text).unwrap();
+        assert!(merged.contains("\nFile: a.rs\n"), "{}", merged);
+        assert_eq!(contents(&merged), contents(&text));
+    }
+
+    #[test]
+    fn edited_packs_are_rejected() {
+        let text = pack(&[("a.rs", "a\n")]).replace("a\n", "b\n");
+
+        let error = merge(&[(PathBuf::from("a.txt"), text)]).err().unwrap();
+        assert!(format!("{:#}", error).contains("edited or corrupted"));
+    }
+}
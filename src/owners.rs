@@ -0,0 +1,258 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobMatcher};
+
+/// Where GitHub looks for the CODEOWNERS file, the first one found being used.
+const LOCATIONS: [&str; 3] = [".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
+
+/// A line of a CODEOWNERS file.
+struct Rule {
+    /// Globs matching the paths the rule applies to, relative to the root of
+    /// the repository.
+    globs: Vec<GlobMatcher>,
+    /// Owners given, none meaning the paths have no owner.
+    owners: Vec<String>,
+}
+
+/// The owners of the files of a repository, read from its CODEOWNERS file.
+pub struct CodeOwners {
+    rules: Vec<Rule>,
+}
+
+/// The path of the CODEOWNERS file of a repository, if it has one.
+pub fn path(repo_root: &Path) -> Option<PathBuf> {
+    LOCATIONS
+        .iter()
+        .map(|location| repo_root.join(location))
+        .find(|path| path.is_file())
+}
+
+impl CodeOwners {
+    /// Read the CODEOWNERS file of a repository, if it has one.
+    pub fn find(repo_root: &Path) -> Result<Option<Self>> {
+        let Some(path) = path(repo_root) else {
+            return Ok(None);
+        };
+
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&content, &path).map(Some)
+    }
+
+    /// Parse the content of a CODEOWNERS file, read from a path.
+    fn parse(content: &str, path: &Path) -> Result<Self> {
+        let mut rules = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let mut fields = line.split_whitespace();
+            let Some(pattern) = fields.next().filter(|pattern| !pattern.starts_with('#')) else {
+                continue;
+            };
+            let owners = fields
+                .take_while(|owner| !owner.starts_with('#'))
+                .map(str::to_string)
+                .collect();
+            let globs = globs(pattern).with_context(|| {
+                format!(
+                    "Invalid pattern `{}` at {}:{}",
+                    pattern,
+                    path.display(),
+                    number + 1
+                )
+            })?;
+            rules.push(Rule { globs, owners });
+        }
+
+        Ok(CodeOwners { rules })
+    }
+
+    /// The owners of a path relative to the root of the repository, given by
+    /// the last rule matching it, and none if no rule does.
+    pub fn owners(&self, path: &Path) -> &[String] {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.globs.iter().any(|glob| glob.is_match(path)))
+            .map_or(&[], |rule| &rule.owners)
+    }
+}
+
+/// Translate a CODEOWNERS pattern into globs.
+///
+/// Patterns follow gitignore, except that they cannot be negated with `!`, do
+/// not support character ranges, and that a wildcard in the last component
+/// only matches files: `docs/*` matches `docs/a.md` but not `docs/a/b.md`.
+fn globs(pattern: &str) -> Result<Vec<GlobMatcher>> {
+    // A leading `#` is escaped, and brackets and braces are matched literally
+    let pattern = pattern.strip_prefix('\\').unwrap_or(pattern);
+    let mut escaped = String::new();
+    for character in pattern.chars() {
+        match character {
+            '[' | ']' | '{' | '}' => escaped.push_str(&format!("[{}]", character)),
+            character => escaped.push(character),
+        }
+    }
+
+    // Patterns with a slash other than a trailing one are anchored at the root
+    let dir_only = escaped.ends_with('/');
+    let trimmed = escaped.trim_end_matches('/');
+    let base = match trimmed.strip_prefix('/') {
+        Some(anchored) => anchored.to_string(),
+        None if trimmed.contains('/') => trimmed.to_string(),
+        None => format!("**/{}", trimmed),
+    };
+
+    // A pattern naming a directory matches everything below it
+    let last = base.rsplit('/').next().unwrap_or_default();
+    let mut patterns = Vec::new();
+    if !dir_only {
+        patterns.push(base.clone());
+    }
+    if dir_only || !last.contains(['*', '?']) {
+        patterns.push(format!("{}/**", base));
+    }
+
+    patterns
+        .iter()
+        .map(|pattern| {
+            Ok(GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .build()?
+                .compile_matcher())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The owners of paths given a CODEOWNERS file.
+    fn owners<'a>(content: &str, paths: &[&'a str]) -> Vec<(&'a str, String)> {
+        let codeowners = CodeOwners::parse(content, Path::new("CODEOWNERS")).unwrap();
+        paths
+            .iter()
+            .map(|path| (*path, codeowners.owners(Path::new(path)).join(" ")))
+            .collect()
+    }
+
+    // The examples of GitHub's documentation of CODEOWNERS files
+
+    #[test]
+    fn later_rules_take_precedence() {
+        let content = "\
+# These owners will be the default owners for everything in the repo
+*       @global-owner1 @global-owner2
+*.js    @js-owner #This is an inline comment.
+*.go docs@example.com
+*.txt @octo-org/octocats
+";
+        assert_eq!(
+            owners(
+                content,
+                &["README.md", "web/app.js", "cmd/main.go", "a/b/notes.txt"]
+            ),
+            [
+                ("README.md", "@global-owner1 @global-owner2".to_string()),
+                ("web/app.js", "@js-owner".to_string()),
+                ("cmd/main.go", "docs@example.com".to_string()),
+                ("a/b/notes.txt", "@octo-org/octocats".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn anchored_directories_match_everything_below() {
+        let content = "/build/logs/ @doctocat\n";
+        assert_eq!(
+            owners(
+                content,
+                &[
+                    "build/logs/a.log",
+                    "build/logs/2024/a.log",
+                    "src/build/logs/a.log"
+                ]
+            ),
+            [
+                ("build/logs/a.log", "@doctocat".to_string()),
+                ("build/logs/2024/a.log", "@doctocat".to_string()),
+                ("src/build/logs/a.log", String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn trailing_wildcards_only_match_files() {
+        let content = "docs/*  docs@example.com\n";
+        assert_eq!(
+            owners(
+                content,
+                &[
+                    "docs/getting-started.md",
+                    "docs/build-app/troubleshooting.md"
+                ]
+            ),
+            [
+                ("docs/getting-started.md", "docs@example.com".to_string()),
+                ("docs/build-app/troubleshooting.md", String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unanchored_directories_match_at_any_depth() {
+        let content = "apps/ @octocat\n**/logs @octocat\n";
+        assert_eq!(
+            owners(
+                content,
+                &[
+                    "apps/a.rs",
+                    "web/apps/a.rs",
+                    "deeply/nested/logs/a.log",
+                    "app.rs"
+                ]
+            ),
+            [
+                ("apps/a.rs", "@octocat".to_string()),
+                ("web/apps/a.rs", "@octocat".to_string()),
+                ("deeply/nested/logs/a.log", "@octocat".to_string()),
+                ("app.rs", String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rules_without_owners_unset_them() {
+        let empty = "/apps/ @octocat\n/apps/github\n";
+        let other = "/apps/ @octocat\n/apps/github @doctocat\n";
+        let paths = ["apps/a.rs", "apps/github/a.rs"];
+        assert_eq!(
+            owners(empty, &paths),
+            [
+                ("apps/a.rs", "@octocat".to_string()),
+                ("apps/github/a.rs", String::new()),
+            ]
+        );
+        assert_eq!(
+            owners(other, &paths),
+            [
+                ("apps/a.rs", "@octocat".to_string()),
+                ("apps/github/a.rs", "@doctocat".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn escaped_hashes_and_brackets_are_literal() {
+        let content = "\\#notes.md @octocat\n[id].tsx @doctocat\n";
+        assert_eq!(
+            owners(content, &["#notes.md", "pages/[id].tsx", "pages/i.tsx"]),
+            [
+                ("#notes.md", "@octocat".to_string()),
+                ("pages/[id].tsx", "@doctocat".to_string()),
+                ("pages/i.tsx", String::new()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,510 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use sha2::{Digest, Sha256};
+
+use crate::ansi;
+use crate::{SEPARATOR, SMALL_FILES};
+
+/// Hexadecimal digits of the SHA-256 of a section kept in its header.
+const HASH_DIGITS: usize = 16;
+
+/// What a header line introduces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Header<'a> {
+    File {
+        /// The number given by `--file-index`.
+        number: Option<usize>,
+        path: &'a str,
+        /// The hash added to headers that would be indistinguishable.
+        tag: Option<&'a str>,
+    },
+    /// A file of the block of `--merge-small-files`, under a short header.
+    SmallFile { path: &'a str, tag: Option<&'a str> },
+    /// The block of files merged by `--merge-small-files`.
+    SmallFiles,
+    /// A chunk of a file given by `--chunk-tokens`.
+    Chunk,
+    /// The message of the HEAD commit.
+    Commit,
+    /// When the pack was written, given by `--pack-metadata`.
+    Packed { time: &'a str },
+    /// A section written from another directory.
+    Appended,
+    /// The index written by `--file-index`.
+    Index,
+    /// Any other section, such as a merge, a language or the log.
+    Other,
+}
+
+/// The end of the header of a section holding content, such as
+/// `(12 lines, sha256 3a7bd3e2360a3d29)`, giving the number of lines following
+/// the header and the start of their SHA-256. Written with `--pack-metadata`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    pub lines: usize,
+    pub hash: &'a str,
+}
+
+/// Counts the lines and hashes the content of a section as it is written, to
+/// end its header with its frame. The content is hashed as written to a file,
+/// without ANSI escape sequences.
+pub struct Framer {
+    lines: usize,
+    hasher: Sha256,
+}
+
+impl Default for Framer {
+    fn default() -> Self {
+        Framer {
+            lines: 0,
+            hasher: Sha256::new(),
+        }
+    }
+}
+
+impl Framer {
+    pub fn update(&mut self, bytes: &[u8]) {
+        let bytes = ansi::strip(bytes);
+        self.lines += bytes.iter().filter(|&&byte| byte == b'\n').count();
+        self.hasher.update(&bytes);
+    }
+
+    /// The frame to add to the header, starting with a space.
+    pub fn finish(self) -> String {
+        let hash = format!("{:x}", self.hasher.finalize());
+        let unit = if self.lines == 1 { "line" } else { "lines" };
+        format!(
+            " ({} {}, sha256 {})",
+            self.lines,
+            unit,
+            &hash[..HASH_DIGITS]
+        )
+    }
+}
+
+/// The frame of the content of a section, starting with a space.
+pub fn frame(body: &[u8]) -> String {
+    let mut framer = Framer::default();
+    framer.update(body);
+    framer.finish()
+}
+
+/// Separate the frame ending a header line, if it has one.
+fn split_frame(line: &str) -> (&str, Option<Frame<'_>>) {
+    let Some((rest, end)) = line.rsplit_once(" (") else {
+        return (line, None);
+    };
+    let frame = end.strip_suffix(')').and_then(|end| {
+        let (lines, hash) = end.split_once(", sha256 ")?;
+        let lines = lines
+            .strip_suffix(" lines")
+            .or_else(|| lines.strip_suffix(" line"))?;
+        let valid = hash.len() == HASH_DIGITS && hash.chars().all(|c| c.is_ascii_hexdigit());
+        Some(Frame {
+            lines: lines.parse().ok()?,
+            hash: valid.then_some(hash)?,
+        })
+    });
+    match frame {
+        Some(frame) => (rest, Some(frame)),
+        None => (line, None),
+    }
+}
+
+/// A part of a pack, from a header to the next one.
+pub struct Section<'a> {
+    /// The header starting the section, missing for the tree printed first
+    /// and for lines outside any section.
+    pub header: Option<Header<'a>>,
+    /// The number of lines and the hash given by the header.
+    pub frame: Option<Frame<'a>>,
+    /// Line number of the header, or of the first line, counting from 1.
+    pub line: usize,
+    /// The lines following the header, each with its newline.
+    pub body: &'a str,
+    /// Whether the pack ends before the lines given by the frame.
+    pub truncated: bool,
+}
+
+impl<'a> Section<'a> {
+    pub fn lines(&self) -> std::str::Lines<'a> {
+        self.body.lines()
+    }
+
+    /// What the section holds, for messages.
+    pub fn name(&self) -> String {
+        match self.header {
+            Some(Header::File { path, .. } | Header::SmallFile { path, .. }) => path.to_string(),
+            Some(Header::Chunk) => "a chunk".to_string(),
+            Some(Header::Commit) => "the commit message".to_string(),
+            Some(_) => "a header".to_string(),
+            None => "the tree".to_string(),
+        }
+    }
+
+    /// Why the section cannot be trusted to be as written: a missing end, or
+    /// content not matching its hash. Only a header written with
+    /// `--pack-metadata` gives them, a section without one ends where the next
+    /// header seems to start.
+    pub fn problem(&self) -> Option<String> {
+        let frame = self.frame?;
+        if self.truncated {
+            return Some(format!(
+                "Line {}: {} is truncated, {} lines expected, {} found",
+                self.line,
+                self.name(),
+                frame.lines,
+                self.body.lines().count()
+            ));
+        }
+        let hash = frame_hash(self.body);
+        if hash != frame.hash {
+            return Some(format!(
+                "Line {}: the content of {} has hash {}, expected {}, it was edited or corrupted",
+                self.line,
+                self.name(),
+                hash,
+                frame.hash
+            ));
+        }
+        None
+    }
+}
+
+/// The hash of content as given in a frame.
+pub fn frame_hash(body: &str) -> String {
+    let hash = format!("{:x}", Sha256::digest(ansi::strip(body.as_bytes())));
+    hash[..HASH_DIGITS].to_string()
+}
+
+/// A line without its newline.
+fn text(line: &str) -> &str {
+    line.strip_suffix('\n').unwrap_or(line)
+}
+
+/// Recognize a header framed by separators, such as `File: src/main.rs`,
+/// with the frame ending it.
+fn header<'a>(lines: &[&'a str], index: usize) -> Option<(Header<'a>, Option<Frame<'a>>)> {
+    let line = |index: usize| lines.get(index).map(|line| text(line));
+    if line(index) != Some(SEPARATOR) || line(index + 2) != Some(SEPARATOR) {
+        return None;
+    }
+    let (line, frame) = split_frame(line(index + 1)?);
+
+    if let Some(rest) = line.strip_prefix("File") {
+        let (number, rest) = match rest.strip_prefix(" [") {
+            Some(numbered) => {
+                let end = numbered.find(']')?;
+                (Some(numbered[..end].parse().ok()?), &numbered[end + 1..])
+            }
+            None => (None, rest),
+        };
+        let (path, tag) = split_tag(rest.strip_prefix(": ")?);
+        return Some((Header::File { number, path, tag }, frame));
+    }
+    let header = if line.starts_with("Chunk ") {
+        Header::Chunk
+    } else if line.starts_with("Commit: ") {
+        Header::Commit
+    } else if let Some(time) = line.strip_prefix("Packed: ") {
+        Header::Packed { time }
+    } else if line == SMALL_FILES {
+        Header::SmallFiles
+    } else if line.starts_with("Appended: ") {
+        Header::Appended
+    } else if line == "Index" {
+        Header::Index
+    } else if ["Merged: ", "Revision: ", "Part: ", "Language: "]
+        .iter()
+        .any(|prefix| line.starts_with(prefix))
+        || line == "Git log"
+        || line == "Binary files"
+        || line == "Dependencies"
+        || line == "Dependency graph"
+    {
+        Header::Other
+    } else {
+        return None;
+    };
+    Some((header, frame))
+}
+
+/// Recognize the short header of a file of the block of small files, such as
+/// `--- src/lib.rs (3 lines, sha256 ...)`. Without a frame it cannot be told
+/// apart from content, and is left to the section holding it.
+fn small_file(line: &str) -> Option<(Header<'_>, Option<Frame<'_>>)> {
+    let (line, frame) = split_frame(line.strip_prefix("--- ")?);
+    let (path, tag) = split_tag(line);
+    Some((Header::SmallFile { path, tag }, Some(frame?)))
+}
+
+/// Separate the hash added to headers that would be indistinguishable.
+fn split_tag(path: &str) -> (&str, Option<&str>) {
+    match path.rsplit_once(" [") {
+        Some((path, tag))
+            if tag.len() == 9
+                && tag.ends_with(']')
+                && tag[..8].chars().all(|c| c.is_ascii_hexdigit()) =>
+        {
+            (path, Some(&tag[..8]))
+        }
+        _ => (path, None),
+    }
+}
+
+/// Split a pack into its sections, failing if it does not look like a file
+/// written by llmr. A header with a frame is followed by the number of lines
+/// it gives whatever they hold, so that content looking like a header is not
+/// read as one.
+pub fn sections<'a>(text: &'a str, path: &Path) -> Result<Vec<Section<'a>>> {
+    if !(text.starts_with("└── ") || text.starts_with(SEPARATOR)) {
+        bail!(
+            "Not a file written by llmr: {} (expected a tree or a header first)",
+            path.display()
+        );
+    }
+
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    // Where each line starts in the text, and where the text ends
+    let mut offsets = Vec::with_capacity(lines.len() + 1);
+    let mut offset = 0;
+    for line in &lines {
+        offsets.push(offset);
+        offset += line.len();
+    }
+    offsets.push(offset);
+    let body = |start: usize, end: usize| &text[offsets[start]..offsets[end]];
+
+    let mut sections = Vec::new();
+    // The header of the section being read, its line number and the index of
+    // its first line
+    let mut current: (Option<Header>, usize, usize) = (None, 1, 0);
+    let mut small_files = false;
+    let mut index = 0;
+    while index < lines.len() {
+        let found = match header(&lines, index) {
+            Some(found) => Some((found, 3)),
+            None if small_files => small_file(self::text(lines[index])).map(|found| (found, 1)),
+            None => None,
+        };
+        let Some(((header, frame), size)) = found else {
+            index += 1;
+            continue;
+        };
+
+        let (previous, line, start) = current;
+        if previous.is_some() || start < index {
+            sections.push(Section {
+                header: previous,
+                frame: None,
+                line,
+                body: body(start, index),
+                truncated: false,
+            });
+        }
+        small_files = matches!(header, Header::SmallFiles | Header::SmallFile { .. });
+        let line = if size == 3 { index + 2 } else { index + 1 };
+        let start = index + size;
+        match frame {
+            Some(frame) => {
+                let end = (start + frame.lines).min(lines.len());
+                let body = body(start, end);
+                sections.push(Section {
+                    header: Some(header),
+                    frame: Some(frame),
+                    line,
+                    body,
+                    truncated: start + frame.lines > lines.len()
+                        || (frame.lines > 0 && !body.ends_with('\n')),
+                });
+                current = (None, end + 1, end);
+                index = end;
+            }
+            None => {
+                current = (Some(header), line, start);
+                index = start;
+            }
+        }
+    }
+    let (header, line, start) = current;
+    if header.is_some() || start < lines.len() {
+        sections.push(Section {
+            header,
+            frame: None,
+            line,
+            body: body(start, lines.len()),
+            truncated: false,
+        });
+    }
+
+    Ok(sections)
+}
+
+/// How the files of a pack differ from those of an earlier version of it.
+#[derive(Debug, Default)]
+pub struct Changes {
+    pub unchanged: usize,
+    pub changed: usize,
+    pub added: usize,
+    pub removed: usize,
+}
+
+/// The hash of each file of a pack, by path, or its content when its header
+/// gives no hash.
+fn files<'a>(sections: &[Section<'a>]) -> HashMap<&'a str, &'a str> {
+    sections
+        .iter()
+        .filter_map(|section| match section.header {
+            Some(Header::File { path, .. } | Header::SmallFile { path, .. }) => {
+                Some((path, section.frame.map_or(section.body, |frame| frame.hash)))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Compare the files of a pack with those of its earlier version.
+pub fn changes(previous: &str, current: &str, path: &Path) -> Result<Changes> {
+    let previous = files(&sections(previous, path)?);
+    let current = files(&sections(current, path)?);
+
+    let mut changes = Changes::default();
+    for (file, content) in &current {
+        match previous.get(file) {
+            Some(old) if old == content => changes.unchanged += 1,
+            Some(_) => changes.changed += 1,
+            None => changes.added += 1,
+        }
+    }
+    changes.removed = previous
+        .keys()
+        .filter(|file| !current.contains_key(*file))
+        .count();
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A section of a file as written by llmr.
+    fn file(path: &str, content: &str) -> String {
+        format!(
+            "{}\nFile: {}{}\n{}\n{}",
+            SEPARATOR,
+            path,
+            frame(content.as_bytes()),
+            SEPARATOR,
+            content
+        )
+    }
+
+    fn parse(text: &str) -> Vec<Section<'_>> {
+        sections(text, Path::new("pack.txt")).unwrap()
+    }
+
+    #[test]
+    fn frame_counts_lines_and_hashes() {
+        assert_eq!(frame(b""), " (0 lines, sha256 e3b0c44298fc1c14)");
+        assert_eq!(frame(b"abc\n"), " (1 line, sha256 edeaaff3f1774ad2)");
+        assert_eq!(frame(b"a\nb\n"), frame(b"a\nb\x1b[31m\n"));
+    }
+
+    #[test]
+    fn content_looking_like_headers_stays_in_its_section() {
+        // Like a README showing an example of output
+        let readme = format!(
+            "Example:\n{}\nFile: src/fake.rs\n{}\nfn fake() {{}}\n{}\nIndex\n{}\n",
+            SEPARATOR, SEPARATOR, SEPARATOR, SEPARATOR
+        );
+        let text = format!(
+            "└── project\n{}{}",
+            file("README.md", &readme),
+            file("src/main.rs", "fn main() {}\n")
+        );
+
+        let sections = parse(&text);
+        assert_eq!(sections.len(), 3);
+        assert_eq!(sections[1].name(), "README.md");
+        assert_eq!(sections[1].body, readme);
+        assert_eq!(sections[2].name(), "src/main.rs");
+        assert!(sections.iter().all(|section| section.problem().is_none()));
+    }
+
+    #[test]
+    fn small_files_are_read_by_their_short_header() {
+        let text = format!(
+            "└── project\n{}\n{}\n{}\n--- a.txt{}\n--- b.txt (1 line)\n--- b.txt{}\nb\n",
+            SEPARATOR,
+            SMALL_FILES,
+            SEPARATOR,
+            frame(b"--- b.txt (1 line)\n"),
+            frame(b"b\n"),
+        );
+
+        let sections = parse(&text);
+        let paths: Vec<String> = sections.iter().map(Section::name).collect();
+        assert_eq!(paths, ["the tree", "a header", "a.txt", "b.txt"]);
+        assert_eq!(sections[2].body, "--- b.txt (1 line)\n");
+    }
+
+    #[test]
+    fn edited_content_is_found() {
+        let text = format!("└── project\n{}", file("a.txt", "one\ntwo\n"));
+        let edited = text.replace("two", "2");
+
+        let sections = parse(&edited);
+        let problem = sections[1].problem().unwrap();
+        assert!(problem.contains("edited or corrupted"), "{}", problem);
+    }
+
+    #[test]
+    fn truncated_content_is_found() {
+        let text = format!("└── project\n{}", file("a.txt", "one\ntwo\nthree\n"));
+        let truncated = &text[..text.len() - "three\n".len()];
+
+        let sections = parse(truncated);
+        let problem = sections[1].problem().unwrap();
+        assert!(problem.contains("3 lines expected, 2 found"), "{}", problem);
+    }
+
+    #[test]
+    fn unframed_header_ends_at_the_next_one() {
+        let text = format!(
+            "└── project\n{}\nFile: a.txt\n{}\na\n{}\nFile: b.txt\n{}\nb\n",
+            SEPARATOR, SEPARATOR, SEPARATOR, SEPARATOR
+        );
+
+        let sections = parse(&text);
+        assert_eq!(sections[1].body, "a\n");
+        assert_eq!(sections[2].body, "b\n");
+        assert!(sections.iter().all(|section| section.problem().is_none()));
+    }
+
+    #[test]
+    fn changes_compare_hashes() {
+        let previous = format!(
+            "└── project\n{}{}",
+            file("a.txt", "a\n"),
+            file("b.txt", "b\n")
+        );
+        let current = format!(
+            "└── project\n{}{}",
+            file("a.txt", "a\n"),
+            file("c.txt", "c\n")
+        );
+
+        let changes = changes(&previous, &current, Path::new("pack.txt")).unwrap();
+        assert_eq!(
+            (
+                changes.unchanged,
+                changes.changed,
+                changes.added,
+                changes.removed
+            ),
+            (1, 0, 1, 1)
+        );
+    }
+}
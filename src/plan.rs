@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::format_size;
+
+/// Average number of bytes of a token, to project the tokens of a file from
+/// its size without reading it.
+const BYTES_PER_TOKEN: u64 = 4;
+
+/// The files `--dry-run` would include, found without reading their content.
+#[derive(Debug, Serialize)]
+pub struct Plan {
+    /// The directory the run started from.
+    pub directory: PathBuf,
+    /// The files planned, in the order they would be emitted.
+    pub files: Vec<PlannedFile>,
+    pub total_size: u64,
+    /// Sum of the projected tokens of the files.
+    pub estimated_tokens: usize,
+}
+
+/// A file planned by `--dry-run`.
+#[derive(Debug, Serialize)]
+pub struct PlannedFile {
+    /// Path relative to the directory the run started from.
+    pub path: PathBuf,
+    /// Size of the file on disk.
+    pub size: u64,
+    /// Number of tokens projected from the size.
+    pub tokens: usize,
+}
+
+impl Plan {
+    pub fn new(directory: PathBuf) -> Self {
+        Plan {
+            directory,
+            files: Vec::new(),
+            total_size: 0,
+            estimated_tokens: 0,
+        }
+    }
+
+    pub fn add(&mut self, path: PathBuf, size: u64) {
+        let tokens = size.div_ceil(BYTES_PER_TOKEN) as usize;
+        self.total_size += size;
+        self.estimated_tokens += tokens;
+        self.files.push(PlannedFile { path, size, tokens });
+    }
+
+    /// Render the plan as a table, one file per line, then the totals.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for file in &self.files {
+            text.push_str(&format!(
+                "{:>12} {:>8} tokens  {}\n",
+                format_size(file.size),
+                file.tokens,
+                file.path.display()
+            ));
+        }
+        text.push_str(&format!(
+            "{} files, {}, about {} tokens\n",
+            self.files.len(),
+            format_size(self.total_size),
+            self.estimated_tokens
+        ));
+        text
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        let json = serde_json::to_string_pretty(self).context("Failed to serialize plan")?;
+        Ok(json + "\n")
+    }
+}
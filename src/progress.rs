@@ -0,0 +1,52 @@
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// A count of the files done by a long step, shown on a single line of stderr
+/// when it is a terminal, so a large run is not taken for a stuck one.
+pub struct Progress {
+    label: &'static str,
+    total: usize,
+    done: AtomicUsize,
+    /// Last percentage shown, the line being redrawn only when it changes.
+    shown: Mutex<Option<usize>>,
+    enabled: bool,
+}
+
+impl Progress {
+    /// Start counting the files of a step, shown unless disabled or stderr is
+    /// not a terminal.
+    pub fn new(label: &'static str, total: usize, enabled: bool) -> Self {
+        Progress {
+            label,
+            total,
+            done: AtomicUsize::new(0),
+            shown: Mutex::new(None),
+            enabled: enabled && total > 0 && io::stderr().is_terminal(),
+        }
+    }
+
+    /// Count a file as done, from any thread.
+    pub fn tick(&self) {
+        if !self.enabled {
+            return;
+        }
+
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        let percent = done * 100 / self.total;
+        let mut shown = self.shown.lock().unwrap_or_else(|error| error.into_inner());
+        if *shown != Some(percent) {
+            *shown = Some(percent);
+            eprint!("\r{}: {}/{} files", self.label, done, self.total);
+            let _ = io::stderr().flush();
+        }
+    }
+
+    /// Clear the line, leaving stderr as it was.
+    pub fn finish(&self) {
+        if self.enabled && self.shown.lock().is_ok_and(|shown| shown.is_some()) {
+            eprint!("\r\x1b[2K");
+            let _ = io::stderr().flush();
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+/// Template used by `--prompt-file` unless `--prompt-template` gives another.
+pub const DEFAULT_TEMPLATE: &str = "\
+The source code of the project in {{directory}} follows: {{summary}}.
+
+Its directory structure is given between <tree> tags, then each file between
+<files> tags, preceded by a header with its path. Read them carefully, then
+answer the request that comes after them, referring to files by their path.
+
+<tree>
+{{tree}}
+</tree>
+
+<files>
+{{files}}
+</files>
+";
+
+/// Parts of a run that a template can place in the prompt.
+pub struct Sections<'a> {
+    /// The directory the files were found from.
+    pub directory: &'a str,
+    /// The number of files, their size and languages, on one line.
+    pub summary: &'a str,
+    pub tree: &'a str,
+    /// The files with their headers, as in the output.
+    pub files: &'a str,
+}
+
+/// Read a template from a file, checking its placeholders before any work is
+/// done.
+pub fn load(path: &Path) -> Result<String> {
+    let template = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read prompt template: {}", path.display()))?;
+    let empty = Sections {
+        directory: "",
+        summary: "",
+        tree: "",
+        files: "",
+    };
+    render(&template, &empty)
+        .with_context(|| format!("Invalid prompt template: {}", path.display()))?;
+    Ok(template)
+}
+
+/// Replace the `{{name}}` placeholders of a template by the sections of the
+/// run, failing on unknown placeholders.
+pub fn render(template: &str, sections: &Sections) -> Result<String> {
+    let mut prompt = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        let name = rest[start + 2..start + end].trim();
+        let value = match name {
+            "directory" => sections.directory,
+            "summary" => sections.summary,
+            "tree" => sections.tree,
+            "files" => sections.files,
+            _ => bail!(
+                "Unknown placeholder in prompt template: {{{{{}}}}} (expected directory, summary, tree or files)",
+                name
+            ),
+        };
+        prompt.push_str(&rest[..start]);
+        prompt.push_str(value.trim_end());
+        rest = &rest[start + end + 2..];
+    }
+    prompt.push_str(rest);
+    Ok(prompt)
+}
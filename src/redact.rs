@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result};
+use regex::RegexSet;
+
+/// Replaces the components of the paths shown that match the patterns given
+/// with `--redact-paths-matching` by numbered placeholders, the same name
+/// always getting the same placeholder, so that redacted files and
+/// directories are still told apart. Extensions are kept, for the language of
+/// files to be known.
+pub struct Redactor {
+    patterns: RegexSet,
+    placeholders: HashMap<OsString, String>,
+}
+
+impl Redactor {
+    /// Build the redactor of the patterns, leaving paths alone if there are none.
+    pub fn new(patterns: &[String]) -> Result<Self> {
+        let patterns = RegexSet::new(patterns).with_context(|| {
+            format!(
+                "Invalid --redact-paths-matching pattern: {}",
+                patterns.join(" ")
+            )
+        })?;
+
+        Ok(Redactor {
+            patterns,
+            placeholders: HashMap::new(),
+        })
+    }
+
+    /// The path with its matching components replaced by their placeholder.
+    pub fn path(&mut self, path: &Path) -> PathBuf {
+        if self.patterns.is_empty() {
+            return path.to_path_buf();
+        }
+
+        path.components()
+            .map(|component| match component {
+                Component::Normal(name) if self.patterns.is_match(&name.to_string_lossy()) => {
+                    let next = self.placeholders.len() + 1;
+                    let placeholder =
+                        self.placeholders
+                            .entry(name.to_os_string())
+                            .or_insert_with(|| match Path::new(name).extension() {
+                                Some(extension) => {
+                                    format!("redacted-{}.{}", next, extension.to_string_lossy())
+                                }
+                                None => format!("redacted-{}", next),
+                            });
+                    OsString::from(placeholder.as_str())
+                }
+                component => component.as_os_str().to_os_string(),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::diagnostics::Diagnostics;
+use crate::source::{self, Candidate, WalkOptions};
+use crate::tree::Node;
+
+/// The report written with `--report-json`, and read back by `--replay`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Report {
+    /// The directory the run started from.
+    pub directory: PathBuf,
+    /// The commit the files were read from with `--ref`.
+    pub revision: Option<String>,
+    /// The files included, in the order they were emitted.
+    pub files: Vec<ReportFile>,
+    /// The files found but left out, relative to the directory.
+    pub skipped: Vec<PathBuf>,
+    pub total_size: u64,
+    /// Sum of the tokens of the files included.
+    pub estimated_tokens: usize,
+    /// The tree as written in the output, with `--json-tree text` or `both`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tree: Option<String>,
+    /// The tree as nested entries, with `--json-tree object` or `both`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tree_object: Option<TreeEntry>,
+}
+
+/// Which forms of the tree the JSON report holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum JsonTree {
+    /// The nested entries only.
+    Object,
+    /// The text of the tree only.
+    Text,
+    /// Both the nested entries and the text.
+    Both,
+    /// No tree.
+    None,
+}
+
+impl JsonTree {
+    pub fn object(self) -> bool {
+        matches!(self, JsonTree::Object | JsonTree::Both)
+    }
+
+    pub fn text(self) -> bool {
+        matches!(self, JsonTree::Text | JsonTree::Both)
+    }
+}
+
+/// A directory or file of the tree.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TreeEntry {
+    pub name: String,
+    pub is_dir: bool,
+    /// Size on disk of an included file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<usize>,
+    /// What the entry is when it is not read as a file, such as a symlink.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub marker: Option<String>,
+    /// Whether the entry is ignored by git, and shown for context only.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ignored: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<TreeEntry>,
+}
+
+impl From<&Node> for TreeEntry {
+    fn from(node: &Node) -> Self {
+        TreeEntry {
+            name: node.name.clone(),
+            is_dir: node.is_dir,
+            size: node.weight.map(|weight| weight.size),
+            tokens: node.weight.and_then(|weight| weight.tokens),
+            marker: node.marker.clone(),
+            ignored: node.ignored,
+            children: node.children.iter().map(TreeEntry::from).collect(),
+        }
+    }
+}
+
+/// A file included in the output.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReportFile {
+    /// Path relative to the directory the run started from.
+    pub path: PathBuf,
+    /// Language detected from the extension, as in the summary of languages.
+    pub language: Option<String>,
+    /// Size of the file on disk.
+    pub size: u64,
+    /// Number of lines and bytes of the content emitted.
+    pub lines: usize,
+    pub bytes: usize,
+    pub tokens: Option<usize>,
+}
+
+/// How `--replay` treats the differences between a report and the files now
+/// on disk.
+pub struct ReplayOptions {
+    /// Fail on any difference instead of warning.
+    pub strict: bool,
+    /// Also include the files that appeared since the report.
+    pub additions: bool,
+}
+
+pub fn write(path: &Path, report: &Report) -> Result<()> {
+    let json = serde_json::to_string_pretty(report).context("Failed to serialize report")?;
+    fs::write(path, json + "\n")
+        .with_context(|| format!("Failed to write report: {}", path.display()))
+}
+
+pub fn read(path: &Path) -> Result<Report> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read report: {}", path.display()))?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse report: {}", path.display()))
+}
+
+/// List the files included by a previous run, as recorded in its report,
+/// warning about those that no longer exist.
+///
+/// Files that appeared since the report, found by walking the directory, are
+/// only included if asked to.
+pub fn replay(
+    report_path: &Path,
+    root: &Path,
+    walk_options: &WalkOptions,
+    options: &ReplayOptions,
+    diagnostics: &mut Diagnostics,
+) -> Result<Vec<Candidate>> {
+    let report = read(report_path)?;
+
+    let mut candidates = Vec::new();
+    let mut missing = Vec::new();
+    for file in &report.files {
+        let path = root.join(&file.path);
+        if !path.is_file() {
+            missing.push(file.path.display().to_string());
+            continue;
+        }
+        candidates.push(Candidate {
+            path,
+            display: file.path.clone(),
+            is_dir: false,
+            range: None,
+            walked: false,
+        });
+    }
+    if !missing.is_empty() {
+        if options.strict {
+            bail!(
+                "Files of {} no longer exist: {}",
+                report_path.display(),
+                missing.join(", ")
+            );
+        }
+        for path in missing {
+            diagnostics.warn(format!(
+                "Warning: {} from {} no longer exists",
+                path,
+                report_path.display()
+            ));
+        }
+    }
+
+    if !options.strict && !options.additions {
+        return Ok(candidates);
+    }
+
+    // Files neither included nor skipped by the previous run are new
+    let known: HashSet<PathBuf> = report
+        .files
+        .iter()
+        .map(|file| &file.path)
+        .chain(&report.skipped)
+        .map(|path| root.join(path))
+        .collect();
+    let added: Vec<Candidate> = source::walk(root, walk_options)?
+        .into_iter()
+        .filter(|candidate| !candidate.is_dir && !known.contains(&candidate.path))
+        .collect();
+
+    if options.additions {
+        candidates.extend(added);
+    } else if !added.is_empty() {
+        let paths: Vec<String> = added
+            .iter()
+            .map(|candidate| candidate.display.display().to_string())
+            .collect();
+        bail!(
+            "Files appeared since {}: {} (use --replay-additions to include them)",
+            report_path.display(),
+            paths.join(", ")
+        );
+    }
+
+    Ok(candidates)
+}
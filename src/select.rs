@@ -0,0 +1,292 @@
+use std::path::Path;
+use std::time::SystemTime;
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use globset::{Glob, GlobMatcher};
+
+use crate::longpath;
+use crate::source::Candidate;
+
+/// Order in which files are processed and emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SortKey {
+    /// The order in which files were found.
+    Walk,
+    /// Alphabetical order of the displayed path.
+    Path,
+    /// Smallest files first.
+    Size,
+    /// Least recently modified files first.
+    Mtime,
+}
+
+/// Order the files among the candidates, returning their indices. Files with
+/// the same size or modification time are ordered by path, whatever the
+/// direction, so that the order does not depend on the filesystem.
+pub fn sort(candidates: &[Candidate], key: SortKey, descending: bool) -> Result<Vec<usize>> {
+    let mut files: Vec<usize> = (0..candidates.len())
+        .filter(|&index| !candidates[index].is_dir)
+        .collect();
+
+    match key {
+        SortKey::Walk if descending => files.reverse(),
+        SortKey::Walk => {}
+        SortKey::Path => {
+            let paths: Vec<&Path> = files
+                .iter()
+                .map(|&index| candidates[index].display.as_path())
+                .collect();
+            sort_by_keys(&mut files, paths, candidates, descending);
+        }
+        SortKey::Size => {
+            let sizes = files
+                .iter()
+                .map(|&index| Ok(file_metadata(&candidates[index])?.len()))
+                .collect::<Result<Vec<u64>>>()?;
+            sort_by_keys(&mut files, sizes, candidates, descending);
+        }
+        SortKey::Mtime => {
+            let times = files
+                .iter()
+                .map(|&index| {
+                    // Files whose modification time is unavailable sort first
+                    Ok(file_metadata(&candidates[index])?
+                        .modified()
+                        .unwrap_or(SystemTime::UNIX_EPOCH))
+                })
+                .collect::<Result<Vec<SystemTime>>>()?;
+            sort_by_keys(&mut files, times, candidates, descending);
+        }
+    }
+
+    Ok(files)
+}
+
+fn file_metadata(candidate: &Candidate) -> Result<std::fs::Metadata> {
+    longpath::metadata(&candidate.path)
+}
+
+/// Stable sort of the indices by precomputed keys, given in the same order.
+fn sort_by_keys<K: Ord>(
+    files: &mut Vec<usize>,
+    keys: Vec<K>,
+    candidates: &[Candidate],
+    descending: bool,
+) {
+    let mut keyed: Vec<(K, usize)> = keys.into_iter().zip(files.iter().copied()).collect();
+    keyed.sort_by(|a, b| {
+        let order = match a.0.cmp(&b.0) {
+            order if descending => order.reverse(),
+            order => order,
+        };
+        order.then_with(|| candidates[a.1].display.cmp(&candidates[b.1].display))
+    });
+    *files = keyed.into_iter().map(|(_, index)| index).collect();
+}
+
+/// Which end of the sorted files to keep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slice {
+    Head(usize),
+    Tail(usize),
+}
+
+/// Keep the first or last files in sort order, returning the selected and the
+/// dropped files.
+pub fn slice(mut files: Vec<usize>, slice: Option<Slice>) -> (Vec<usize>, Vec<usize>) {
+    match slice {
+        None => (files, Vec::new()),
+        Some(Slice::Head(count)) => {
+            let dropped = files.split_off(count.min(files.len()));
+            (files, dropped)
+        }
+        Some(Slice::Tail(count)) => {
+            let selected = files.split_off(files.len().saturating_sub(count));
+            (selected, files)
+        }
+    }
+}
+
+/// Drop the files sorted before the one given with `--start-at`, to resume a
+/// large walk processed in slices, returning the kept and the dropped files.
+/// When the files are in ascending path order, the path need not be among
+/// them, the files kept starting at the first one sorted after it.
+pub fn start_at(
+    mut files: Vec<usize>,
+    candidates: &[Candidate],
+    start: &Path,
+    by_path: bool,
+) -> Result<(Vec<usize>, Vec<usize>)> {
+    let found = files
+        .iter()
+        .position(|&index| candidates[index].display == start);
+    let position = match found {
+        Some(position) => position,
+        None if by_path => {
+            files.partition_point(|&index| candidates[index].display.as_path() < start)
+        }
+        None => bail!(
+            "--start-at {}: not among the files to process",
+            start.display()
+        ),
+    };
+
+    let kept = files.split_off(position);
+    Ok((kept, files))
+}
+
+/// Files to process first, matched by the globs given with `--priority`.
+pub struct Priority {
+    globs: Vec<GlobMatcher>,
+}
+
+impl Priority {
+    pub fn new(patterns: &[String]) -> Result<Self> {
+        let globs = patterns
+            .iter()
+            .map(|pattern| {
+                Ok(Glob::new(pattern)
+                    .with_context(|| format!("Invalid priority glob: {}", pattern))?
+                    .compile_matcher())
+            })
+            .collect::<Result<Vec<GlobMatcher>>>()?;
+
+        Ok(Priority { globs })
+    }
+
+    /// Index of the first glob matching a displayed path, if any.
+    pub fn rank(&self, path: &Path) -> Option<usize> {
+        self.globs.iter().position(|glob| glob.is_match(path))
+    }
+}
+
+/// Move the files matching a priority glob to the front, in the order of the
+/// globs, keeping the sort order otherwise.
+pub fn prioritize(files: &mut [usize], candidates: &[Candidate], priority: &Priority) {
+    files.sort_by_key(|&index| {
+        priority
+            .rank(&candidates[index].display)
+            .unwrap_or(usize::MAX)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::File;
+    use std::time::Duration;
+
+    use crate::source::tests::fixture;
+
+    /// Candidates for files given by their path and content, in the order
+    /// given, as a walk could find them.
+    fn candidates(name: &str, files: &[(&str, &str)]) -> Vec<Candidate> {
+        let root = fixture(name, files);
+        files
+            .iter()
+            .map(|(path, _)| Candidate {
+                path: root.join(path),
+                display: path.into(),
+                is_dir: false,
+                range: None,
+                walked: true,
+            })
+            .collect()
+    }
+
+    fn sorted(candidates: &[Candidate], key: SortKey, descending: bool) -> Vec<String> {
+        sort(candidates, key, descending)
+            .unwrap()
+            .into_iter()
+            .map(|index| candidates[index].display.display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn equal_sizes_are_ordered_by_path_in_both_directions() {
+        let candidates = candidates(
+            "sort-size",
+            &[
+                ("c.rs", "12"),
+                ("b.rs", "1"),
+                ("a.rs", "12"),
+                ("d.rs", "12"),
+            ],
+        );
+        assert_eq!(
+            sorted(&candidates, SortKey::Size, false),
+            ["b.rs", "a.rs", "c.rs", "d.rs"]
+        );
+        assert_eq!(
+            sorted(&candidates, SortKey::Size, true),
+            ["a.rs", "c.rs", "d.rs", "b.rs"]
+        );
+    }
+
+    #[test]
+    fn equal_modification_times_are_ordered_by_path() {
+        let candidates = candidates("sort-mtime", &[("b.rs", ""), ("a.rs", ""), ("old.rs", "")]);
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        for (candidate, time) in candidates
+            .iter()
+            .zip([time, time, time - Duration::from_secs(60)])
+        {
+            File::options()
+                .write(true)
+                .open(&candidate.path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap();
+        }
+        assert_eq!(
+            sorted(&candidates, SortKey::Mtime, false),
+            ["old.rs", "a.rs", "b.rs"]
+        );
+        assert_eq!(
+            sorted(&candidates, SortKey::Mtime, true),
+            ["a.rs", "b.rs", "old.rs"]
+        );
+    }
+
+    #[test]
+    fn walk_and_path_orders() {
+        let candidates = candidates("sort-path", &[("b/a.rs", ""), ("a.rs", ""), ("b.rs", "")]);
+        assert_eq!(
+            sorted(&candidates, SortKey::Walk, false),
+            ["b/a.rs", "a.rs", "b.rs"]
+        );
+        assert_eq!(
+            sorted(&candidates, SortKey::Walk, true),
+            ["b.rs", "a.rs", "b/a.rs"]
+        );
+        assert_eq!(
+            sorted(&candidates, SortKey::Path, false),
+            ["a.rs", "b/a.rs", "b.rs"]
+        );
+        // Paths compare by component, so a directory sorts before a file
+        // whose name extends its own
+        assert_eq!(
+            sorted(&candidates, SortKey::Path, true),
+            ["b.rs", "b/a.rs", "a.rs"]
+        );
+    }
+
+    #[test]
+    fn slices_keep_either_end() {
+        let files = vec![0, 1, 2, 3];
+        assert_eq!(
+            slice(files.clone(), Some(Slice::Head(3))),
+            (vec![0, 1, 2], vec![3])
+        );
+        assert_eq!(
+            slice(files.clone(), Some(Slice::Tail(3))),
+            (vec![1, 2, 3], vec![0])
+        );
+        assert_eq!(
+            slice(files, Some(Slice::Tail(9))),
+            (vec![0, 1, 2, 3], vec![])
+        );
+    }
+}
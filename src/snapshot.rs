@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use git2::{ObjectType, Oid, Repository, TreeWalkMode, TreeWalkResult};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+
+use crate::is_text;
+use crate::source::{Candidate, WalkOptions, LLMRIGNORE};
+
+/// File mode git gives to symbolic links.
+const SYMLINK_MODE: i32 = 0o120000;
+
+/// A file of a revision, read from its blob.
+pub struct SnapshotFile {
+    pub size: u64,
+    pub is_text: bool,
+    /// Content of the file, read when it is text and within the size limit.
+    pub content: Option<String>,
+    /// Offset of the first byte of the content that is not valid UTF-8, and
+    /// was replaced when reading it, if any.
+    pub invalid_utf8: Option<usize>,
+}
+
+/// The files of the current directory as of a git revision, read from the
+/// object database without touching the working tree.
+pub struct Snapshot {
+    /// Full id of the commit the revision resolved to.
+    pub commit: String,
+    /// Files and directories, relative to the current directory, in tree order.
+    pub candidates: Vec<Candidate>,
+    /// Files, by their path in the current directory.
+    files: HashMap<PathBuf, SnapshotFile>,
+    /// Entries that cannot be read as files, such as symbolic links and
+    /// submodules, with the marker shown in the tree.
+    pub marked: Vec<(PathBuf, &'static str)>,
+}
+
+impl Snapshot {
+    /// The file of the revision at a path of the current directory.
+    pub fn file(&self, path: &Path) -> Option<&SnapshotFile> {
+        self.files.get(path)
+    }
+}
+
+/// Whether a path of the tree of a commit passes the hidden, `--exclude-dir`
+/// and `--ignore-pattern` rules, the path being relative to the directory llmr
+/// runs in.
+pub fn selected(
+    path: &Path,
+    is_dir: bool,
+    exclude_dirs: &[String],
+    ignore_patterns: &Gitignore,
+) -> bool {
+    let components: Vec<&str> = path.iter().filter_map(|name| name.to_str()).collect();
+    let Some((_, parents)) = components.split_last() else {
+        return false;
+    };
+    let dirs = if is_dir { &components[..] } else { parents };
+
+    !components.iter().any(|name| name.starts_with('.'))
+        && !dirs
+            .iter()
+            .any(|dir| exclude_dirs.iter().any(|excluded| excluded == dir))
+        && !ignore_patterns
+            .matched_path_or_any_parents(path, is_dir)
+            .is_ignore()
+}
+
+/// An entry of the tree of a commit.
+struct Entry {
+    /// Path relative to the root of the repository.
+    path: PathBuf,
+    id: Oid,
+    kind: Option<ObjectType>,
+    mode: i32,
+}
+
+/// The ignore files read from the blobs of a commit, by directory.
+struct IgnoreFiles {
+    /// Matchers of each directory, the first one taking precedence.
+    dirs: HashMap<PathBuf, Vec<Gitignore>>,
+}
+
+impl IgnoreFiles {
+    /// Read the ignore files enabled by the options from the tree entries.
+    fn read(repository: &Repository, entries: &[Entry], options: &WalkOptions) -> Result<Self> {
+        let mut names = Vec::new();
+        if options.llmrignore {
+            names.push(LLMRIGNORE);
+        }
+        if options.dot_ignore {
+            names.push(".ignore");
+        }
+        if options.gitignore {
+            names.push(".gitignore");
+        }
+
+        let mut dirs: HashMap<PathBuf, Vec<Gitignore>> = HashMap::new();
+        for name in names {
+            for entry in entries
+                .iter()
+                .filter(|entry| entry.kind == Some(ObjectType::Blob))
+                .filter(|entry| entry.path.file_name().is_some_and(|file| file == name))
+            {
+                let blob = repository
+                    .find_blob(entry.id)
+                    .with_context(|| format!("Failed to read blob: {}", entry.path.display()))?;
+                let dir = entry.path.parent().unwrap_or(Path::new("")).to_path_buf();
+                let mut builder = GitignoreBuilder::new(&dir);
+                for line in String::from_utf8_lossy(blob.content()).lines() {
+                    // Invalid patterns are skipped, as git does
+                    let _ = builder.add_line(Some(entry.path.clone()), line);
+                }
+                let matcher = builder
+                    .build()
+                    .with_context(|| format!("Failed to read {}", entry.path.display()))?;
+                dirs.entry(dir).or_default().push(matcher);
+            }
+        }
+
+        Ok(IgnoreFiles { dirs })
+    }
+
+    /// Whether a path relative to the root of the repository is ignored, the
+    /// ignore files of the deepest directories taking precedence.
+    fn ignored(&self, path: &Path, is_dir: bool) -> bool {
+        for dir in path.ancestors().skip(1) {
+            for matcher in self.dirs.get(dir).into_iter().flatten() {
+                match matcher.matched_path_or_any_parents(path, is_dir) {
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                    Match::None => {}
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Read the files below the current directory as of a revision, applying the
+/// ignore files found in its tree and the same rules as the walker.
+///
+/// Files larger than the size limit are listed without their content.
+/// Symbolic links and submodules are not followed, and only marked.
+pub fn read(
+    root: &Path,
+    revision: &str,
+    options: &WalkOptions,
+    max_file_size: u64,
+) -> Result<Snapshot> {
+    let repository = Repository::discover(root)
+        .with_context(|| format!("Not in a git repository: {}", root.display()))?;
+    let commit = repository
+        .revparse_single(revision)
+        .and_then(|object| object.peel_to_commit())
+        .with_context(|| format!("Unknown revision: {}", revision))?;
+    let tree = commit
+        .tree()
+        .with_context(|| format!("Failed to read the tree of revision: {}", revision))?;
+
+    // Only the current directory is read, the whole tree of a bare repository
+    let prefix = match repository.workdir() {
+        Some(workdir) => root
+            .strip_prefix(fs::canonicalize(workdir).unwrap_or_else(|_| workdir.to_path_buf()))
+            .unwrap_or(Path::new(""))
+            .to_path_buf(),
+        None => PathBuf::new(),
+    };
+
+    // The walk cannot fail from its callback, so entries are listed first
+    let mut entries: Vec<Entry> = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        if let Some(name) = entry.name() {
+            entries.push(Entry {
+                path: Path::new(dir).join(name),
+                id: entry.id(),
+                kind: entry.kind(),
+                mode: entry.filemode(),
+            });
+        }
+        TreeWalkResult::Ok
+    })
+    .with_context(|| format!("Failed to walk the tree of revision: {}", revision))?;
+    let ignore_files = IgnoreFiles::read(&repository, &entries, options)?;
+
+    let mut candidates = Vec::new();
+    let mut files = HashMap::new();
+    let mut marked = Vec::new();
+    for entry in &entries {
+        let Ok(relative) = entry.path.strip_prefix(&prefix) else {
+            continue;
+        };
+        let is_dir = entry.kind == Some(ObjectType::Tree);
+        if !selected(
+            relative,
+            is_dir,
+            &options.exclude_dirs,
+            &options.ignore_patterns,
+        ) || ignore_files.ignored(&entry.path, is_dir)
+        {
+            continue;
+        }
+
+        let path = root.join(relative);
+        match entry.kind {
+            Some(ObjectType::Tree) => {}
+            Some(ObjectType::Commit) => {
+                marked.push((relative.to_path_buf(), "submodule"));
+                continue;
+            }
+            Some(ObjectType::Blob) if entry.mode == SYMLINK_MODE => {
+                marked.push((relative.to_path_buf(), "symlink"));
+                continue;
+            }
+            Some(ObjectType::Blob) => {
+                let blob = repository
+                    .find_blob(entry.id)
+                    .with_context(|| format!("Failed to read blob: {}", entry.path.display()))?;
+                let content = blob.content();
+                let is_text = is_text(&content[..content.len().min(1024)]);
+                let size = content.len() as u64;
+                files.insert(
+                    path.clone(),
+                    SnapshotFile {
+                        size,
+                        is_text,
+                        content: (is_text && size <= max_file_size)
+                            .then(|| String::from_utf8_lossy(content).into_owned()),
+                        invalid_utf8: std::str::from_utf8(content)
+                            .err()
+                            .map(|error| error.valid_up_to()),
+                    },
+                );
+            }
+            _ => continue,
+        }
+        candidates.push(Candidate {
+            path,
+            display: relative.to_path_buf(),
+            is_dir,
+            range: None,
+            walked: true,
+        });
+    }
+
+    Ok(Snapshot {
+        commit: commit.id().to_string(),
+        candidates,
+        files,
+        marked,
+    })
+}
@@ -0,0 +1,1156 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use globset::{GlobBuilder, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::{DirEntry, Walk, WalkBuilder};
+use serde::Deserialize;
+
+/// Format of the list given to `--files-from`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ListFormat {
+    /// Detect the format from the file extension.
+    Auto,
+    /// One path per line.
+    Plain,
+    /// A JSON array of `{path, range?, rename?}` objects.
+    Json,
+}
+
+/// An inclusive range of lines, numbered from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: Option<usize>,
+}
+
+impl LineRange {
+    /// Parse a range such as `10-20` or `10-`.
+    pub fn parse(range: &str) -> Result<Self> {
+        let (start, end) = range
+            .split_once('-')
+            .with_context(|| format!("Invalid line range: {}", range))?;
+        let start: usize = start
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid line range start: {}", range))?;
+        let end: Option<usize> = match end.trim() {
+            "" => None,
+            end => Some(
+                end.parse()
+                    .with_context(|| format!("Invalid line range end: {}", range))?,
+            ),
+        };
+
+        if start == 0 || end.is_some_and(|end| end < start) {
+            bail!("Invalid line range: {}", range);
+        }
+
+        Ok(LineRange { start, end })
+    }
+
+    /// Extract the lines of the range from a file's content.
+    pub fn apply(&self, content: &str) -> String {
+        let lines = content.lines().skip(self.start - 1);
+        match self.end {
+            Some(end) => lines
+                .take(end + 1 - self.start)
+                .collect::<Vec<_>>()
+                .join("\n"),
+            None => lines.collect::<Vec<_>>().join("\n"),
+        }
+    }
+}
+
+/// A path to process, as found by the walker or listed with `--files-from`.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    /// Path on disk.
+    pub path: PathBuf,
+    /// Path shown in the tree and the file headers.
+    pub display: PathBuf,
+    pub is_dir: bool,
+    /// Lines of the file to include, if not all of them.
+    pub range: Option<LineRange>,
+    /// Whether the walker's hidden and ignore rules were applied.
+    pub walked: bool,
+}
+
+/// Name of the ignore files specific to llmr, which take precedence over the
+/// other ignore files.
+pub const LLMRIGNORE: &str = ".llmrignore";
+
+/// Directory of the walk root holding the `include` and `exclude` files, in
+/// which llmr preferences are committed to a repository.
+pub const LLMR_DIR: &str = ".llmr";
+
+/// Read the patterns of a file of the `.llmr` directory of the walk root, if
+/// there is one, skipping blank lines and comments as in a gitignore file.
+pub fn llmr_patterns(root: &Path, name: &str) -> Result<Vec<String>> {
+    let path = root.join(LLMR_DIR).join(name);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+
+    let text =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(text
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+/// When to stop walking, remembering whether a walk was cut short by it.
+#[derive(Clone)]
+pub struct Deadline {
+    at: Instant,
+    reached: Arc<AtomicBool>,
+}
+
+impl Deadline {
+    pub fn new(at: Instant) -> Self {
+        Deadline {
+            at,
+            reached: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Whether the time is up, for a walk about to visit another entry.
+    fn reached(&self) -> bool {
+        let reached = Instant::now() >= self.at;
+        if reached {
+            self.reached.store(true, Ordering::Relaxed);
+        }
+        reached
+    }
+
+    /// Whether a walk stopped before visiting all its entries.
+    pub fn cut_short(&self) -> bool {
+        self.reached.load(Ordering::Relaxed)
+    }
+}
+
+/// Options controlling which paths the walker visits.
+#[derive(Clone)]
+pub struct WalkOptions {
+    /// Patterns given with `--ignore-pattern`.
+    pub ignore_patterns: Gitignore,
+    /// Names of the directories pruned at any depth, given with `--exclude-dir`.
+    pub exclude_dirs: Vec<String>,
+    /// Whether `.llmrignore` files apply.
+    pub llmrignore: bool,
+    /// Whether `.ignore` files apply.
+    pub dot_ignore: bool,
+    /// Whether `.gitignore` files and the repository's `.git/info/exclude` apply.
+    pub gitignore: bool,
+    /// The user's global excludes file, unless disabled.
+    pub global_excludes: Option<PathBuf>,
+    /// Whether ignore files above the walk root apply, up to the root of the
+    /// enclosing repository for git's own ignore files.
+    pub parents: bool,
+    /// Whether `.gitignore` files and git's excludes only apply inside a git
+    /// repository. `.ignore` files and `--ignore-pattern` always apply.
+    pub require_git: bool,
+    /// Whether symbolic links to directories are walked into, the walker
+    /// stopping at links that loop back to a directory being walked.
+    pub follow_symlinks: bool,
+    /// When to stop walking, keeping the paths found until then.
+    pub deadline: Option<Deadline>,
+}
+
+/// The root of the git repository containing a path, if any.
+pub fn repo_root(path: &Path) -> Option<&Path> {
+    path.ancestors().find(|dir| dir.join(".git").exists())
+}
+
+/// Whether a path is inside a git repository.
+pub fn in_git_repo(path: &Path) -> bool {
+    repo_root(path).is_some()
+}
+
+/// Build the matcher for the patterns given with `--ignore-pattern`, which use
+/// gitignore syntax and are anchored at the walk root.
+///
+/// The patterns are matched after the ignore files, as if they were the last
+/// rules of a root-level ignore file: a negated pattern re-includes paths
+/// excluded by an earlier pattern or by an ignore file, hidden paths too, but
+/// not the contents of a directory that stays excluded.
+pub fn ignore_patterns(root: &Path, patterns: &[String]) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(root);
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .with_context(|| format!("Invalid ignore pattern: {}", pattern))?;
+    }
+    builder.build().context("Failed to build ignore patterns")
+}
+
+/// Describe the ignore sources that apply when walking a directory, one line
+/// each, from the one that decides first. Ignore files are listed when found in
+/// the directory or, with `parents`, above it; those found while walking apply
+/// too.
+pub fn ignore_sources(
+    root: &Path,
+    options: &WalkOptions,
+    include: &[String],
+    ignore_patterns: &[String],
+) -> Vec<String> {
+    let patterns = |patterns: &[String]| match patterns.is_empty() {
+        true => "none".to_string(),
+        false => patterns.join(" "),
+    };
+    let repo = repo_root(root);
+    let git_applies = !options.require_git || repo.is_some();
+    let mut sources = vec![
+        format!("--include and .llmr/include: {}", patterns(include)),
+        format!(
+            "--ignore-pattern and .llmr/exclude: {}",
+            patterns(ignore_patterns)
+        ),
+        format!("--exclude-dir: {}", patterns(&options.exclude_dirs)),
+    ];
+
+    // Git's own ignore files are read up to the root of the repository
+    let kinds = [
+        (LLMRIGNORE, options.llmrignore, "--no-llmrignore", None),
+        (".ignore", options.dot_ignore, "--no-dot-ignore", None),
+        (".gitignore", options.gitignore, "--no-gitignore", repo),
+    ];
+    for (name, enabled, flag, top) in kinds {
+        let state = if !enabled {
+            format!("off ({})", flag)
+        } else if name == ".gitignore" && !git_applies {
+            "off outside a repository (--require-git)".to_string()
+        } else {
+            let mut dirs = vec![root];
+            if options.parents {
+                dirs.extend(
+                    root.ancestors()
+                        .skip(1)
+                        .take_while(|dir| top.is_none_or(|top| dir.starts_with(top))),
+                );
+            }
+            let found: Vec<String> = dirs
+                .iter()
+                .map(|dir| dir.join(name))
+                .filter(|path| path.is_file())
+                .map(|path| path.display().to_string())
+                .collect();
+            match found.is_empty() {
+                true => "on".to_string(),
+                false => format!("on, {}", found.join(" ")),
+            }
+        };
+        sources.push(format!("{}: {}", name, state));
+    }
+
+    let exclude = repo.map(|repo| repo.join(".git").join("info").join("exclude"));
+    sources.push(format!(
+        ".git/info/exclude: {}",
+        match exclude {
+            _ if !options.gitignore => "off (--no-gitignore)".to_string(),
+            _ if !git_applies => "off outside a repository (--require-git)".to_string(),
+            Some(exclude) if exclude.is_file() => format!("on, {}", exclude.display()),
+            _ => "none".to_string(),
+        }
+    ));
+    sources.push(format!(
+        "global excludes file: {}",
+        match &options.global_excludes {
+            _ if !git_applies => "off outside a repository (--require-git)".to_string(),
+            Some(path) => format!("on, {}", path.display()),
+            None => "none, or off (--no-global-gitignore)".to_string(),
+        }
+    ));
+    sources.push(format!(
+        "ignore files of parent directories: {}",
+        match options.parents {
+            true => "on",
+            false => "off (--no-parent-ignore)",
+        }
+    ));
+    sources
+}
+
+/// Build the walker of a directory, applying the ignore rules of the options,
+/// skipping hidden paths if asked to, and visiting only the entries accepted
+/// by a filter.
+fn walker<F>(root: &Path, options: &WalkOptions, hidden: bool, filter: F) -> Walk
+where
+    F: Fn(&DirEntry) -> bool + Send + Sync + 'static,
+{
+    // Build the file walker, respecting gitignore and git's excludes, including
+    // the ignore files of the enclosing repository when walking one of its
+    // subdirectories. The walker matches them against the absolute path, so
+    // anchored patterns apply, and gives precedence to the deepest file, as git
+    // does. Its default for `require_git` is set explicitly, so `.gitignore`
+    // files apply outside a repository unless asked otherwise
+    let mut builder = WalkBuilder::new(root);
+    builder
+        .ignore(options.dot_ignore)
+        .git_ignore(options.gitignore)
+        .git_exclude(options.gitignore)
+        .git_global(false)
+        .require_git(options.require_git)
+        .parents(options.parents)
+        .hidden(hidden)
+        .follow_links(options.follow_symlinks);
+    if options.llmrignore {
+        builder.add_custom_ignore_filename(LLMRIGNORE);
+    }
+
+    // The global excludes file is resolved here rather than by the walker, which
+    // does not know about `GIT_CONFIG_GLOBAL`
+    if let Some(global_excludes) = &options.global_excludes {
+        if !options.require_git || in_git_repo(root) {
+            builder.add_ignore(global_excludes);
+        }
+    }
+
+    let ignore_patterns = options.ignore_patterns.clone();
+    let exclude_dirs = options.exclude_dirs.clone();
+    builder
+        .filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|ft| ft.is_dir());
+            let excluded = is_dir
+                && entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|name| exclude_dirs.iter().any(|excluded| excluded == name));
+            !ignore_patterns.matched(entry.path(), is_dir).is_ignore() && !excluded && filter(entry)
+        })
+        .build()
+}
+
+/// Walk a directory, respecting gitignore, and list the paths found below it.
+pub fn walk(root: &Path, options: &WalkOptions) -> Result<Vec<Candidate>> {
+    walk_below(root, root, options)
+}
+
+/// Walk a directory below the walk root, listing the paths found with their
+/// path relative to the root, then those re-included by a negated
+/// `--ignore-pattern`.
+fn walk_below(root: &Path, dir: &Path, options: &WalkOptions) -> Result<Vec<Candidate>> {
+    let mut candidates = Vec::new();
+
+    // Iterate through all entries (files and directories) found by the walker
+    for entry in walker(dir, options, true, |_| true) {
+        if options.deadline.as_ref().is_some_and(Deadline::reached) {
+            break;
+        }
+        let entry = entry.with_context(|| "Error during directory traversal")?;
+        let path = entry.path();
+
+        // Skip the root path of the current directory
+        if path == dir {
+            continue;
+        }
+
+        // Named pipes, sockets and devices are kept for the caller to report
+        let is_dir = entry.file_type().is_some_and(|ft| ft.is_dir());
+        let special = entry
+            .file_type()
+            .is_some_and(|ft| !ft.is_dir() && !ft.is_file() && !ft.is_symlink());
+        if !is_dir && !special && !path.is_file() {
+            continue;
+        }
+
+        let display = path
+            .strip_prefix(root)
+            .with_context(|| format!("Failed to strip prefix for path: {}", path.display()))?;
+
+        candidates.push(Candidate {
+            path: path.to_path_buf(),
+            display: display.to_path_buf(),
+            is_dir,
+            range: None,
+            walked: true,
+        });
+    }
+
+    if options.ignore_patterns.num_whitelists() > 0 {
+        reinclude(root, dir, options, &mut candidates)?;
+    }
+    Ok(candidates)
+}
+
+/// Add the paths excluded by an ignore file or for being hidden that a negated
+/// `--ignore-pattern` re-includes, given the candidates found by walking a
+/// directory, as if the patterns came last in a root-level ignore file.
+///
+/// Only the entries of the directories visited are looked at, as the walker
+/// does. A directory re-included is walked in turn, its own entries still
+/// subject to the ignore files.
+fn reinclude(
+    root: &Path,
+    dir: &Path,
+    options: &WalkOptions,
+    candidates: &mut Vec<Candidate>,
+) -> Result<()> {
+    let found: HashSet<PathBuf> = candidates
+        .iter()
+        .map(|candidate| candidate.path.clone())
+        .collect();
+    let mut visited: HashSet<PathBuf> = candidates
+        .iter()
+        .filter(|candidate| candidate.is_dir)
+        .map(|candidate| candidate.path.clone())
+        .collect();
+    visited.insert(dir.to_path_buf());
+
+    // The patterns and --exclude-dir still apply, through the filter of the
+    // walker
+    let bare = WalkOptions {
+        llmrignore: false,
+        dot_ignore: false,
+        gitignore: false,
+        global_excludes: None,
+        ..options.clone()
+    };
+    let walker = walker(dir, &bare, false, move |entry| {
+        entry
+            .path()
+            .parent()
+            .is_some_and(|parent| visited.contains(parent))
+    });
+
+    let mut dirs = Vec::new();
+    for entry in walker {
+        let entry = entry.with_context(|| "Error during directory traversal")?;
+        let path = entry.path();
+        let is_dir = entry.file_type().is_some_and(|ft| ft.is_dir());
+        if path == dir
+            || found.contains(path)
+            || (!is_dir && !path.is_file())
+            || !options.ignore_patterns.matched(path, is_dir).is_whitelist()
+        {
+            continue;
+        }
+
+        let display = path
+            .strip_prefix(root)
+            .with_context(|| format!("Failed to strip prefix for path: {}", path.display()))?;
+        candidates.push(Candidate {
+            path: path.to_path_buf(),
+            display: display.to_path_buf(),
+            is_dir,
+            range: None,
+            walked: true,
+        });
+        if is_dir {
+            dirs.push(path.to_path_buf());
+        }
+    }
+    for dir in dirs {
+        candidates.extend(walk_below(root, &dir, options)?);
+    }
+
+    Ok(())
+}
+
+/// What a path is when it is neither a directory nor a regular file, such as a
+/// named pipe, which a read would block on until something writes to it.
+pub fn special_kind(path: &Path) -> Option<&'static str> {
+    let file_type = fs::metadata(path).ok()?.file_type();
+    if file_type.is_file() || file_type.is_dir() {
+        return None;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_fifo() {
+            return Some("named pipe");
+        } else if file_type.is_socket() {
+            return Some("socket");
+        } else if file_type.is_block_device() {
+            return Some("block device");
+        } else if file_type.is_char_device() {
+            return Some("character device");
+        }
+    }
+    Some("special file")
+}
+
+/// A file found under several paths through symbolic links, and only shown
+/// under one of them.
+pub struct Link {
+    /// The candidate left out.
+    pub candidate: Candidate,
+    /// Where the link points to, as written in the link.
+    pub target: PathBuf,
+    /// The path the content of the file is shown under.
+    pub shown: PathBuf,
+}
+
+/// Leave out the files that are reached again through a symbolic link, to the
+/// file or to a directory above it, keeping the file under its real path if it
+/// was found there, else under the first path it was found at.
+pub fn dedup_links(candidates: &mut Vec<Candidate>) -> Vec<Link> {
+    let canonical: Vec<Option<PathBuf>> = candidates
+        .iter()
+        .map(|candidate| match candidate.is_dir {
+            true => None,
+            false => fs::canonicalize(&candidate.path).ok(),
+        })
+        .collect();
+
+    // Each file is shown under its real path if it was found there, else
+    // under the first path it was found at
+    let mut shown: HashMap<&Path, usize> = HashMap::new();
+    for real in [true, false] {
+        for (index, canonical) in canonical.iter().enumerate() {
+            if let Some(canonical) = canonical {
+                if !real || candidates[index].path == *canonical {
+                    shown.entry(canonical).or_insert(index);
+                }
+            }
+        }
+    }
+
+    let mut links = Vec::new();
+    let mut kept = Vec::new();
+    for (index, candidate) in candidates.iter().enumerate() {
+        match canonical[index]
+            .as_deref()
+            .map(|canonical| shown[canonical])
+        {
+            Some(first) if first != index => links.push(Link {
+                candidate: candidate.clone(),
+                target: fs::read_link(&candidate.path)
+                    .ok()
+                    .or_else(|| canonical[index].clone())
+                    .unwrap_or_default(),
+                shown: candidates[first].display.clone(),
+            }),
+            _ => kept.push(candidate.clone()),
+        }
+    }
+    *candidates = kept;
+
+    links
+}
+
+/// List the paths of a directory excluded by `.gitignore` files, git's
+/// excludes or `--exclude-dir` alone, given the candidates found by walking it.
+///
+/// Only the directories visited by the walk are searched, so the contents of an
+/// ignored directory are not listed, only the directory itself.
+pub fn ignored(
+    root: &Path,
+    options: &WalkOptions,
+    candidates: &[Candidate],
+) -> Result<Vec<Candidate>> {
+    let mut visited: HashSet<PathBuf> = candidates
+        .iter()
+        .filter(|candidate| candidate.walked)
+        .map(|candidate| candidate.path.clone())
+        .collect();
+    visited.insert(root.to_path_buf());
+
+    let options = WalkOptions {
+        gitignore: false,
+        global_excludes: None,
+        exclude_dirs: Vec::new(),
+        ..options.clone()
+    };
+    let filter_visited = visited.clone();
+    let walker = walker(root, &options, true, move |entry| {
+        entry
+            .path()
+            .parent()
+            .is_some_and(|parent| filter_visited.contains(parent))
+    });
+
+    let mut ignored = Vec::new();
+    for entry in walker {
+        let entry = entry.with_context(|| "Error during directory traversal")?;
+        let path = entry.path();
+        if path == root || visited.contains(path) {
+            continue;
+        }
+
+        let is_dir = entry.file_type().is_some_and(|ft| ft.is_dir());
+        if !is_dir && !path.is_file() {
+            continue;
+        }
+
+        let display = path
+            .strip_prefix(root)
+            .with_context(|| format!("Failed to strip prefix for path: {}", path.display()))?;
+        ignored.push(Candidate {
+            path: path.to_path_buf(),
+            display: display.to_path_buf(),
+            is_dir,
+            range: None,
+            walked: true,
+        });
+    }
+
+    Ok(ignored)
+}
+
+/// Add the files matching the patterns given with `--include`, whatever the
+/// hidden and ignore rules, to the candidates found by the walker.
+///
+/// Ignored directories are not traversed by the walker, so each pattern is
+/// searched from the directory it is anchored in, the part of the pattern before
+/// its first wildcard. The `.git` directories and those of `--exclude-dir` below
+/// it are not searched.
+pub fn include(
+    candidates: &mut Vec<Candidate>,
+    root: &Path,
+    options: &WalkOptions,
+    patterns: &[String],
+) -> Result<()> {
+    if patterns.is_empty() {
+        return Ok(());
+    }
+
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .with_context(|| format!("Invalid include pattern: {}", pattern))?;
+        builder.add(glob);
+    }
+    let globs = builder
+        .build()
+        .context("Failed to build include patterns")?;
+
+    let mut seen: HashSet<PathBuf> = candidates
+        .iter()
+        .map(|candidate| candidate.path.clone())
+        .collect();
+
+    for pattern in patterns {
+        let base: PathBuf = Path::new(pattern)
+            .components()
+            .take_while(|component| {
+                !component
+                    .as_os_str()
+                    .to_string_lossy()
+                    .contains(['*', '?', '[', '{'])
+            })
+            .collect();
+        let base = root.join(base);
+        if !base.exists() {
+            continue;
+        }
+
+        let exclude_dirs = options.exclude_dirs.clone();
+        let walker = WalkBuilder::new(&base)
+            .standard_filters(false)
+            .follow_links(options.follow_symlinks)
+            .filter_entry(move |entry| {
+                let is_dir = entry.file_type().is_some_and(|ft| ft.is_dir());
+                entry.depth() == 0
+                    || !is_dir
+                    || entry.file_name().to_str().is_none_or(|name| {
+                        name != ".git" && !exclude_dirs.iter().any(|excluded| excluded == name)
+                    })
+            })
+            .build();
+        for entry in walker {
+            let entry = entry.with_context(|| "Error during directory traversal")?;
+            let path = entry.path();
+            if !path.is_file() || seen.contains(path) {
+                continue;
+            }
+
+            let Ok(display) = path.strip_prefix(root) else {
+                continue;
+            };
+            if !globs.is_match(display) {
+                continue;
+            }
+
+            seen.insert(path.to_path_buf());
+            candidates.push(Candidate {
+                path: path.to_path_buf(),
+                display: display.to_path_buf(),
+                is_dir: false,
+                range: None,
+                walked: false,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// A file listed in a JSON `--files-from` list.
+#[derive(Debug, Deserialize)]
+struct FileSpec {
+    path: PathBuf,
+    range: Option<String>,
+    rename: Option<PathBuf>,
+}
+
+/// Read the files to process from a list, or from stdin if the list is `-`.
+pub fn files_from(root: &Path, list: &Path, format: ListFormat) -> Result<Vec<Candidate>> {
+    let mut content = String::new();
+    if list == Path::new("-") {
+        io::stdin()
+            .read_to_string(&mut content)
+            .context("Failed to read file list from stdin")?;
+    } else {
+        File::open(list)
+            .and_then(|mut file| file.read_to_string(&mut content))
+            .with_context(|| format!("Failed to read file list: {}", list.display()))?;
+    }
+
+    let format = match format {
+        ListFormat::Auto if list.extension().is_some_and(|ext| ext == "json") => ListFormat::Json,
+        ListFormat::Auto => ListFormat::Plain,
+        format => format,
+    };
+
+    let specs: Vec<FileSpec> = match format {
+        ListFormat::Json => serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse file list: {}", list.display()))?,
+        _ => content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| FileSpec {
+                path: PathBuf::from(line),
+                range: None,
+                rename: None,
+            })
+            .collect(),
+    };
+
+    specs
+        .into_iter()
+        .map(|spec| {
+            let path = root.join(&spec.path);
+            let display = match spec.rename {
+                Some(rename) => rename,
+                None => path.strip_prefix(root).unwrap_or(&spec.path).to_path_buf(),
+            };
+            let range = spec.range.as_deref().map(LineRange::parse).transpose()?;
+
+            Ok(Candidate {
+                path,
+                display,
+                is_dir: false,
+                range,
+                walked: false,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Create a directory holding files, given by their path and content, in
+    /// a fresh directory of its own.
+    pub(crate) fn fixture(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("llmr-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (path, content) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        root
+    }
+
+    pub(crate) fn options() -> WalkOptions {
+        WalkOptions {
+            ignore_patterns: Gitignore::empty(),
+            exclude_dirs: Vec::new(),
+            llmrignore: true,
+            dot_ignore: true,
+            gitignore: true,
+            global_excludes: None,
+            parents: true,
+            require_git: false,
+            follow_symlinks: false,
+            deadline: None,
+        }
+    }
+
+    /// The files found by walking a directory, as shown.
+    pub(crate) fn files(root: &Path, options: &WalkOptions) -> Vec<String> {
+        let mut files: Vec<String> = walk(root, options)
+            .unwrap()
+            .into_iter()
+            .filter(|candidate| !candidate.is_dir)
+            .map(|candidate| candidate.display.display().to_string())
+            .collect();
+        files.sort();
+        files
+    }
+
+    /// A repository ignoring `/src/generated` from its root.
+    fn repository(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let mut all = vec![
+            (".git/HEAD", "ref: refs/heads/main\n"),
+            (".gitignore", "/src/generated\n"),
+            ("src/generated/a.rs", ""),
+            ("src/lib.rs", ""),
+        ];
+        all.extend_from_slice(files);
+        fixture(name, &all)
+    }
+
+    #[test]
+    fn anchored_parent_patterns_apply_to_a_subdirectory() {
+        let root = repository("anchored-parent", &[]);
+        assert_eq!(files(&root.join("src"), &options()), ["lib.rs"]);
+    }
+
+    #[test]
+    fn child_negation_overrides_anchored_parent_pattern() {
+        let root = repository("child-negation", &[("src/.gitignore", "!generated\n")]);
+        assert_eq!(
+            files(&root.join("src"), &options()),
+            ["generated/a.rs", "lib.rs"]
+        );
+    }
+
+    #[test]
+    fn parent_exclude_file_applies_to_a_subdirectory() {
+        let root = repository("parent-exclude", &[(".git/info/exclude", "/src/lib.rs\n")]);
+        assert!(files(&root.join("src"), &options()).is_empty());
+    }
+
+    #[test]
+    fn no_parent_ignore_leaves_out_parent_files_only() {
+        let root = repository(
+            "no-parent",
+            &[("src/.gitignore", "*.tmp\n"), ("src/a.tmp", "")],
+        );
+        let options = WalkOptions {
+            parents: false,
+            ..options()
+        };
+        assert_eq!(
+            files(&root.join("src"), &options),
+            ["generated/a.rs", "lib.rs"]
+        );
+    }
+
+    /// Ignore files of each kind disagreeing on the same paths.
+    fn disagreeing(name: &str) -> PathBuf {
+        fixture(
+            name,
+            &[
+                (".gitignore", "a.rs\nb.rs\nc.rs\n"),
+                (".ignore", "!a.rs\n!b.rs\nd.rs\n"),
+                (LLMRIGNORE, "b.rs\n!d.rs\n"),
+                ("a.rs", ""),
+                ("b.rs", ""),
+                ("c.rs", ""),
+                ("d.rs", ""),
+                ("e.rs", ""),
+            ],
+        )
+    }
+
+    #[test]
+    fn llmrignore_then_dot_ignore_then_gitignore_decide() {
+        let root = disagreeing("precedence");
+        assert_eq!(files(&root, &options()), ["a.rs", "d.rs", "e.rs"]);
+
+        let without_dot_ignore = WalkOptions {
+            dot_ignore: false,
+            ..options()
+        };
+        assert_eq!(files(&root, &without_dot_ignore), ["d.rs", "e.rs"]);
+
+        let without_llmrignore = WalkOptions {
+            llmrignore: false,
+            ..options()
+        };
+        assert_eq!(files(&root, &without_llmrignore), ["a.rs", "b.rs", "e.rs"]);
+    }
+
+    /// The options with patterns given with `--ignore-pattern`.
+    fn with_patterns(root: &Path, patterns: &[&str]) -> WalkOptions {
+        let patterns: Vec<String> = patterns.iter().map(|pattern| pattern.to_string()).collect();
+        WalkOptions {
+            ignore_patterns: ignore_patterns(root, &patterns).unwrap(),
+            ..options()
+        }
+    }
+
+    #[test]
+    fn ignore_patterns_negate_and_anchor_like_gitignore() {
+        let root = fixture(
+            "ignore-patterns",
+            &[
+                ("testdata/big.json", ""),
+                ("testdata/small.json", ""),
+                ("a.rs", ""),
+                ("src/a.rs", ""),
+            ],
+        );
+        let options = with_patterns(&root, &["testdata/*", "!testdata/small.json", "/a.rs"]);
+        assert_eq!(files(&root, &options), ["src/a.rs", "testdata/small.json"]);
+
+        // A directory pattern prunes the directory, which a negation cannot
+        // re-include files from, as in git
+        let options = with_patterns(&root, &["testdata/", "!testdata/small.json"]);
+        assert_eq!(files(&root, &options), ["a.rs", "src/a.rs"]);
+    }
+
+    #[test]
+    fn ignore_patterns_come_after_the_ignore_files() {
+        let root = fixture(
+            "ignore-pattern-layers",
+            &[
+                (".gitignore", "*.log\n"),
+                (LLMRIGNORE, "*.tmp\n"),
+                ("a.log", ""),
+                ("a.tmp", ""),
+                ("a.rs", ""),
+                ("build/a.rs", ""),
+            ],
+        );
+
+        // A negated pattern re-includes what an ignore file excludes, as
+        // the last rule of a root-level ignore file would
+        let options = with_patterns(&root, &["!a.log", "!a.tmp"]);
+        assert_eq!(
+            files(&root, &options),
+            ["a.log", "a.rs", "a.tmp", "build/a.rs"]
+        );
+
+        // It excludes what the ignore files keep, as does --exclude-dir,
+        // which no negation overrides
+        let options = WalkOptions {
+            exclude_dirs: vec!["build".to_string()],
+            ..with_patterns(&root, &["a.rs", "!build/a.rs"])
+        };
+        assert!(files(&root, &options).is_empty());
+    }
+
+    #[test]
+    fn negated_ignore_patterns_re_include_directories() {
+        let root = fixture(
+            "ignore-pattern-directories",
+            &[
+                (".gitignore", "build/\nlogs/\n*.o\n"),
+                (".env", ""),
+                ("build/a.rs", ""),
+                ("build/a.o", ""),
+                ("logs/a.log", ""),
+            ],
+        );
+
+        // The contents of a directory re-included are still subject to the
+        // ignore files, and those of a directory left excluded stay out
+        let options = with_patterns(&root, &["!build/", "!*.log", "!.env"]);
+        assert_eq!(files(&root, &options), [".env", "build/a.rs"]);
+    }
+
+    #[test]
+    fn global_excludes_come_after_the_repository_files() {
+        let root = fixture(
+            "global-walk",
+            &[
+                ("global-ignore", "*.swp\nbuild/\n*.keep\n"),
+                ("tree/.git/HEAD", ""),
+                ("tree/.git/info/exclude", "*.local\n"),
+                ("tree/.gitignore", "!*.keep\n"),
+                ("tree/build/a.o", ""),
+                ("tree/a.rs.swp", ""),
+                ("tree/a.keep", ""),
+                ("tree/a.local", ""),
+                ("tree/a.rs", ""),
+            ],
+        );
+        let options = WalkOptions {
+            global_excludes: Some(root.join("global-ignore")),
+            ..options()
+        };
+        assert_eq!(files(&root.join("tree"), &options), ["a.keep", "a.rs"]);
+
+        let without_global = WalkOptions {
+            global_excludes: None,
+            ..options
+        };
+        assert_eq!(
+            files(&root.join("tree"), &without_global),
+            ["a.keep", "a.rs", "a.rs.swp", "build/a.o"]
+        );
+    }
+
+    #[test]
+    fn gitignore_applies_outside_a_repository_unless_required() {
+        let files_of = |name: &str, repository: bool, require_git: bool| {
+            let mut tree = vec![
+                ("global-ignore", "*.swp\n"),
+                ("tree/.gitignore", "*.log\n"),
+                ("tree/.ignore", "*.tmp\n"),
+                ("tree/a.log", ""),
+                ("tree/a.rs", ""),
+                ("tree/a.swp", ""),
+                ("tree/a.tmp", ""),
+            ];
+            if repository {
+                tree.push(("tree/.git/HEAD", ""));
+            }
+            let root = fixture(name, &tree);
+            let options = WalkOptions {
+                global_excludes: Some(root.join("global-ignore")),
+                require_git,
+                ..options()
+            };
+            files(&root.join("tree"), &options)
+        };
+
+        assert_eq!(files_of("plain", false, false), ["a.rs"]);
+        assert_eq!(files_of("repo", true, false), ["a.rs"]);
+        assert_eq!(files_of("repo-required", true, true), ["a.rs"]);
+        // .ignore files apply whether or not git's files do
+        assert_eq!(
+            files_of("plain-required", false, true),
+            ["a.log", "a.rs", "a.swp"]
+        );
+    }
+
+    #[test]
+    fn deadline_tells_whether_the_walk_was_cut_short() {
+        let root = fixture("deadline", &[("a.rs", ""), ("src/b.rs", "")]);
+        let walked = |at| {
+            let options = WalkOptions {
+                deadline: Some(Deadline::new(at)),
+                ..options()
+            };
+            let found = files(&root, &options).len();
+            (found, options.deadline.unwrap().cut_short())
+        };
+
+        assert_eq!(walked(Instant::now()), (0, true));
+        // A walk that finishes in time is not reported, whenever it ends
+        let far = Instant::now() + std::time::Duration::from_secs(3600);
+        assert_eq!(walked(far), (2, false));
+    }
+
+    /// The files found by walking a directory, then adding those of `--include`.
+    fn included(root: &Path, options: &WalkOptions, patterns: &[String]) -> Vec<String> {
+        let mut candidates = walk(root, options).unwrap();
+        include(&mut candidates, root, options, patterns).unwrap();
+        let mut files: Vec<String> = candidates
+            .into_iter()
+            .filter(|candidate| !candidate.is_dir)
+            .map(|candidate| candidate.display.display().to_string())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn include_beats_ignore_patterns_and_ignore_files() {
+        let root = fixture(
+            "include",
+            &[
+                (".gitignore", "node_modules/\n"),
+                (
+                    ".llmr/include",
+                    "# Our own packages\nnode_modules/@myorg/**\n",
+                ),
+                (".llmr/exclude", "vendor/\n"),
+                (".env.example", ""),
+                ("node_modules/@myorg/a/index.js", ""),
+                ("node_modules/left-pad/index.js", ""),
+                ("vendor/keep.rs", ""),
+                ("vendor/other.rs", ""),
+                ("a.rs", ""),
+            ],
+        );
+        let exclude = llmr_patterns(&root, "exclude").unwrap();
+        let options = WalkOptions {
+            ignore_patterns: ignore_patterns(&root, &exclude).unwrap(),
+            ..options()
+        };
+        assert_eq!(exclude, ["vendor/"]);
+        assert_eq!(files(&root, &options), ["a.rs"]);
+
+        let mut patterns = llmr_patterns(&root, "include").unwrap();
+        patterns.extend([".env.example".to_string(), "vendor/keep.rs".to_string()]);
+        assert_eq!(
+            included(&root, &options, &patterns),
+            [
+                ".env.example",
+                "a.rs",
+                "node_modules/@myorg/a/index.js",
+                "vendor/keep.rs"
+            ]
+        );
+    }
+
+    #[test]
+    fn include_globs_do_not_cross_directories() {
+        let root = fixture(
+            "include-separator",
+            &[
+                (".gitignore", "*.gen.rs\n"),
+                ("a.gen.rs", ""),
+                ("src/b.gen.rs", ""),
+            ],
+        );
+        let patterns = ["*.gen.rs".to_string()];
+        assert_eq!(included(&root, &options(), &patterns), ["a.gen.rs"]);
+    }
+
+    #[test]
+    fn include_skips_git_and_excluded_directories() {
+        let root = fixture(
+            "include-git",
+            &[
+                (".git/hooks/pre-commit.sample", ""),
+                (".git/HEAD", ""),
+                ("target/debug/build.log", ""),
+                ("sub/.git/HEAD", ""),
+                ("a.log", ""),
+            ],
+        );
+        let options = WalkOptions {
+            exclude_dirs: vec!["target".to_string()],
+            ..options()
+        };
+        assert_eq!(included(&root, &options, &["**/*".to_string()]), ["a.log"]);
+        // Unless the pattern is anchored in one
+        assert_eq!(
+            included(&root, &options, &[".git/HEAD".to_string()]),
+            [".git/HEAD", "a.log"]
+        );
+    }
+
+    #[test]
+    fn ignore_sources_follow_the_options() {
+        let root = repository("sources", &[("src/.ignore", "*.tmp\n")]);
+        let options = WalkOptions {
+            llmrignore: false,
+            ..options()
+        };
+        let sources = ignore_sources(&root.join("src"), &options, &[], &["*.log".to_string()]);
+        assert_eq!(sources[1], "--ignore-pattern and .llmr/exclude: *.log");
+        assert_eq!(sources[3], ".llmrignore: off (--no-llmrignore)");
+        assert_eq!(
+            sources[4],
+            format!(".ignore: on, {}", root.join("src/.ignore").display())
+        );
+        assert_eq!(
+            sources[5],
+            format!(".gitignore: on, {}", root.join(".gitignore").display())
+        );
+        assert_eq!(sources[6], ".git/info/exclude: none");
+    }
+}
@@ -0,0 +1,70 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::collision::fnv1a;
+
+/// What `--state` records of the parts written so far, for the next run to
+/// continue where they left off.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    /// Number of parts written.
+    pub parts: usize,
+    /// Whether the last part held all the files left.
+    pub complete: bool,
+    /// The files sent, in the order they were.
+    pub sent: Vec<SentFile>,
+}
+
+/// A file sent in a part.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SentFile {
+    /// Path relative to the directory the run started from.
+    pub path: PathBuf,
+    /// Hash of the content sent, to send the file again once it changes.
+    pub hash: String,
+    pub part: usize,
+}
+
+impl State {
+    /// The record of a file sent in a previous part, if any.
+    pub fn sent(&self, path: &Path) -> Option<&SentFile> {
+        self.sent.iter().find(|file| file.path == path)
+    }
+
+    /// Record a file sent in a part, replacing its previous record.
+    pub fn record(&mut self, path: &Path, content: &str, part: usize) {
+        self.sent.retain(|file| file.path != path);
+        self.sent.push(SentFile {
+            path: path.to_path_buf(),
+            hash: content_hash(content),
+            part,
+        });
+    }
+}
+
+/// Stable hash of the content of a file, short and fast to tell the files
+/// changed between runs. It does not resist collisions made on purpose, which
+/// the manifests of `--content-hash-manifest` do with SHA-256.
+pub fn content_hash(content: &str) -> String {
+    format!("{:016x}", fnv1a(content.as_bytes()))
+}
+
+/// Read the state, starting over when the file does not exist yet.
+pub fn read(path: &Path) -> Result<State> {
+    if !path.exists() {
+        return Ok(State::default());
+    }
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read state: {}", path.display()))?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse state: {}", path.display()))
+}
+
+pub fn write(path: &Path, state: &State) -> Result<()> {
+    let json = serde_json::to_string_pretty(state).context("Failed to serialize state")?;
+    fs::write(path, json + "\n")
+        .with_context(|| format!("Failed to write state: {}", path.display()))
+}
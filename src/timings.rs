@@ -0,0 +1,105 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+/// A phase of a run, timed with `--timings`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Finding the paths to process.
+    Walk,
+    /// Telling text files from non-text files.
+    Detect,
+    /// Reading the content of the files.
+    Read,
+    /// Changing the content of the files, such as extracting line ranges.
+    Transform,
+    /// Counting tokens.
+    Tokenize,
+    /// Printing the output.
+    Write,
+}
+
+impl Phase {
+    const ALL: [Phase; 6] = [
+        Phase::Walk,
+        Phase::Detect,
+        Phase::Read,
+        Phase::Transform,
+        Phase::Tokenize,
+        Phase::Write,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Phase::Walk => "walk",
+            Phase::Detect => "detect",
+            Phase::Read => "read",
+            Phase::Transform => "transform",
+            Phase::Tokenize => "tokenize",
+            Phase::Write => "write",
+        }
+    }
+}
+
+/// Time spent in each phase.
+///
+/// Work done on several threads adds up the time of every thread, so a phase
+/// may take longer than the whole run.
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+    durations: [Duration; Phase::ALL.len()],
+}
+
+impl Timings {
+    pub fn add(&mut self, phase: Phase, duration: Duration) {
+        self.durations[phase as usize] += duration;
+    }
+
+    /// Run a function, adding the time it takes to a phase.
+    pub fn time<T>(&mut self, phase: Phase, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.add(phase, start.elapsed());
+        result
+    }
+
+    /// Add the durations measured by another run of some of the phases.
+    pub fn merge(&mut self, other: &Timings) {
+        for phase in Phase::ALL {
+            self.add(phase, other.durations[phase as usize]);
+        }
+    }
+
+    /// Print the time spent in each phase to stderr.
+    pub fn print(&self) {
+        eprintln!("Timings:");
+        for phase in Phase::ALL {
+            eprintln!(
+                "    {:<10} {:.2?}",
+                phase.name(),
+                self.durations[phase as usize]
+            );
+        }
+    }
+}
+
+/// Parse a duration such as `500ms`, `30s`, `2m` or `1h`, in seconds when it
+/// has no unit.
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let duration = duration.trim();
+    let split = duration
+        .find(|character: char| !character.is_ascii_digit() && character != '.')
+        .unwrap_or(duration.len());
+    let (value, unit) = duration.split_at(split);
+    let value: f64 = value
+        .parse()
+        .with_context(|| format!("Invalid duration: {}", duration))?;
+    let seconds = match unit.trim() {
+        "ms" => value / 1000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => anyhow::bail!("Invalid duration unit: {} (use ms, s, m or h)", duration),
+    };
+    Ok(Duration::from_secs_f64(seconds))
+}
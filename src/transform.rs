@@ -0,0 +1,357 @@
+use std::path::Path;
+
+use serde_json::Value;
+
+/// Extensions of the prose files wrapped by `--reflow`.
+const PROSE_EXTENSIONS: [&str; 3] = ["md", "txt", "rst"];
+
+/// Text telling a leading comment block apart as a license header, compared
+/// case-insensitively.
+const LICENSE_MARKERS: [&str; 5] = [
+    "spdx-license-identifier",
+    "copyright",
+    "licensed under",
+    "permission is hereby granted",
+    "all rights reserved",
+];
+
+/// Openers and closers of block comments.
+const BLOCK_COMMENTS: [(&str, &str); 4] =
+    [("/*", "*/"), ("<!--", "-->"), ("{-", "-}"), ("(*", "*)")];
+
+/// Prefixes of line comments.
+const LINE_COMMENTS: [&str; 4] = ["//", "#", "--", ";"];
+
+/// Changes applied to the content of each file before it is emitted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Transforms {
+    /// Remove the indentation shared by all the lines.
+    pub dedent: bool,
+    /// Remove the blank lines at the start and the end of the content.
+    pub strip_blank_bounds: bool,
+    /// Wrap the long lines of prose files to this width.
+    pub reflow: Option<usize>,
+    /// Remove the comment block holding the license at the start of the content.
+    pub strip_license_headers: bool,
+    /// Keep the byte-order mark starting the content.
+    pub preserve_bom: bool,
+    /// Remove the null and empty fields of JSON files, written back compactly.
+    pub compact_json: bool,
+}
+
+impl Transforms {
+    /// Remove the UTF-8 byte-order mark starting the content, unless asked to
+    /// keep it.
+    pub fn strip_bom(&self, content: String) -> String {
+        match content.strip_prefix('\u{feff}') {
+            Some(rest) if !self.preserve_bom => rest.to_string(),
+            _ => content,
+        }
+    }
+
+    /// Remove the indentation shared by all the lines if asked, returning the
+    /// content and the indentation removed from each line.
+    pub fn dedent(&self, content: String) -> (String, String) {
+        if !self.dedent {
+            return (content, String::new());
+        }
+
+        let indent = common_indent(&content).to_string();
+        if indent.is_empty() {
+            return (content, indent);
+        }
+        let lines: Vec<&str> = content
+            .lines()
+            .map(|line| {
+                line.strip_prefix(indent.as_str())
+                    .unwrap_or(line.trim_start())
+            })
+            .collect();
+        (lines.join("\n"), indent)
+    }
+
+    /// Remove the license header of the content if asked, returning the
+    /// content and the header removed.
+    pub fn strip_license_header(&self, path: &Path, content: String) -> (String, String) {
+        if !self.strip_license_headers || is_prose(path) {
+            return (content, String::new());
+        }
+
+        match license_header(&content) {
+            Some((start, end)) => {
+                let header = content[start..end].to_string();
+                (format!("{}{}", &content[..start], &content[end..]), header)
+            }
+            None => (content, String::new()),
+        }
+    }
+
+    pub fn apply(&self, path: &Path, mut content: String) -> String {
+        if self.compact_json && is_json(path) {
+            if let Some(compacted) = compact_json(&content) {
+                content = compacted;
+            }
+        }
+        if let Some(width) = self.reflow.filter(|_| is_prose(path)) {
+            content = reflow(&content, width);
+        }
+        if self.strip_blank_bounds {
+            content = strip_blank_bounds(&content).to_string();
+        }
+        content
+    }
+}
+
+/// The leading whitespace shared by all the lines that are not blank.
+fn common_indent(content: &str) -> &str {
+    let mut common: Option<&str> = None;
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        let indent = &line[..line.len() - line.trim_start().len()];
+        common = Some(match common {
+            None => indent,
+            Some(common) => {
+                let shared = common
+                    .char_indices()
+                    .zip(indent.chars())
+                    .find(|((_, a), b)| a != b)
+                    .map_or(common.len().min(indent.len()), |((index, _), _)| index);
+                &common[..shared]
+            }
+        });
+    }
+    common.unwrap_or("")
+}
+
+/// The byte range of the comment block starting the content, after a shebang
+/// line and blank lines, along with the blank lines following it, when the
+/// block holds a license marker. Only whole comment lines are taken, so a
+/// block closed on a line holding code is left alone.
+fn license_header(content: &str) -> Option<(usize, usize)> {
+    let mut lines = content
+        .split_inclusive('\n')
+        .scan(0, |offset, line| {
+            let start = *offset;
+            *offset += line.len();
+            Some((start, line))
+        })
+        .peekable();
+
+    // Step 1: Skip the shebang line and the blank lines
+    if let Some((_, line)) = lines.peek() {
+        if line.starts_with("#!") && !line.starts_with("#![") {
+            lines.next();
+        }
+    }
+    while lines.next_if(|(_, line)| line.trim().is_empty()).is_some() {}
+    let (start, first) = *lines.peek()?;
+    let first = first.trim();
+
+    // Step 2: Take the comment block
+    let mut end = start;
+    if let Some((opener, closer)) = BLOCK_COMMENTS
+        .iter()
+        .find(|(opener, _)| first.starts_with(opener))
+    {
+        let mut text = &first[opener.len()..];
+        loop {
+            let (offset, line) = lines.next()?;
+            end = offset + line.len();
+            if offset != start {
+                text = line.trim();
+            }
+            if let Some(index) = text.find(closer) {
+                if !text[index + closer.len()..].trim().is_empty() {
+                    return None;
+                }
+                break;
+            }
+        }
+    } else {
+        let prefix = LINE_COMMENTS
+            .iter()
+            .find(|prefix| is_line_comment(first, prefix))?;
+        while let Some((offset, line)) =
+            lines.next_if(|(_, line)| is_line_comment(line.trim(), prefix))
+        {
+            end = offset + line.len();
+        }
+    }
+    let block = content[start..end].to_lowercase();
+    if !LICENSE_MARKERS.iter().any(|marker| block.contains(marker)) {
+        return None;
+    }
+
+    // Step 3: Take the blank lines after it
+    while let Some((offset, line)) = lines.next_if(|(_, line)| line.trim().is_empty()) {
+        end = offset + line.len();
+    }
+    Some((start, end))
+}
+
+/// Whether a trimmed line is a comment starting with the prefix, leaving out
+/// doc comments of the next item and attributes.
+fn is_line_comment(line: &str, prefix: &str) -> bool {
+    let Some(rest) = line.strip_prefix(prefix) else {
+        return false;
+    };
+    match prefix {
+        "//" => !rest.starts_with('/') || rest.starts_with("//"),
+        "#" => rest.is_empty() || rest.starts_with([' ', '#']),
+        _ => true,
+    }
+}
+
+/// Remove the fields holding null, an empty array or an empty object, at any
+/// depth, and write the rest back without whitespace, keeping the order of the
+/// keys. Content that does not parse as JSON is left alone.
+fn compact_json(content: &str) -> Option<String> {
+    let mut value: Value = serde_json::from_str(content).ok()?;
+    prune(&mut value);
+    serde_json::to_string(&value).ok()
+}
+
+/// Remove the empty fields of the objects within a value. Array elements are
+/// kept, so that positions still mean the same.
+fn prune(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for field in fields.values_mut() {
+                prune(field);
+            }
+            fields.retain(|_, field| !is_empty(field));
+        }
+        Value::Array(items) => items.iter_mut().for_each(prune),
+        _ => {}
+    }
+}
+
+fn is_empty(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::Array(items) => items.is_empty(),
+        Value::Object(fields) => fields.is_empty(),
+        _ => false,
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
+
+fn is_prose(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| PROSE_EXTENSIONS.contains(&extension))
+}
+
+/// Wrap the lines longer than the width, leaving fenced code blocks and tables
+/// untouched.
+fn reflow(content: &str, width: usize) -> String {
+    let mut lines = Vec::new();
+    let mut fenced = false;
+
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fenced = !fenced;
+            lines.push(line.to_string());
+        } else if fenced || trimmed.starts_with('|') || line.chars().count() <= width {
+            lines.push(line.to_string());
+        } else {
+            wrap_line(line, width, &mut lines);
+        }
+    }
+
+    lines.join("\n")
+}
+
+/// Wrap a line at word boundaries, indenting the continuation lines like the
+/// text of the line, after its indentation and list marker if any.
+fn wrap_line(line: &str, width: usize, lines: &mut Vec<String>) {
+    let text = line.trim_start();
+    let indent = &line[..line.len() - text.len()];
+    let marker = list_marker(text);
+    let hanging = format!("{}{}", indent, " ".repeat(marker.len()));
+
+    let mut current = format!("{}{}", indent, marker);
+    let mut empty = true;
+    for word in text[marker.len()..].split_whitespace() {
+        if !empty && current.chars().count() + 1 + word.chars().count() > width {
+            lines.push(current);
+            current = hanging.clone();
+            empty = true;
+        }
+        if !empty {
+            current.push(' ');
+        }
+        current.push_str(word);
+        empty = false;
+    }
+    lines.push(current);
+}
+
+/// The list marker at the start of a line, such as `- ` or `12. `, if any.
+fn list_marker(text: &str) -> &str {
+    for bullet in ["- ", "* ", "+ "] {
+        if text.starts_with(bullet) {
+            return bullet;
+        }
+    }
+
+    let digits = text.chars().take_while(char::is_ascii_digit).count();
+    let rest = &text[digits..];
+    if digits > 0 && (rest.starts_with(". ") || rest.starts_with(") ")) {
+        return &text[..digits + 2];
+    }
+
+    ""
+}
+
+/// Remove the lines holding only whitespace at the start and the end of the
+/// content, keeping the indentation of the first line with real content.
+fn strip_blank_bounds(content: &str) -> &str {
+    let mut start = None;
+    let mut end = 0;
+    let mut offset = 0;
+
+    for line in content.split_inclusive('\n') {
+        if !line.trim().is_empty() {
+            start.get_or_insert(offset);
+            end = offset + line.trim_end_matches(['\r', '\n']).len();
+        }
+        offset += line.len();
+    }
+
+    match start {
+        Some(start) => &content[start..end],
+        None => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_order_mark_is_stripped_unless_preserved() {
+        let content = "\u{feff}fn main() {}\n".to_string();
+        assert_eq!(
+            Transforms::default().strip_bom(content.clone()),
+            "fn main() {}\n"
+        );
+
+        let preserve = Transforms {
+            preserve_bom: true,
+            ..Transforms::default()
+        };
+        assert_eq!(preserve.strip_bom(content.clone()), content);
+    }
+
+    #[test]
+    fn only_a_leading_byte_order_mark_is_stripped() {
+        let content = "a\u{feff}b\n".to_string();
+        assert_eq!(Transforms::default().strip_bom(content.clone()), content);
+        assert_eq!(Transforms::default().strip_bom("\u{feff}".to_string()), "");
+    }
+}
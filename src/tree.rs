@@ -0,0 +1,369 @@
+use std::cmp::Reverse;
+use std::ops::Add;
+use std::path::{Component, Path, PathBuf};
+
+use clap::ValueEnum;
+
+use crate::format_size;
+
+/// The name of the root of the tree for a directory given as a path: `.` for
+/// the current directory, else the path as given without the separators
+/// ending it, so that a filesystem root stays `/` and `../` shows as `..`.
+pub fn root_name(given: &Path) -> String {
+    let components: PathBuf = given
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .collect();
+    match components.as_os_str().is_empty() {
+        true => ".".to_string(),
+        false => components.display().to_string(),
+    }
+}
+
+/// What an included file weighs.
+#[derive(Debug, Clone, Copy)]
+pub struct Weight {
+    pub lines: usize,
+    /// Size of the file on disk, in bytes.
+    pub size: u64,
+    /// Number of tokens of the content, if counted.
+    pub tokens: Option<usize>,
+}
+
+/// A directory or file shown in the tree.
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub name: String,
+    pub is_dir: bool,
+    /// Whether the file is a non-text file.
+    pub binary: bool,
+    /// Whether the entry is ignored by git, and shown for context only.
+    pub ignored: bool,
+    /// What the entry is when it is not read as a file, such as a symlink.
+    pub marker: Option<String>,
+    /// What an included file weighs.
+    pub weight: Option<Weight>,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    fn new(name: &str, is_dir: bool) -> Self {
+        Node {
+            name: name.to_string(),
+            is_dir,
+            binary: false,
+            ignored: false,
+            marker: None,
+            weight: None,
+            children: Vec::new(),
+        }
+    }
+
+    /// A measure of the weight of a file, or its sum over all the files below
+    /// a directory.
+    fn total<T: Add<Output = T>>(&self, measure: &impl Fn(&Weight) -> Option<T>) -> Option<T> {
+        if !self.is_dir {
+            return self.weight.as_ref().and_then(measure);
+        }
+
+        self.children
+            .iter()
+            .filter_map(|child| child.total(measure))
+            .reduce(|total, value| total + value)
+    }
+
+    /// Annotations shown after the name.
+    fn annotations(&self, options: &RenderOptions) -> String {
+        let mut annotations = String::new();
+        if self.binary {
+            annotations.push_str(" [Non-text file]");
+        }
+        if self.ignored {
+            annotations.push_str(" [ignored]");
+        }
+        if let Some(marker) = &self.marker {
+            annotations.push_str(&format!(" [{}]", marker));
+        }
+        if options.line_counts {
+            match self.total(&|weight| Some(weight.lines)) {
+                Some(1) => annotations.push_str(" [1 line]"),
+                Some(lines) => annotations.push_str(&format!(" [{} lines]", lines)),
+                None => {}
+            }
+        }
+        if options.sizes {
+            if let Some(size) = self.total(&|weight| Some(weight.size)) {
+                annotations.push_str(&format!(" [{}", format_size(size)));
+                if let Some(tokens) = self.total(&|weight| weight.tokens) {
+                    annotations.push_str(&format!(", {} tokens", tokens));
+                }
+                annotations.push(']');
+            }
+        }
+        annotations
+    }
+
+    /// Find or create the child directory with the given name.
+    fn dir(&mut self, name: &str) -> &mut Node {
+        let position = self
+            .children
+            .iter()
+            .position(|child| child.is_dir && child.name == name);
+
+        match position {
+            Some(index) => &mut self.children[index],
+            None => {
+                self.children.push(Node::new(name, true));
+                self.children.last_mut().expect("child was just pushed")
+            }
+        }
+    }
+}
+
+/// Order of the entries of the tree among their siblings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TreeSort {
+    /// The order in which the paths were found.
+    Walk,
+    /// Alphabetical order of the names.
+    Name,
+    /// Heaviest directories first, by the tokens of the files included below
+    /// them, the files keeping their place.
+    Weight,
+}
+
+/// The directory structure of the processed files, in the order they were added.
+#[derive(Debug, Clone)]
+pub struct Tree {
+    pub root: Node,
+}
+
+impl Tree {
+    pub fn new(root_name: &str) -> Self {
+        Tree {
+            root: Node::new(root_name, true),
+        }
+    }
+
+    /// Walk down to the directory containing a relative path, creating missing
+    /// directories along the way, and return it with the path's file name.
+    fn parent_of<'a>(&mut self, relative: &'a Path) -> (&mut Node, Option<&'a str>) {
+        let names: Vec<&str> = relative
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => name.to_str(),
+                Component::ParentDir => Some(".."),
+                _ => None,
+            })
+            .collect();
+
+        let mut node = &mut self.root;
+        let Some((last, parents)) = names.split_last() else {
+            return (node, None);
+        };
+        for name in parents {
+            node = node.dir(name);
+        }
+
+        (node, Some(last))
+    }
+
+    /// Add a directory, given relative to the root.
+    pub fn add_dir(&mut self, relative: &Path) {
+        if let (parent, Some(name)) = self.parent_of(relative) {
+            parent.dir(name);
+        }
+    }
+
+    /// Add a file, given relative to the root, with its weight if it is
+    /// included.
+    pub fn add_file(&mut self, relative: &Path, binary: bool, weight: Option<Weight>) {
+        if let (parent, Some(name)) = self.parent_of(relative) {
+            let mut node = Node::new(name, false);
+            node.binary = binary;
+            node.weight = weight;
+            parent.children.push(node);
+        }
+    }
+
+    /// Add a file or directory ignored by git, given relative to the root.
+    pub fn add_ignored(&mut self, relative: &Path, is_dir: bool) {
+        if let (parent, Some(name)) = self.parent_of(relative) {
+            let node = if is_dir {
+                parent.dir(name)
+            } else {
+                parent.children.push(Node::new(name, false));
+                parent.children.last_mut().expect("child was just pushed")
+            };
+            node.ignored = true;
+        }
+    }
+
+    /// Add a file, given relative to the root, shown with a marker instead of
+    /// being read.
+    pub fn add_marked(&mut self, relative: &Path, marker: &str) {
+        if let (parent, Some(name)) = self.parent_of(relative) {
+            let mut node = Node::new(name, false);
+            node.marker = Some(marker.to_string());
+            parent.children.push(node);
+        }
+    }
+
+    /// Add a link to a file, given relative to the root, shown with its target
+    /// and the path the content of the file is shown under.
+    pub fn add_link(&mut self, relative: &Path, target: &Path, shown: &Path) {
+        if let (parent, Some(name)) = self.parent_of(relative) {
+            let mut node = Node::new(&format!("{} -> {}", name, target.display()), false);
+            node.marker = Some(format!("see {}", shown.display()));
+            parent.children.push(node);
+        }
+    }
+
+    /// Order the entries of every directory.
+    pub fn sort(&mut self, sort: TreeSort) {
+        sort_children(&mut self.root, sort);
+    }
+
+    /// Render the tree as text, one entry per line.
+    pub fn render(&self, options: &RenderOptions) -> String {
+        let root = format!("└── {}{}", self.root.name, self.root.annotations(options));
+        let mut output = format!("{}\n", paint(root, 0, options));
+        render_children(&self.root, 0, options, &mut output);
+
+        // Keep the first entries, after the root, and count the others
+        if let Some(max) = options.max_entries {
+            let lines: Vec<&str> = output.lines().collect();
+            let entries = lines.len() - 1;
+            if entries > max {
+                let mut truncated = lines[..=max].join("\n");
+                truncated.push_str(&format!("\n…[+{} more]\n", entries - max));
+                return truncated;
+            }
+        }
+        output
+    }
+}
+
+/// Options controlling how the tree is rendered.
+#[derive(Debug, Clone, Default)]
+pub struct RenderOptions {
+    /// Annotate files and directories with their number of lines.
+    pub line_counts: bool,
+    /// Annotate files and directories with their size and number of tokens.
+    pub sizes: bool,
+    /// Collapse runs of at least this many consecutive non-text files in a
+    /// directory into a single entry.
+    pub collapse_binaries: Option<usize>,
+    /// Maximum number of entries shown below the root, the others being
+    /// counted in a last line.
+    pub max_entries: Option<usize>,
+    /// Shade the entries by depth with ANSI colors, for a terminal.
+    pub depth_colors: bool,
+}
+
+/// Colors of the 256-color palette shading the entries by depth, from blue to
+/// green, starting over below the last one.
+const DEPTH_COLORS: [u8; 8] = [33, 39, 45, 51, 50, 49, 48, 47];
+
+/// Shade an entry by its depth, the root being at depth 0, when asked.
+fn paint(entry: String, depth: usize, options: &RenderOptions) -> String {
+    match options.depth_colors {
+        true => format!(
+            "\x1b[38;5;{}m{}\x1b[0m",
+            DEPTH_COLORS[depth % DEPTH_COLORS.len()],
+            entry
+        ),
+        false => entry,
+    }
+}
+
+fn sort_children(node: &mut Node, sort: TreeSort) {
+    match sort {
+        TreeSort::Walk => return,
+        TreeSort::Name => node.children.sort_by(|a, b| a.name.cmp(&b.name)),
+        // Reorder the directories among the places they hold
+        TreeSort::Weight => {
+            let places: Vec<usize> = (0..node.children.len())
+                .filter(|&index| node.children[index].is_dir)
+                .collect();
+            let mut dirs: Vec<Node> = places
+                .iter()
+                .map(|&index| std::mem::replace(&mut node.children[index], Node::new("", false)))
+                .collect();
+            dirs.sort_by_cached_key(|dir| Reverse(dir.total(&|weight| weight.tokens).unwrap_or(0)));
+            for (index, dir) in places.into_iter().zip(dirs) {
+                node.children[index] = dir;
+            }
+        }
+    }
+    for child in &mut node.children {
+        sort_children(child, sort);
+    }
+}
+
+fn render_children(node: &Node, depth: usize, options: &RenderOptions, output: &mut String) {
+    let indent = "    ".repeat(depth);
+    let mut index = 0;
+
+    while index < node.children.len() {
+        let child = &node.children[index];
+
+        // Collapse runs of non-text files
+        if let Some(min) = options.collapse_binaries {
+            let run = node.children[index..]
+                .iter()
+                .take_while(|sibling| sibling.binary)
+                .count();
+            if run > 0 && run >= min {
+                let noun = if run == 1 { "file" } else { "files" };
+                let entry = format!("└── [+{} binary {}]", run, noun);
+                output.push_str(&format!("{}{}\n", indent, paint(entry, depth + 1, options)));
+                index += run;
+                continue;
+            }
+        }
+
+        let annotations = child.annotations(options);
+        if child.is_dir {
+            let entry = format!("├── {}{}", child.name, annotations);
+            output.push_str(&format!("{}{}\n", indent, paint(entry, depth + 1, options)));
+            render_children(child, depth + 1, options, output);
+        } else {
+            let entry = format!("└── {}{}", child.name, annotations);
+            output.push_str(&format!("{}{}\n", indent, paint(entry, depth + 1, options)));
+        }
+        index += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn root_name_of_current_directory_is_dot() {
+        assert_eq!(root_name(Path::new("")), ".");
+        assert_eq!(root_name(Path::new(".")), ".");
+        assert_eq!(root_name(Path::new("./")), ".");
+    }
+
+    #[test]
+    fn root_name_of_filesystem_root() {
+        assert_eq!(root_name(Path::new("/")), "/");
+        assert_eq!(root_name(Path::new("//")), "/");
+    }
+
+    #[test]
+    fn root_name_drops_trailing_separators() {
+        assert_eq!(root_name(Path::new("src/")), "src");
+        assert_eq!(root_name(Path::new("/srv/app//")), "/srv/app");
+        assert_eq!(root_name(Path::new("./src/")), "src");
+    }
+
+    #[test]
+    fn root_name_keeps_parent_components_as_given() {
+        assert_eq!(root_name(Path::new("..")), "..");
+        assert_eq!(root_name(Path::new("../")), "..");
+        assert_eq!(root_name(Path::new("../../app/..")), "../../app/..");
+    }
+}
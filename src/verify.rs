@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use anyhow::{Context, Result};
+use clap::Args;
+
+use crate::collision::short_hash;
+use crate::pack::{self, Header, Section};
+use crate::SEPARATOR;
+
+/// Exit code when the pack was read but has problems.
+const DIRTY: i32 = 1;
+/// Exit code when the pack could not be read as a file written by llmr.
+const UNPARSEABLE: i32 = 2;
+
+/// Arguments of `llmr verify`.
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    /// File written by llmr to check.
+    #[arg(value_name = "PACK")]
+    pack: PathBuf,
+}
+
+/// Parse an index entry, such as `[3] src/main.rs (120 tokens)`.
+fn index_entry(line: &str) -> Option<(usize, &str)> {
+    let rest = line.strip_prefix('[')?;
+    let (number, rest) = rest.split_once("] ")?;
+    let (path, _) = rest.rsplit_once(" (")?;
+    Some((number.parse().ok()?, path))
+}
+
+/// Check the sections of one directory, up to the next appended one.
+fn check_part(sections: &[Section], problems: &mut Vec<String>) {
+    let mut seen: HashMap<(Option<usize>, &str), usize> = HashMap::new();
+    let mut index: Option<(usize, Vec<(usize, &str)>)> = None;
+
+    for section in sections {
+        // The content of a framed section is read by its line count, so only
+        // elsewhere would a separator alone be the start of a header
+        if section.frame.is_none() {
+            let first = match section.header {
+                Some(_) => section.line + 2,
+                None => section.line,
+            };
+            for (offset, line) in section.lines().enumerate() {
+                if line == SEPARATOR {
+                    problems.push(format!(
+                        "Line {}: separator without a header, in the section of {}",
+                        first + offset,
+                        section.name()
+                    ));
+                }
+            }
+        }
+        problems.extend(section.problem());
+
+        match section.header {
+            // Only the tree is written outside of a section
+            None if !section.body.starts_with("└── ") => {
+                problems.push(format!("Line {}: lines outside any section", section.line));
+            }
+            Some(Header::File { number, path, tag }) => {
+                // Even an empty file is followed by a blank line
+                if section.frame.is_none() && section.body.is_empty() {
+                    problems.push(format!(
+                        "Line {}: {} has no content, the section is truncated",
+                        section.line, path
+                    ));
+                }
+                if let Some(tag) = tag {
+                    let expected = short_hash(Path::new(path));
+                    if tag != expected {
+                        problems.push(format!(
+                            "Line {}: hash of {} is [{}], expected [{}]",
+                            section.line, path, tag, expected
+                        ));
+                    }
+                }
+                if let Some(first) = seen.insert((number, path), section.line) {
+                    problems.push(format!(
+                        "Line {}: {} already has a section at line {}",
+                        section.line, path, first
+                    ));
+                }
+            }
+            Some(Header::SmallFile { path, .. }) => {
+                if let Some(first) = seen.insert((None, path), section.line) {
+                    problems.push(format!(
+                        "Line {}: {} already has a section at line {}",
+                        section.line, path, first
+                    ));
+                }
+            }
+            Some(Header::Index) => {
+                let entries = section.lines().filter_map(index_entry).collect();
+                index = Some((section.line, entries));
+            }
+            _ => {}
+        }
+    }
+
+    // Every file listed in the index should have its section
+    if let Some((line, entries)) = index {
+        for (number, path) in entries {
+            if !seen.contains_key(&(Some(number), path)) {
+                problems.push(format!(
+                    "Line {}: [{}] {} is listed in the index but has no section",
+                    line, number, path
+                ));
+            }
+        }
+    }
+}
+
+/// Check the structure of a pack, returning the number of files and the
+/// problems found.
+fn check(text: &str, path: &Path) -> Result<(usize, Vec<String>)> {
+    let sections = pack::sections(text, path)?;
+    let files = sections
+        .iter()
+        .filter(|section| {
+            matches!(
+                section.header,
+                Some(Header::File { .. } | Header::SmallFile { .. })
+            )
+        })
+        .count();
+
+    let mut problems = Vec::new();
+    let mut start = 0;
+    for (position, section) in sections.iter().enumerate() {
+        if section.header == Some(Header::Appended) {
+            check_part(&sections[start..position], &mut problems);
+            start = position;
+        }
+    }
+    check_part(&sections[start..], &mut problems);
+
+    // Every line written ends with a newline
+    if !text.ends_with('\n') {
+        problems.push(format!(
+            "Line {}: the pack does not end with a newline, it is truncated",
+            text.lines().count()
+        ));
+    }
+
+    Ok((files, problems))
+}
+
+/// Check that a pack is intact, exiting with 1 if it has problems and 2 if it
+/// cannot be read as a file written by llmr.
+pub fn run(args: &VerifyArgs) -> Result<()> {
+    let checked = fs::read_to_string(&args.pack)
+        .with_context(|| format!("Failed to read pack: {}", args.pack.display()))
+        .and_then(|text| check(&text, &args.pack));
+    let (files, problems) = match checked {
+        Ok(checked) => checked,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            process::exit(UNPARSEABLE);
+        }
+    };
+
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        eprintln!(
+            "{}: {} files, no problems found",
+            args.pack.display(),
+            files
+        );
+        return Ok(());
+    }
+    eprintln!(
+        "{}: {} files, {} problems found",
+        args.pack.display(),
+        files,
+        problems.len()
+    );
+    process::exit(DIRTY);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(header: &str, content: &str) -> String {
+        format!(
+            "{}\n{}{}\n{}\n{}",
+            SEPARATOR,
+            header,
+            pack::frame(content.as_bytes()),
+            SEPARATOR,
+            content
+        )
+    }
+
+    /// A pack of two indexed files, one showing an example of a pack.
+    fn intact() -> String {
+        let example = format!("{}\nFile: fake.rs\n{}\n", SEPARATOR, SEPARATOR);
+        format!(
+            "└── project\n{}\nIndex\n{}\n[1] README.md (9 tokens)\n[2] a.rs (1 tokens)\n{}{}",
+            SEPARATOR,
+            SEPARATOR,
+            file("File [1]: README.md", &example),
+            file("File [2]: a.rs", "a\n")
+        )
+    }
+
+    fn problems(text: &str) -> Vec<String> {
+        check(text, Path::new("pack.txt")).unwrap().1
+    }
+
+    #[test]
+    fn intact_pack_has_no_problems() {
+        let (files, problems) = check(&intact(), Path::new("pack.txt")).unwrap();
+        assert_eq!(files, 2);
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn pack_without_metadata_has_no_problems() {
+        let text = format!(
+            "└── project\n{}\nFile: a.rs\n{}\na\n{}\nFile: b.rs\n{}\nb\n",
+            SEPARATOR, SEPARATOR, SEPARATOR, SEPARATOR
+        );
+        let (files, problems) = check(&text, Path::new("pack.txt")).unwrap();
+        assert_eq!(files, 2);
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn edited_file_is_reported() {
+        let problems = problems(&intact().replace("a\n", "b\n"));
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert!(problems[0].contains("the content of a.rs has hash"));
+    }
+
+    #[test]
+    fn truncated_pack_is_reported() {
+        let text = intact();
+        let problems = problems(&text[..text.len() - 2]);
+        assert!(problems
+            .iter()
+            .any(|problem| problem.contains("a.rs is truncated")));
+    }
+
+    #[test]
+    fn missing_section_is_reported() {
+        let text = intact();
+        let text = text.strip_suffix(&file("File [2]: a.rs", "a\n")).unwrap();
+        let problems = problems(text);
+        assert!(
+            problems
+                .iter()
+                .any(|problem| problem
+                    .contains("[2] a.rs is listed in the index but has no section")),
+            "{:?}",
+            problems
+        );
+    }
+
+    #[test]
+    fn stray_lines_are_reported() {
+        let problems = problems(&format!("{}stray\n{}\n", intact(), SEPARATOR));
+        assert!(problems
+            .iter()
+            .any(|problem| problem.contains("outside any section")));
+        assert!(problems
+            .iter()
+            .any(|problem| problem.contains("separator without a header")));
+    }
+
+    #[test]
+    fn unparseable_pack_is_an_error() {
+        assert!(check("hello\n", Path::new("pack.txt")).is_err());
+    }
+}
@@ -0,0 +1,439 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use globset::Glob;
+use ignore::WalkBuilder;
+use toml::{Table, Value};
+
+/// A kind of project whose packages can be grouped in a workspace.
+trait Ecosystem {
+    /// Name of the manifest files, as shown in messages.
+    fn manifest(&self) -> &'static str;
+
+    /// Find the directories of the workspace containing a directory, and of the
+    /// local packages they depend on, if the directory is in such a project.
+    fn discover(&self, start: &Path) -> Result<Option<Vec<PathBuf>>>;
+
+    /// Read the package whose manifest is in a directory, if it describes one.
+    fn package(&self, dir: &Path) -> Result<Option<Package>>;
+}
+
+/// The ecosystems tried in turn by `--workspace` and `--package`.
+const ECOSYSTEMS: &[&dyn Ecosystem] = &[&Cargo, &Npm];
+
+/// A package found by scanning the manifests below a directory.
+#[derive(Debug, Clone)]
+pub struct Package {
+    pub name: String,
+    pub dir: PathBuf,
+    /// Name of the manifest files describing the package.
+    ecosystem: &'static str,
+    /// Names of the packages it depends on, in the same ecosystem.
+    dependencies: Vec<String>,
+}
+
+/// Find the packages whose manifest lies below a directory, skipping ignored
+/// directories such as `node_modules` or `target`.
+fn find_packages(root: &Path) -> Result<Vec<Package>> {
+    let mut packages = Vec::new();
+    for entry in WalkBuilder::new(root).build() {
+        let entry = entry.with_context(|| "Error during directory traversal")?;
+        let (Some(name), Some(dir)) = (entry.file_name().to_str(), entry.path().parent()) else {
+            continue;
+        };
+
+        for ecosystem in ECOSYSTEMS {
+            if ecosystem.manifest() == name {
+                packages.extend(ecosystem.package(dir)?);
+            }
+        }
+    }
+    Ok(packages)
+}
+
+/// Select the directories of the packages with the given names below a
+/// directory, and of the packages they depend on, directly or not, if asked.
+/// Returns the directories and a warning for each name shared by several
+/// packages, all of which are selected.
+pub fn select_packages(
+    root: &Path,
+    names: &[String],
+    dependencies: bool,
+) -> Result<(Vec<PathBuf>, Vec<String>)> {
+    let packages = find_packages(root)?;
+    let mut by_name: BTreeMap<&str, Vec<&Package>> = BTreeMap::new();
+    for package in &packages {
+        by_name.entry(&package.name).or_default().push(package);
+    }
+
+    let mut warnings = Vec::new();
+    let mut selected: Vec<&Package> = Vec::new();
+    for name in names {
+        let Some(found) = by_name.get(name.as_str()) else {
+            let known: Vec<&str> = by_name.keys().copied().collect();
+            bail!(
+                "Unknown package: {} (found: {})",
+                name,
+                if known.is_empty() {
+                    "none".to_string()
+                } else {
+                    known.join(", ")
+                }
+            );
+        };
+        if found.len() > 1 {
+            let manifests: Vec<String> = found
+                .iter()
+                .map(|package| package.dir.join(package.ecosystem).display().to_string())
+                .collect();
+            warnings.push(format!(
+                "Warning: several packages are named {}: {}",
+                name,
+                manifests.join(", ")
+            ));
+        }
+        selected.extend(found);
+    }
+
+    // Follow the dependencies within the same ecosystem
+    if dependencies {
+        let mut index = 0;
+        while index < selected.len() {
+            let package = selected[index];
+            for dependency in &package.dependencies {
+                let found = by_name.get(dependency.as_str()).into_iter().flatten();
+                for dependency in found {
+                    let seen = selected.iter().any(|other| other.dir == dependency.dir);
+                    if dependency.ecosystem == package.ecosystem && !seen {
+                        selected.push(dependency);
+                    }
+                }
+            }
+            index += 1;
+        }
+    }
+
+    let dirs: BTreeSet<PathBuf> = selected
+        .into_iter()
+        .map(|package| package.dir.clone())
+        .collect();
+    Ok((dirs.into_iter().collect(), warnings))
+}
+
+/// A label given to the files of a walked root with `--path-prefix`.
+#[derive(Debug)]
+pub struct PathPrefix {
+    /// The root, canonicalized to be compared with the walked ones.
+    pub root: PathBuf,
+    /// Path its files are shown under, in place of their path from the current
+    /// directory.
+    pub prefix: PathBuf,
+}
+
+/// Parse labels of the form `root=prefix`, the root relative to the current
+/// directory.
+pub fn path_prefixes(specs: &[String]) -> Result<Vec<PathPrefix>> {
+    let mut parsed: Vec<PathPrefix> = Vec::new();
+    for spec in specs {
+        let (root, prefix) = spec
+            .rsplit_once('=')
+            .with_context(|| format!("Invalid path prefix: {} (expected ROOT=PREFIX)", spec))?;
+        let prefix: PathBuf = Path::new(prefix.trim())
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect();
+        if prefix.as_os_str().is_empty() {
+            bail!("Invalid path prefix: {} (expected ROOT=PREFIX)", spec);
+        }
+        let root = fs::canonicalize(root.trim())
+            .with_context(|| format!("Failed to find directory: {}", root.trim()))?;
+        if parsed.iter().any(|parsed| parsed.root == root) {
+            bail!("Path prefix given twice for {}", root.display());
+        }
+        parsed.push(PathPrefix { root, prefix });
+    }
+    Ok(parsed)
+}
+
+/// Find the directories of the workspace containing a directory that lie
+/// outside of it, such as sibling members and local path dependencies.
+pub fn outside_dirs(start: &Path) -> Result<Vec<PathBuf>> {
+    for ecosystem in ECOSYSTEMS {
+        if let Some(dirs) = ecosystem.discover(start)? {
+            let outside: Vec<PathBuf> = dirs
+                .into_iter()
+                .filter(|dir| !dir.starts_with(start) && !start.starts_with(dir))
+                .collect();
+
+            // Directories nested in another one are walked with it
+            return Ok(outside
+                .iter()
+                .filter(|dir| {
+                    !outside
+                        .iter()
+                        .any(|other| other != *dir && dir.starts_with(other))
+                })
+                .cloned()
+                .collect());
+        }
+    }
+
+    let manifests: Vec<&str> = ECOSYSTEMS
+        .iter()
+        .map(|ecosystem| ecosystem.manifest())
+        .collect();
+    bail!(
+        "No workspace found from {} (looked for {})",
+        start.display(),
+        manifests.join(", ")
+    )
+}
+
+/// Express a path relative to a base directory, going up with `..` as needed.
+pub fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let path: Vec<Component> = path.components().collect();
+    let base: Vec<Component> = base.components().collect();
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..base.len() {
+        relative.push("..");
+    }
+    for component in &path[common..] {
+        relative.push(component);
+    }
+    relative
+}
+
+/// Read a manifest in the TOML format, such as `Cargo.toml`.
+pub fn read_toml(path: &Path) -> Result<Table> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read manifest: {}", path.display()))?;
+    content
+        .parse::<Table>()
+        .with_context(|| format!("Failed to parse manifest: {}", path.display()))
+}
+
+/// Read a manifest in the JSON format, such as `package.json`.
+pub fn read_json(path: &Path) -> Result<serde_json::Value> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read manifest: {}", path.display()))?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse manifest: {}", path.display()))
+}
+
+/// Cargo workspaces, described by `Cargo.toml` files.
+struct Cargo;
+
+impl Cargo {
+    fn read(dir: &Path) -> Result<Option<Table>> {
+        let path = dir.join("Cargo.toml");
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        read_toml(&path).map(Some)
+    }
+
+    /// Directories of the local packages a manifest depends on.
+    fn path_dependencies(dir: &Path, manifest: &Table) -> Vec<PathBuf> {
+        let workspace = manifest.get("workspace");
+        let tables = ["dependencies", "dev-dependencies", "build-dependencies"]
+            .into_iter()
+            .filter_map(|name| manifest.get(name))
+            .chain(workspace.and_then(|workspace| workspace.get("dependencies")));
+
+        tables
+            .filter_map(Value::as_table)
+            .flat_map(|table| table.values())
+            .filter_map(|dependency| dependency.get("path")?.as_str())
+            .map(|path| normalize(&dir.join(path)))
+            .collect()
+    }
+}
+
+impl Ecosystem for Cargo {
+    fn manifest(&self) -> &'static str {
+        "Cargo.toml"
+    }
+
+    fn discover(&self, start: &Path) -> Result<Option<Vec<PathBuf>>> {
+        // The workspace root is the closest directory whose manifest has a
+        // workspace table, else the package in the start directory stands alone
+        let mut root = None;
+        for dir in start.ancestors() {
+            if let Some(manifest) = Cargo::read(dir)? {
+                if manifest.contains_key("workspace") {
+                    root = Some((dir.to_path_buf(), manifest));
+                    break;
+                }
+            }
+        }
+        let (root, manifest) = match root {
+            Some(root) => root,
+            None => match Cargo::read(start)? {
+                Some(manifest) => (start.to_path_buf(), manifest),
+                None => return Ok(None),
+            },
+        };
+
+        let workspace = manifest.get("workspace");
+        let patterns = |key: &str| -> Vec<String> {
+            workspace
+                .and_then(|workspace| workspace.get(key)?.as_array())
+                .map(|values| {
+                    values
+                        .iter()
+                        .filter_map(|value| value.as_str().map(str::to_string))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let excluded: Vec<PathBuf> = patterns("exclude")
+            .iter()
+            .map(|path| normalize(&root.join(path)))
+            .collect();
+
+        let mut members = BTreeSet::new();
+        members.insert(root.clone());
+        for pattern in patterns("members") {
+            members.extend(expand(&root, &pattern)?);
+        }
+        members.retain(|member| !excluded.iter().any(|excluded| member.starts_with(excluded)));
+
+        // Local dependencies of the members may live outside the workspace
+        let mut dirs = members.clone();
+        for member in &members {
+            if let Some(manifest) = Cargo::read(member)? {
+                dirs.extend(Cargo::path_dependencies(member, &manifest));
+            }
+        }
+
+        Ok(Some(dirs.into_iter().collect()))
+    }
+
+    fn package(&self, dir: &Path) -> Result<Option<Package>> {
+        let Some(manifest) = Cargo::read(dir)? else {
+            return Ok(None);
+        };
+        let Some(name) = manifest
+            .get("package")
+            .and_then(|package| package.get("name")?.as_str())
+        else {
+            return Ok(None);
+        };
+
+        // Local dependencies are given by path or inherited from the workspace,
+        // possibly renamed
+        let dependencies = ["dependencies", "dev-dependencies", "build-dependencies"]
+            .into_iter()
+            .filter_map(|name| manifest.get(name)?.as_table())
+            .flat_map(|table| table.iter())
+            .filter(|(_, dependency)| {
+                dependency.get("path").is_some() || dependency.get("workspace").is_some()
+            })
+            .map(|(key, dependency)| {
+                dependency
+                    .get("package")
+                    .and_then(Value::as_str)
+                    .unwrap_or(key)
+                    .to_string()
+            })
+            .collect();
+
+        Ok(Some(Package {
+            name: name.to_string(),
+            dir: dir.to_path_buf(),
+            ecosystem: self.manifest(),
+            dependencies,
+        }))
+    }
+}
+
+/// npm, pnpm and yarn packages, described by `package.json` files.
+struct Npm;
+
+impl Ecosystem for Npm {
+    fn manifest(&self) -> &'static str {
+        "package.json"
+    }
+
+    fn discover(&self, _start: &Path) -> Result<Option<Vec<PathBuf>>> {
+        // Walking npm workspaces is not supported yet
+        Ok(None)
+    }
+
+    fn package(&self, dir: &Path) -> Result<Option<Package>> {
+        let manifest = read_json(&dir.join(self.manifest()))?;
+        let Some(name) = manifest.get("name").and_then(|name| name.as_str()) else {
+            return Ok(None);
+        };
+
+        let dependencies = ["dependencies", "devDependencies", "peerDependencies"]
+            .into_iter()
+            .filter_map(|key| manifest.get(key)?.as_object())
+            .flat_map(|dependencies| dependencies.keys().cloned())
+            .collect();
+
+        Ok(Some(Package {
+            name: name.to_string(),
+            dir: dir.to_path_buf(),
+            ecosystem: self.manifest(),
+            dependencies,
+        }))
+    }
+}
+
+/// Expand a member pattern such as `crates/*` to the directories it matches.
+fn expand(root: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let mut dirs = vec![root.to_path_buf()];
+
+    for component in Path::new(pattern).components() {
+        let name = component.as_os_str().to_string_lossy();
+        if !name.contains(['*', '?', '[', '{']) {
+            dirs = dirs.iter().map(|dir| dir.join(&*name)).collect();
+            continue;
+        }
+
+        let glob = Glob::new(&name)
+            .with_context(|| format!("Invalid workspace member pattern: {}", pattern))?
+            .compile_matcher();
+        let mut matched = Vec::new();
+        for dir in &dirs {
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if entry.path().is_dir() && glob.is_match(entry.file_name()) {
+                    matched.push(entry.path());
+                }
+            }
+        }
+        matched.sort();
+        dirs = matched;
+    }
+
+    Ok(dirs
+        .into_iter()
+        .filter(|dir| dir.is_dir())
+        .map(|dir| normalize(&dir))
+        .collect())
+}
+
+/// Resolve the `.` and `..` components of a path without touching the
+/// filesystem.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
@@ -0,0 +1,31 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+/// The document written by `--format yaml`.
+#[derive(Debug, Serialize)]
+pub struct Document {
+    /// The directory the paths are shown from, as at the top of the tree.
+    pub root: String,
+    /// The directory structure, left out with `--flatten`.
+    pub tree: Option<String>,
+    /// The files included, in the order they would be emitted as text.
+    pub files: Vec<File>,
+}
+
+/// A file of the document, with its content.
+#[derive(Debug, Serialize)]
+pub struct File {
+    /// Path shown in the file header.
+    pub path: String,
+    /// Language detected from the extension, as in the summary of languages.
+    pub language: Option<String>,
+    pub tokens: usize,
+    pub content: String,
+}
+
+/// Serialize the document. Strings spanning several lines are written as
+/// literal block scalars, and those that cannot be, such as strings holding
+/// control characters, as quoted scalars, so the content reads back unchanged.
+pub fn to_yaml(document: &Document) -> Result<String> {
+    serde_yaml::to_string(document).context("Failed to serialize YAML document")
+}
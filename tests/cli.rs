@@ -0,0 +1,146 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Create a directory holding files, given by their path and content, in a
+/// fresh directory of its own.
+fn fixture(name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("llmr-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    for (path, content) in files {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    root
+}
+
+/// Run llmr in a directory, failing the test if it fails.
+fn llmr(dir: &Path, args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_llmr"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "llmr {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// The paths of the file headers of an output.
+fn headers(output: &str) -> Vec<&str> {
+    output
+        .lines()
+        .filter_map(|line| line.strip_prefix("File: "))
+        .map(|header| header.split(" (").next().unwrap())
+        .collect()
+}
+
+/// The block of a file in an output, as many lines as its header gives after
+/// the separator closing it.
+fn body(output: &str, path: &str) -> String {
+    let header = format!("File: {} (", path);
+    let start = output.find(&header).unwrap() + header.len();
+    let lines: usize = output[start..].split(' ').next().unwrap().parse().unwrap();
+    output[start..]
+        .split_inclusive('\n')
+        .skip(2)
+        .take(lines)
+        .collect()
+}
+
+/// Run llmr in a directory, returning what it wrote to a file, with the line
+/// counts read by `body`.
+fn pack(dir: &Path, args: &[&str]) -> String {
+    let mut args = args.to_vec();
+    args.extend(["-o", "pack.txt", "--pack-metadata"]);
+    llmr(dir, &args);
+    fs::read_to_string(dir.join("pack.txt")).unwrap()
+}
+
+#[test]
+fn headers_are_plain_unless_asked_for_metadata() {
+    let root = fixture("headers", &[("a.txt", b"a\n")]);
+    llmr(&root, &["-o", "plain.txt"]);
+    let plain = fs::read_to_string(root.join("plain.txt")).unwrap();
+    assert!(plain.lines().any(|line| line == "File: a.txt"), "{}", plain);
+
+    let framed = pack(&root, &[]);
+    assert!(
+        framed
+            .lines()
+            .any(|line| line.starts_with("File: a.txt (1 line, sha256 ")),
+        "{}",
+        framed
+    );
+}
+
+#[test]
+fn no_trim_keeps_trailing_whitespace() {
+    let content = "a  \n\tb\t\n\n\n";
+    let root = fixture("no-trim", &[("ws.txt", content.as_bytes())]);
+
+    // Only the newline ending the block is added
+    assert_eq!(
+        body(&pack(&root, &["--no-trim"]), "ws.txt"),
+        format!("{}\n", content)
+    );
+    assert_eq!(body(&pack(&root, &[]), "ws.txt"), "a  \n\tb\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn colors_never_reach_an_output_file() {
+    let root = fixture("ansi", &[("a.txt", b"plain\n")]);
+    let output = pack(&root, &["--filter-cmd", "printf '\\033[31mred\\033[0m\\n'"]);
+    assert!(!output.contains('\x1b'));
+    assert_eq!(body(&output, "a.txt"), "red\n");
+}
+
+#[test]
+fn byte_order_marks_are_kept_only_when_asked() {
+    let root = fixture("bom", &[("bom.txt", b"\xef\xbb\xbfx\r\n")]);
+    assert_eq!(body(&pack(&root, &[]), "bom.txt"), "x\n");
+    assert_eq!(
+        body(&pack(&root, &["--preserve-bom", "--no-trim"]), "bom.txt"),
+        "\u{feff}x\r\n\n"
+    );
+}
+
+#[test]
+fn max_output_bytes_never_lets_the_output_over() {
+    let root = fixture("max-output-bytes", &[("a.txt", b"a\n")]);
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_llmr"))
+            .args(args)
+            .current_dir(&root)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["--max-output-bytes", "10"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("over the output size limit"));
+
+    let output = run(&["--max-output-bytes", "100000", "--format", "chat"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn second_run_leaves_out_the_output_of_the_first() {
+    let root = fixture("self-exclusion", &[("src/main.rs", b"fn main() {}\n")]);
+    let args = ["-o", "context.md", "--errors", "file:skips.log"];
+
+    llmr(&root, &args);
+    let first = fs::read_to_string(root.join("context.md")).unwrap();
+    llmr(&root, &args);
+    let second = fs::read_to_string(root.join("context.md")).unwrap();
+
+    assert_eq!(headers(&first), ["src/main.rs"]);
+    assert_eq!(headers(&second), ["src/main.rs"]);
+    assert_eq!(first, second);
+}